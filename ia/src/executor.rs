@@ -86,5 +86,5 @@ pub(crate) fn run_once_next_tick(target: &Arc<TaskInfo>) {
     let t = target.clone();
     ::raw::schedule(move || {
         TaskInfo::run_once(&t);
-    });
+    }).expect("unable to schedule a task");
 }
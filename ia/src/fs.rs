@@ -1,5 +1,10 @@
 use std::io::{Read, Write, Seek};
 use std::io;
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use futures::prelude::*;
+
 use error::IoResult;
 
 pub struct File {
@@ -14,6 +19,121 @@ impl File {
             }
         })
     }
+
+    /// Truncates or extends the file to `len` bytes.
+    pub fn set_len(&self, len: u64) -> IoResult<()> {
+        ::raw::file_truncate(self.handle, len)
+    }
+
+    /// Flushes all data and metadata to disk.
+    pub fn sync_all(&self) -> IoResult<()> {
+        ::raw::file_sync_all(self.handle)
+    }
+
+    /// Reads at most `len` bytes on the host's I/O pool.
+    ///
+    /// Only one asynchronous operation may be in progress on a file at a time;
+    /// the synchronous methods fail while it is.
+    pub fn read_async(&self, len: usize) -> ReadFuture {
+        ReadFuture {
+            started: false,
+            handle: self.handle,
+            len: len,
+            status: Rc::new(RefCell::new(None))
+        }
+    }
+
+    /// Writes `data` on the host's I/O pool.
+    ///
+    /// The `Future` returned by this resolves to the number of bytes written.
+    pub fn write_async(&self, data: Vec<u8>) -> WriteFuture {
+        WriteFuture {
+            started: false,
+            handle: self.handle,
+            data: data,
+            status: Rc::new(RefCell::new(None))
+        }
+    }
+}
+
+/// A `Future` representing a pending `File::read_async` request.
+pub struct ReadFuture {
+    started: bool,
+    handle: i32,
+    len: usize,
+    status: Rc<RefCell<Option<IoResult<Vec<u8>>>>>
+}
+
+impl Future for ReadFuture {
+    type Item = Vec<u8>;
+    type Error = ::error::Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<Vec<u8>>, ::error::Io> {
+        if let Some(v) = self.status.borrow_mut().take() {
+            return match v {
+                Ok(v) => Ok(Async::Ready(v)),
+                Err(e) => Err(e)
+            };
+        }
+
+        if self.started {
+            return Ok(Async::NotReady);
+        }
+
+        self.started = true;
+
+        let status = self.status.clone();
+        let task = ::executor::current_task();
+
+        ::raw::file_read_async(self.handle, self.len, move |result| {
+            *status.borrow_mut() = Some(result);
+            ::executor::run_once_next_tick(&task);
+        });
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// A `Future` representing a pending `File::write_async` request.
+pub struct WriteFuture {
+    started: bool,
+    handle: i32,
+    data: Vec<u8>,
+    status: Rc<RefCell<Option<IoResult<usize>>>>
+}
+
+impl Future for WriteFuture {
+    type Item = usize;
+    type Error = ::error::Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<usize>, ::error::Io> {
+        if let Some(v) = self.status.borrow_mut().take() {
+            return match v {
+                Ok(v) => Ok(Async::Ready(v)),
+                Err(e) => Err(e)
+            };
+        }
+
+        if self.started {
+            return Ok(Async::NotReady);
+        }
+
+        self.started = true;
+
+        let status = self.status.clone();
+        let task = ::executor::current_task();
+
+        ::raw::file_write_async(self.handle, &self.data, move |result| {
+            *status.borrow_mut() = Some(result);
+            ::executor::run_once_next_tick(&task);
+        });
+
+        Ok(Async::NotReady)
+    }
 }
 
 impl Read for File {
@@ -59,3 +179,131 @@ impl Drop for File {
         ::raw::file_close(self.handle);
     }
 }
+
+fn read_le(data: &[u8]) -> u64 {
+    data.iter().rev().fold(0, |acc, b| (acc << 8) | (*b as u64))
+}
+
+/// Metadata about a file or directory, as returned by `metadata`.
+#[derive(Debug, Clone)]
+pub struct Metadata {
+    len: u64,
+    modified_millis: i64,
+    kind: u32,
+    readonly: bool
+}
+
+impl Metadata {
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == 0
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == 1
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.kind == 2
+    }
+
+    pub fn readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Last modification time in milliseconds since the Unix epoch.
+    pub fn modified_millis(&self) -> i64 {
+        self.modified_millis
+    }
+}
+
+/// Queries metadata about a path, without following symlinks.
+pub fn metadata(path: &str) -> IoResult<Metadata> {
+    let record = ::raw::file_stat(path)?;
+    Ok(Metadata {
+        len: read_le(&record[0..8]),
+        modified_millis: read_le(&record[8..16]) as i64,
+        kind: read_le(&record[16..20]) as u32,
+        readonly: read_le(&record[20..24]) != 0
+    })
+}
+
+/// An entry returned by `read_dir`.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    dir: String,
+    name: String
+}
+
+impl DirEntry {
+    pub fn file_name(&self) -> &str {
+        &self.name
+    }
+
+    /// The full path of this entry, i.e. the directory joined with `file_name`.
+    pub fn path(&self) -> String {
+        if self.dir.ends_with('/') {
+            format!("{}{}", self.dir, self.name)
+        } else {
+            format!("{}/{}", self.dir, self.name)
+        }
+    }
+
+    pub fn metadata(&self) -> IoResult<Metadata> {
+        metadata(&self.path())
+    }
+}
+
+/// An iterator over the entries of a directory.
+pub struct ReadDir {
+    inner: ::std::vec::IntoIter<DirEntry>
+}
+
+impl Iterator for ReadDir {
+    type Item = DirEntry;
+
+    fn next(&mut self) -> Option<DirEntry> {
+        self.inner.next()
+    }
+}
+
+pub fn read_dir(path: &str) -> IoResult<ReadDir> {
+    let entries: Vec<DirEntry> = ::raw::file_readdir(path)?
+        .into_iter()
+        .map(|name| DirEntry {
+            dir: path.to_string(),
+            name: name
+        })
+        .collect();
+
+    Ok(ReadDir {
+        inner: entries.into_iter()
+    })
+}
+
+pub fn create_dir(path: &str) -> IoResult<()> {
+    ::raw::file_mkdir(path, false)
+}
+
+pub fn create_dir_all(path: &str) -> IoResult<()> {
+    ::raw::file_mkdir(path, true)
+}
+
+pub fn remove_file(path: &str) -> IoResult<()> {
+    ::raw::file_remove(path, 0)
+}
+
+pub fn remove_dir(path: &str) -> IoResult<()> {
+    ::raw::file_remove(path, 1)
+}
+
+pub fn remove_dir_all(path: &str) -> IoResult<()> {
+    ::raw::file_remove(path, 2)
+}
+
+pub fn rename(from: &str, to: &str) -> IoResult<()> {
+    ::raw::file_rename(from, to)
+}
@@ -0,0 +1,147 @@
+//! Handlers for HTTP services run by Ice.
+//!
+//! An HTTP service is declared in the `services` section of the Ice config:
+//!
+//! ```yaml
+//! services:
+//!   - kind: Http
+//!     address: 127.0.0.1:8080
+//!     app: hello_world
+//! ```
+//!
+//! and requests to it are passed to the handler defined with `app_http_handler!`.
+
+use error::{Io, IoResult};
+
+/// An incoming HTTP request.
+///
+/// If the request is dropped without being responded to, a `500` response is sent.
+pub struct Request {
+    handle: i32,
+    done: bool
+}
+
+impl Request {
+    /// Takes ownership of a request handle passed by the host.
+    pub unsafe fn from_raw(handle: i32) -> Request {
+        Request {
+            handle: handle,
+            done: false
+        }
+    }
+
+    pub fn method(&self) -> String {
+        into_string(::raw::http_method(self.handle))
+    }
+
+    pub fn path(&self) -> String {
+        into_string(::raw::http_path(self.handle))
+    }
+
+    /// Returns the value of the header `name` (case-insensitive) if present.
+    pub fn header(&self, name: &str) -> Option<String> {
+        ::raw::http_header(self.handle, name).ok()
+            .map(|v| String::from_utf8_lossy(&v).into_owned())
+    }
+
+    pub fn headers(&self) -> Vec<(String, String)> {
+        into_string(::raw::http_headers(self.handle))
+            .split("\r\n")
+            .filter_map(|line| {
+                let mut parts = line.splitn(2, ": ");
+                match (parts.next(), parts.next()) {
+                    (Some(k), Some(v)) => Some((k.to_string(), v.to_string())),
+                    _ => None
+                }
+            })
+            .collect()
+    }
+
+    pub fn body(&self) -> Vec<u8> {
+        ::raw::http_body(self.handle).unwrap_or_else(|_| Vec::new())
+    }
+
+    /// Sends `resp` back to the client.
+    pub fn respond(mut self, resp: Response) -> IoResult<()> {
+        self.done = true;
+
+        ::raw::http_set_status(self.handle, resp.status)?;
+        for (k, v) in &resp.headers {
+            ::raw::http_add_header(self.handle, k, v)?;
+        }
+        if resp.body.len() > 0 {
+            ::raw::http_write_body(self.handle, &resp.body)?;
+        }
+        ::raw::http_end(self.handle);
+
+        Ok(())
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = ::raw::http_set_status(self.handle, 500);
+            ::raw::http_end(self.handle);
+        }
+    }
+}
+
+fn into_string(v: IoResult<Vec<u8>>) -> String {
+    v.map(|v| String::from_utf8_lossy(&v).into_owned())
+        .unwrap_or_else(|_: Io| String::new())
+}
+
+/// An HTTP response.
+#[derive(Clone, Debug)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>
+}
+
+impl Response {
+    pub fn new(status: u16) -> Response {
+        Response {
+            status: status,
+            headers: Vec::new(),
+            body: Vec::new()
+        }
+    }
+
+    pub fn header<K: Into<String>, V: Into<String>>(mut self, k: K, v: V) -> Response {
+        self.headers.push((k.into(), v.into()));
+        self
+    }
+
+    pub fn body<T: Into<Vec<u8>>>(mut self, body: T) -> Response {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Defines the handler for requests to HTTP services that target this application.
+///
+/// ```no_run
+/// #[macro_use]
+/// extern crate ia;
+///
+/// use ia::http::{Request, Response};
+///
+/// app_http_handler!(|req: Request| {
+///     let path = req.path();
+///     req.respond(Response::new(200).body(format!("Hello from {}", path))).unwrap();
+/// });
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! app_http_handler {
+    ($handler:expr) => {
+        #[no_mangle]
+        pub extern "C" fn __app_http_handle(req: i32) -> i32 {
+            let handler = $handler;
+            handler(unsafe { $crate::http::Request::from_raw(req) });
+            0
+        }
+    }
+}
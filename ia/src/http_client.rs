@@ -0,0 +1,145 @@
+//! An asynchronous client for outbound HTTP requests.
+//!
+//! Only `http://` URLs are supported for now. The application must have the
+//! `HttpRequestAny` or `HttpRequest(host)` permission.
+//!
+//! ```no_run
+//! # extern crate ia;
+//! # use ia::futures::prelude::*;
+//! let fut = ia::http_client::get("http://127.0.0.1:8080/")
+//!     .map(|resp| println!("{} {}", resp.status, resp.body.len()));
+//! ```
+
+use futures::prelude::*;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use error::{Io, IoResult};
+
+/// A request to be sent with `send`.
+pub struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>
+}
+
+impl Request {
+    pub fn new(method: &str, url: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: Vec::new(),
+            body: Vec::new()
+        }
+    }
+
+    /// Adds a header. `Host`, `Connection`, `Content-Length` and
+    /// `Transfer-Encoding` are set by the host and ignored here.
+    pub fn header(mut self, name: &str, value: &str) -> Request {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<T: Into<Vec<u8>>>(mut self, body: T) -> Request {
+        self.body = body.into();
+        self
+    }
+
+    pub fn send(self) -> ResponseFuture {
+        ResponseFuture {
+            request: Some(self),
+            status: Rc::new(RefCell::new(None))
+        }
+    }
+}
+
+/// A complete response.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+impl Response {
+    /// Returns the value of the header `name` (case-insensitive) if present.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_str())
+    }
+}
+
+/// Sends a `GET` request to `url`.
+pub fn get(url: &str) -> ResponseFuture {
+    Request::new("GET", url).send()
+}
+
+/// A `Future` representing a pending request.
+pub struct ResponseFuture {
+    request: Option<Request>,
+    status: Rc<RefCell<Option<IoResult<Response>>>>
+}
+
+impl Future for ResponseFuture {
+    type Item = Response;
+    type Error = Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<Response>, Io> {
+        if let Some(v) = self.status.borrow_mut().take() {
+            return match v {
+                Ok(v) => Ok(Async::Ready(v)),
+                Err(e) => Err(e)
+            };
+        }
+
+        let req = match self.request.take() {
+            Some(v) => v,
+            None => return Ok(Async::NotReady)
+        };
+
+        let mut headers = String::new();
+        for &(ref k, ref v) in &req.headers {
+            headers.push_str(k);
+            headers.push_str(": ");
+            headers.push_str(v);
+            headers.push_str("\r\n");
+        }
+
+        let status = self.status.clone();
+        let task = ::executor::current_task();
+
+        ::raw::http_request(&req.method, &req.url, &headers, &req.body, move |resp| {
+            *status.borrow_mut() = Some(resp.and_then(|resp| {
+                let status = resp.status();
+                let headers = parse_headers(&resp.headers()?);
+                let body = resp.take_body()?;
+                Ok(Response {
+                    status: status,
+                    headers: headers,
+                    body: body
+                })
+            }));
+            ::executor::run_once_next_tick(&task);
+        });
+
+        Ok(Async::NotReady)
+    }
+}
+
+fn parse_headers(raw: &[u8]) -> Vec<(String, String)> {
+    String::from_utf8_lossy(raw)
+        .split("\r\n")
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, ": ");
+            match (parts.next(), parts.next()) {
+                (Some(k), Some(v)) => Some((k.to_string(), v.to_string())),
+                _ => None
+            }
+        })
+        .collect()
+}
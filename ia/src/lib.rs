@@ -6,8 +6,11 @@
 //! the underlying Ice Core engine):
 //!
 //! - Asynchronous TCP server and client
-//! - File I/O
-//! - Timer (not working for now due to an Ice bug)
+//! - Asynchronous UDP sockets
+//! - HTTP request handlers for host-side HTTP services
+//! - Asynchronous HTTP client
+//! - Synchronous and asynchronous file I/O
+//! - Timers
 //!
 //! The asynchronous APIs are based on `futures`, while low-level callback-based APIs
 //! are also provided.
@@ -82,7 +85,12 @@ pub mod raw;
 pub mod executor;
 pub mod utils;
 pub mod error;
+#[macro_use]
 pub mod net;
 pub mod fs;
+pub mod time;
+#[macro_use]
+pub mod http;
+pub mod http_client;
 
 pub use executor::spawn;
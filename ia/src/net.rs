@@ -3,6 +3,7 @@ use futures::prelude::*;
 use std::collections::VecDeque;
 use std::cell::{RefCell, UnsafeCell};
 use std::rc::Rc;
+use std::net::IpAddr;
 
 use error::IoResult;
 
@@ -10,6 +11,7 @@ use error::IoResult;
 pub struct TcpListener {
     notify: Rc<UnsafeCell<VecDeque<::raw::TcpStream>>>,
     listening: bool,
+    tls: bool,
     addr: String
 }
 
@@ -26,13 +28,18 @@ impl Stream for TcpListener {
 
             self.listening = true;
 
-            ::raw::listen_tcp(&self.addr, move |s| {
+            let on_accept = move |s| {
                 let notify = unsafe {
                     &mut *notify.get()
                 };
                 notify.push_back(s);
                 ::executor::run_once_next_tick(&task);
-            });
+            };
+            if self.tls {
+                ::raw::listen_tls(&self.addr, on_accept);
+            } else {
+                ::raw::listen_tcp(&self.addr, on_accept);
+            }
         }
 
         let notify = unsafe {
@@ -48,18 +55,30 @@ impl Stream for TcpListener {
 }
 
 impl TcpListener {
-    /// Listens on the specified address (in the format `ip:port`).
+    /// Listens on the specified address (in the format `host:port`).
     ///
     /// The application must have `TcpListenAny` or `TcpListen(addr)` permissions.
     pub fn new(addr: &str) -> TcpListener {
         TcpListener {
             addr: addr.to_string(),
             listening: false,
+            tls: false,
             notify: Rc::new(
                 UnsafeCell::new(VecDeque::new())
             )
         }
     }
+
+    /// Like `new`, but performs a TLS handshake on each accepted connection.
+    ///
+    /// The certificate and key are set with `tls.cert` and `tls.key` in the
+    /// application's config.
+    pub fn new_tls(addr: &str) -> TcpListener {
+        TcpListener {
+            tls: true,
+            ..TcpListener::new(addr)
+        }
+    }
 }
 
 /// A TCP connection that can be read from or written to.
@@ -69,17 +88,36 @@ pub struct TcpConnection {
 }
 
 impl TcpConnection {
-    /// Connects to the specified address (in the format `ip:port`).
+    /// Takes ownership of a stream id passed by the host, as in `app_tcp_accept!`.
+    pub unsafe fn from_raw(handle: i32) -> TcpConnection {
+        TcpConnection {
+            raw: ::raw::TcpStream::from_raw(handle)
+        }
+    }
+
+    /// Connects to the specified address (in the format `host:port`; host names are resolved by Ice).
     ///
     /// The application must have `TcpConnectAny` or `TcpConnect(addr)` permissions.
     pub fn connect(addr: &str) -> ConnectFuture {
         ConnectFuture {
             started: false,
+            tls: false,
             addr: addr.into(),
             status: Rc::new(RefCell::new(None))
         }
     }
 
+    /// Connects to the specified address and performs a TLS handshake.
+    ///
+    /// The server certificate is verified against the host part of `addr`, trusting
+    /// the system roots and `tls.ca` from the application's config.
+    pub fn connect_tls(addr: &str) -> ConnectFuture {
+        ConnectFuture {
+            tls: true,
+            ..TcpConnection::connect(addr)
+        }
+    }
+
     /// Writes to the connection.
     ///
     /// The `Future` returned by this resolves after all bytes are written successfully
@@ -109,6 +147,7 @@ impl TcpConnection {
 /// A `Future` representing a pending `TcpConnection::connect` request.
 pub struct ConnectFuture {
     started: bool,
+    tls: bool,
     addr: String,
     status: Rc<RefCell<Option<IoResult<TcpConnection>>>>
 }
@@ -136,18 +175,77 @@ impl Future for ConnectFuture {
         let status = self.status.clone();
         let task = ::executor::current_task();
 
-        ::raw::connect_tcp(&self.addr, move |stream| {
+        let on_connect = move |stream: IoResult<::raw::TcpStream>| {
             *status.borrow_mut() = Some(match stream {
                 Ok(v) => Ok(TcpConnection { raw: v }),
                 Err(e) => Err(e)
             });
             ::executor::run_once_next_tick(&task);
-        });
+        };
+        if self.tls {
+            ::raw::connect_tls(&self.addr, on_connect);
+        } else {
+            ::raw::connect_tcp(&self.addr, on_connect);
+        }
 
          Ok(Async::NotReady)
     }
 }
 
+/// Resolves a host name to all of its IP addresses.
+///
+/// The application must have `TcpConnectAny` or a `TcpConnect` permission naming the host.
+pub fn resolve(host: &str) -> ResolveFuture {
+    ResolveFuture {
+        started: false,
+        host: host.into(),
+        status: Rc::new(RefCell::new(None))
+    }
+}
+
+/// A `Future` representing a pending `resolve` request.
+pub struct ResolveFuture {
+    started: bool,
+    host: String,
+    status: Rc<RefCell<Option<IoResult<Vec<IpAddr>>>>>
+}
+
+impl Future for ResolveFuture {
+    type Item = Vec<IpAddr>;
+    type Error = ::error::Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<Vec<IpAddr>>, ::error::Io> {
+        if let Some(v) = self.status.borrow_mut().take() {
+            return match v {
+                Ok(v) => Ok(Async::Ready(v)),
+                Err(e) => Err(e)
+            };
+        }
+
+        if self.started {
+            return Ok(Async::NotReady);
+        }
+
+        self.started = true;
+
+        let status = self.status.clone();
+        let task = ::executor::current_task();
+
+        ::raw::dns_resolve(&self.host, move |addrs| {
+            *status.borrow_mut() = Some(addrs.map(|addrs| {
+                addrs.iter()
+                    .filter_map(|v| v.parse().ok())
+                    .collect()
+            }));
+            ::executor::run_once_next_tick(&task);
+        });
+
+        Ok(Async::NotReady)
+    }
+}
+
 /// A `Future` representing a pending `TcpConnection::read` request.
 pub struct ReadFuture {
     started: bool,
@@ -242,3 +340,198 @@ impl Future for WriteFuture {
         Ok(Async::NotReady)
     }
 }
+
+/// A UDP socket.
+#[derive(Clone)]
+pub struct UdpSocket {
+    raw: ::raw::UdpSocket
+}
+
+impl UdpSocket {
+    /// Binds to the specified address (in the format `ip:port`).
+    ///
+    /// The application must have `UdpBindAny` or `UdpBind(addr)` permissions.
+    pub fn bind(addr: &str) -> IoResult<UdpSocket> {
+        ::raw::bind_udp(addr).map(|v| UdpSocket { raw: v })
+    }
+
+    /// Sends a datagram to the specified address (in the format `ip:port`).
+    ///
+    /// The application must have the `UdpSendTo` permission.
+    pub fn send_to(&self, data: Vec<u8>, addr: &str) -> SendToFuture {
+        SendToFuture {
+            started: false,
+            socket: self.raw.clone(),
+            data: data,
+            addr: addr.into(),
+            status: Rc::new(RefCell::new(None))
+        }
+    }
+
+    /// Receives a datagram of at most `max_len` bytes.
+    ///
+    /// The `Future` returned by this resolves to the received data and the address of the peer.
+    pub fn recv_from(&self, max_len: usize) -> RecvFromFuture {
+        RecvFromFuture {
+            started: false,
+            max_len: max_len,
+            socket: self.raw.clone(),
+            status: Rc::new(RefCell::new(None))
+        }
+    }
+
+    /// Returns a `Stream` of incoming datagrams of at most `max_len` bytes each.
+    pub fn incoming(&self, max_len: usize) -> UdpIncoming {
+        UdpIncoming {
+            socket: self.clone(),
+            max_len: max_len,
+            current: None
+        }
+    }
+}
+
+/// A `Future` representing a pending `UdpSocket::send_to` request.
+pub struct SendToFuture {
+    started: bool,
+    socket: ::raw::UdpSocket,
+    data: Vec<u8>,
+    addr: String,
+    status: Rc<RefCell<Option<IoResult<i32>>>>
+}
+
+impl Future for SendToFuture {
+    type Item = usize;
+    type Error = ::error::Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<usize>, ::error::Io> {
+        if let Some(v) = self.status.borrow_mut().take() {
+            return match v {
+                Ok(v) => Ok(Async::Ready(v as usize)),
+                Err(e) => Err(e)
+            };
+        }
+
+        if self.started {
+            return Ok(Async::NotReady);
+        }
+
+        self.started = true;
+
+        let status = self.status.clone();
+        let task = ::executor::current_task();
+
+        self.socket.send_to(&self.data, &self.addr, move |result| {
+            *status.borrow_mut() = Some(result);
+            ::executor::run_once_next_tick(&task);
+        });
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// A `Future` representing a pending `UdpSocket::recv_from` request.
+pub struct RecvFromFuture {
+    started: bool,
+    max_len: usize,
+    socket: ::raw::UdpSocket,
+    status: Rc<RefCell<Option<IoResult<(Vec<u8>, String)>>>>
+}
+
+impl Future for RecvFromFuture {
+    type Item = (Vec<u8>, String);
+    type Error = ::error::Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<(Vec<u8>, String)>, ::error::Io> {
+        if let Some(v) = self.status.borrow_mut().take() {
+            return match v {
+                Ok(v) => Ok(Async::Ready(v)),
+                Err(e) => Err(e)
+            };
+        }
+
+        if self.started {
+            return Ok(Async::NotReady);
+        }
+
+        self.started = true;
+
+        let status = self.status.clone();
+        let task = ::executor::current_task();
+        let max_len = self.max_len;
+
+        self.socket.recv_from(self.max_len, move |packet| {
+            *status.borrow_mut() = Some(packet.and_then(|packet| {
+                let addr = packet.addr();
+                let mut buffer: Vec<u8> = vec! [ 0; max_len ];
+                let real_len = packet.take(&mut buffer)?;
+                buffer.truncate(real_len);
+                Ok((buffer, addr))
+            }));
+            ::executor::run_once_next_tick(&task);
+        });
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// A `Stream` of datagrams received on a `UdpSocket`.
+pub struct UdpIncoming {
+    socket: UdpSocket,
+    max_len: usize,
+    current: Option<RecvFromFuture>
+}
+
+impl Stream for UdpIncoming {
+    type Item = (Vec<u8>, String);
+    type Error = ::error::Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<Option<(Vec<u8>, String)>>, ::error::Io> {
+        if self.current.is_none() {
+            self.current = Some(self.socket.recv_from(self.max_len));
+        }
+
+        let result = self.current.as_mut().unwrap().poll();
+        match result {
+            Ok(Async::Ready(v)) => {
+                self.current = None;
+                Ok(Async::Ready(Some(v)))
+            },
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => {
+                self.current = None;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Defines the handler for connections accepted by TCP services that target this application.
+///
+/// ```no_run
+/// #[macro_use]
+/// extern crate ia;
+///
+/// use ia::net::TcpConnection;
+///
+/// app_tcp_accept!(|conn: TcpConnection| {
+///     ia::spawn(conn.write(b"Hello, world!\n".to_vec()));
+/// });
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! app_tcp_accept {
+    ($handler:expr) => {
+        #[no_mangle]
+        pub extern "C" fn __app_tcp_accept(stream_id: i32) -> i32 {
+            let handler = $handler;
+            handler(unsafe { $crate::net::TcpConnection::from_raw(stream_id) });
+            0
+        }
+    }
+}
@@ -2,6 +2,7 @@ pub use executor::spawn;
 
 use std::boxed::FnBox;
 use std::rc::Rc;
+use std::cell::Cell;
 use std::ops::Deref;
 use std::io::SeekFrom;
 
@@ -21,6 +22,18 @@ extern "C" {
         cb: extern "C" fn (user_data: i32, stream_tid: i32) -> i32,
         user_data: i32
     ) -> i32;
+    fn __ice_tcp_connect_tls(
+        addr_base: *const u8,
+        addr_len: usize,
+        cb: extern "C" fn (user_data: i32, stream_tid: i32) -> i32,
+        user_data: i32
+    );
+    fn __ice_tcp_listen_tls(
+        addr_base: *const u8,
+        addr_len: usize,
+        cb: extern "C" fn (user_data: i32, stream_tid: i32) -> i32,
+        user_data: i32
+    ) -> i32;
     fn __ice_tcp_release_buffer(
         buffer_id: i32
     );
@@ -43,6 +56,45 @@ extern "C" {
         user_data: i32
     );
     fn __ice_tcp_destroy(stream_tid: i32);
+    fn __ice_udp_bind(
+        addr_base: *const u8,
+        addr_len: usize
+    ) -> i32;
+    fn __ice_udp_send_to(
+        socket_tid: i32,
+        data_base: *const u8,
+        data_len: usize,
+        addr_base: *const u8,
+        addr_len: usize,
+        cb: extern "C" fn (user_data: i32, len: i32) -> i32,
+        user_data: i32
+    );
+    fn __ice_udp_recv_from(
+        socket_tid: i32,
+        max_len: usize,
+        cb: extern "C" fn (user_data: i32, packet_tid: i32) -> i32,
+        user_data: i32
+    );
+    fn __ice_udp_packet_addr(
+        packet_tid: i32,
+        output: *mut u8,
+        output_len: usize
+    ) -> i32;
+    fn __ice_udp_take_packet(
+        packet_tid: i32,
+        output: *mut u8,
+        output_len: usize
+    ) -> i32;
+    fn __ice_udp_release_packet(packet_tid: i32);
+    fn __ice_udp_close(socket_tid: i32);
+    fn __ice_dns_resolve(
+        host_base: *const u8,
+        host_len: usize,
+        cb: extern "C" fn (user_data: i32, result_tid: i32) -> i32,
+        user_data: i32
+    );
+    fn __ice_dns_addresses(result_tid: i32, output: *mut u8, output_len: usize) -> i32;
+    fn __ice_dns_release(result_tid: i32);
     fn __ice_file_open(
         path_base: *const u8,
         path_len: usize,
@@ -66,8 +118,97 @@ extern "C" {
         from: i32,
         offset: i64
     ) -> i64;
+    fn __ice_file_read_async(
+        fd: i32,
+        len: usize,
+        cb: extern "C" fn (user_data: i32, buffer_id: i32) -> i32,
+        user_data: i32
+    );
+    fn __ice_file_write_async(
+        fd: i32,
+        data_base: *const u8,
+        data_len: usize,
+        cb: extern "C" fn (user_data: i32, len: i32) -> i32,
+        user_data: i32
+    );
+    fn __ice_file_take_buffer(
+        buffer_id: i32,
+        output: *mut u8,
+        output_len: usize
+    ) -> i32;
+    fn __ice_file_stat(
+        path_base: *const u8,
+        path_len: usize,
+        output: *mut u8,
+        output_len: usize
+    ) -> i32;
+    fn __ice_file_readdir(
+        path_base: *const u8,
+        path_len: usize,
+        output: *mut u8,
+        output_len: usize
+    ) -> i32;
+    fn __ice_file_mkdir(path_base: *const u8, path_len: usize, recursive: i32) -> i32;
+    fn __ice_file_remove(path_base: *const u8, path_len: usize, kind: i32) -> i32;
+    fn __ice_file_rename(
+        from_base: *const u8,
+        from_len: usize,
+        to_base: *const u8,
+        to_len: usize
+    ) -> i32;
+    fn __ice_file_truncate(fd: i32, len: i64) -> i32;
+    fn __ice_file_sync_all(fd: i32) -> i32;
+    fn __ice_http_method(req: i32, output: *mut u8, output_len: usize) -> i32;
+    fn __ice_http_path(req: i32, output: *mut u8, output_len: usize) -> i32;
+    fn __ice_http_header(
+        req: i32,
+        name_base: *const u8,
+        name_len: usize,
+        output: *mut u8,
+        output_len: usize
+    ) -> i32;
+    fn __ice_http_headers(req: i32, output: *mut u8, output_len: usize) -> i32;
+    fn __ice_http_body(req: i32, output: *mut u8, output_len: usize) -> i32;
+    fn __ice_http_set_status(req: i32, status: i32) -> i32;
+    fn __ice_http_add_header(
+        req: i32,
+        name_base: *const u8,
+        name_len: usize,
+        value_base: *const u8,
+        value_len: usize
+    ) -> i32;
+    fn __ice_http_write_body(req: i32, data_base: *const u8, data_len: usize) -> i32;
+    fn __ice_http_end(req: i32);
+    fn __ice_http_client_request(
+        method_base: *const u8,
+        method_len: usize,
+        url_base: *const u8,
+        url_len: usize,
+        headers_base: *const u8,
+        headers_len: usize,
+        body_base: *const u8,
+        body_len: usize,
+        cb: extern "C" fn (user_data: i32, resp: i32) -> i32,
+        user_data: i32
+    );
+    fn __ice_http_client_status(resp: i32) -> i32;
+    fn __ice_http_client_headers(resp: i32, output: *mut u8, output_len: usize) -> i32;
+    fn __ice_http_client_body_len(resp: i32) -> i32;
+    fn __ice_http_client_take_body(resp: i32, output: *mut u8, output_len: usize) -> i32;
+    fn __ice_http_client_release(resp: i32);
     fn __ice_timer_now_millis() -> i64;
-    fn __ice_timer_set_immediate(cb: extern "C" fn (user_data: i32) -> i32, user_data: i32);
+    fn __ice_timer_set_immediate(cb: extern "C" fn (user_data: i32) -> i32, user_data: i32) -> i32;
+    fn __ice_timer_set_timeout(
+        ms: i64,
+        cb: extern "C" fn (user_data: i32) -> i32,
+        user_data: i32
+    ) -> i32;
+    fn __ice_timer_set_interval(
+        ms: i64,
+        cb: extern "C" fn (user_data: i32) -> i32,
+        user_data: i32
+    ) -> i32;
+    fn __ice_timer_clear(id: i32) -> i32;
     fn __ice_logging_info(base: *const u8, len: usize);
     fn __ice_logging_warning(base: *const u8, len: usize);
 }
@@ -82,6 +223,32 @@ macro_rules! app_init {
     }
 }
 
+/// Declares the version of the application passed to `app_upgrade!` of the
+/// code that replaces it.
+#[macro_export]
+macro_rules! app_version {
+    ($version:expr) => {
+        #[no_mangle]
+        pub extern "C" fn __app_version() -> i32 {
+            $version
+        }
+    }
+}
+
+/// Opts in to keeping the memory of the application when its code is upgraded.
+///
+/// The body runs instead of `__app_init`, with the previous version bound to
+/// `$old_version`, and returns 0 on success.
+#[macro_export]
+macro_rules! app_upgrade {
+    ($old_version:ident, $body:block) => {
+        #[no_mangle]
+        pub extern "C" fn __app_upgrade($old_version: i32) -> i32 {
+            $body
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn __app_invoke0(
     target: extern "C" fn () -> i32
@@ -176,16 +343,85 @@ impl_wrap_callback!(a: i32, b: i32, c: i32, d: i32, );
 impl_wrap_callback!(a: i32, b: i32, c: i32, d: i32, e: i32, );
 impl_wrap_callback!(a: i32, b: i32, c: i32, d: i32, e: i32, f: i32, );
 
-/*
-pub fn set_timeout<T: FnOnce()>(ms: i64, cb: T) {
-    unimplemented!()
-}*/
+/// A timer registered with the host.
+///
+/// The timer is cancelled (if it hasn't fired yet) when this is dropped.
+pub struct Timer {
+    id: i32,
+    raw_ctx: i32,
+    fired: Rc<Cell<bool>>
+}
 
-pub fn schedule<T: FnOnce() + 'static>(cb: T) {
+impl Drop for Timer {
+    fn drop(&mut self) {
+        unsafe {
+            // Ids of fired timeouts may have been reused by the host.
+            if self.id >= 0 && !self.fired.get() {
+                __ice_timer_clear(self.id);
+            }
+            drop(Box::from_raw(self.raw_ctx as *mut Box<Fn() -> i32>));
+        }
+    }
+}
+
+fn start_timer<T: Fn() + 'static>(ms: i64, repeat: bool, cb: T) -> IoResult<Timer> {
+    let fired = Rc::new(Cell::new(false));
+    let fired2 = fired.clone();
+
+    let cb: Box<Fn() -> i32> = Box::new(move || {
+        if !repeat {
+            fired2.set(true);
+        }
+        cb();
+        0
+    });
+    let (cb, raw_ctx) = cb.wrap_callback();
+
+    let id = unsafe {
+        if repeat {
+            __ice_timer_set_interval(ms, cb, raw_ctx)
+        } else {
+            __ice_timer_set_timeout(ms, cb, raw_ctx)
+        }
+    };
+
+    let timer = Timer {
+        id: id,
+        raw_ctx: raw_ctx,
+        fired: fired
+    };
+    if id >= 0 {
+        Ok(timer)
+    } else {
+        Err(error::Io::Generic)
+    }
+}
+
+/// Calls `cb` once after `ms` milliseconds.
+pub fn set_timeout<T: Fn() + 'static>(ms: i64, cb: T) -> IoResult<Timer> {
+    start_timer(ms, false, cb)
+}
+
+/// Calls `cb` every `ms` milliseconds until the returned `Timer` is dropped.
+pub fn set_interval<T: Fn() + 'static>(ms: i64, cb: T) -> IoResult<Timer> {
+    start_timer(ms, true, cb)
+}
+
+/// Calls `cb` once the current callback returns.
+///
+/// Fails if the application has as many pending callbacks as it may.
+pub fn schedule<T: FnOnce() + 'static>(cb: T) -> IoResult<()> {
     let cb: Box<FnBox() -> i32> = Box::new(|| { cb(); 0 });
     let (cb, raw_ctx) = cb.wrap_callback();
-    unsafe {
-        __ice_timer_set_immediate(cb, raw_ctx);
+    let ret = unsafe {
+        __ice_timer_set_immediate(cb, raw_ctx)
+    };
+    if ret >= 0 {
+        Ok(())
+    } else {
+        // The host won't call back, so the callback is freed here.
+        drop(unsafe { Box::from_raw(raw_ctx as *mut Box<FnBox() -> i32>) });
+        Err(error::Io::Generic)
     }
 }
 
@@ -214,6 +450,17 @@ pub struct TcpStreamImpl {
     handle: i32
 }
 
+impl TcpStream {
+    /// Takes ownership of a stream id passed by the host.
+    pub unsafe fn from_raw(handle: i32) -> TcpStream {
+        TcpStream {
+            inner: Rc::new(TcpStreamImpl {
+                handle: handle
+            })
+        }
+    }
+}
+
 impl Drop for TcpStreamImpl {
     fn drop(&mut self) {
         unsafe {
@@ -300,6 +547,23 @@ impl TcpStreamImpl {
 pub fn listen_tcp<T: Fn(TcpStream) + 'static>(
     addr: &str,
     cb: T
+) -> i32 {
+    listen_tcp_with(addr, cb, false)
+}
+
+/// Like `listen_tcp`, but accepted streams are TLS-encrypted with the certificate
+/// configured for the application.
+pub fn listen_tls<T: Fn(TcpStream) + 'static>(
+    addr: &str,
+    cb: T
+) -> i32 {
+    listen_tcp_with(addr, cb, true)
+}
+
+fn listen_tcp_with<T: Fn(TcpStream) + 'static>(
+    addr: &str,
+    cb: T,
+    tls: bool
 ) -> i32 {
     let cb: Box<Fn(i32) -> i32> = Box::new(move |stream_tid| {
         if stream_tid >= 0 {
@@ -315,7 +579,8 @@ pub fn listen_tcp<T: Fn(TcpStream) + 'static>(
 
     unsafe {
         let addr = addr.as_bytes();
-        __ice_tcp_listen(
+        let listen = if tls { __ice_tcp_listen_tls } else { __ice_tcp_listen };
+        listen(
             &addr[0],
             addr.len(),
             cb,
@@ -327,6 +592,22 @@ pub fn listen_tcp<T: Fn(TcpStream) + 'static>(
 pub fn connect_tcp<F: FnOnce(IoResult<TcpStream>) + 'static>(
     addr: &str,
     cb: F
+) {
+    connect_tcp_with(addr, cb, false)
+}
+
+/// Like `connect_tcp`, but performs a TLS handshake after connecting.
+pub fn connect_tls<F: FnOnce(IoResult<TcpStream>) + 'static>(
+    addr: &str,
+    cb: F
+) {
+    connect_tcp_with(addr, cb, true)
+}
+
+fn connect_tcp_with<F: FnOnce(IoResult<TcpStream>) + 'static>(
+    addr: &str,
+    cb: F,
+    tls: bool
 ) {
     let cb: Box<FnBox(i32) -> i32> = Box::new(move |stream_tid| {
         cb(if stream_tid >= 0 {
@@ -345,7 +626,8 @@ pub fn connect_tcp<F: FnOnce(IoResult<TcpStream>) + 'static>(
 
     unsafe {
         let addr = addr.as_bytes();
-        __ice_tcp_connect(
+        let connect = if tls { __ice_tcp_connect_tls } else { __ice_tcp_connect };
+        connect(
             &addr[0],
             addr.len(),
             cb,
@@ -354,6 +636,338 @@ pub fn connect_tcp<F: FnOnce(IoResult<TcpStream>) + 'static>(
     }
 }
 
+#[derive(Clone)]
+pub struct UdpSocket {
+    inner: Rc<UdpSocketImpl>
+}
+
+impl Deref for UdpSocket {
+    type Target = UdpSocketImpl;
+    fn deref(&self) -> &Self::Target {
+        &*self.inner
+    }
+}
+
+pub struct UdpSocketImpl {
+    handle: i32
+}
+
+impl Drop for UdpSocketImpl {
+    fn drop(&mut self) {
+        unsafe {
+            __ice_udp_close(self.handle);
+        }
+    }
+}
+
+pub struct UdpPacket {
+    handle: i32
+}
+
+impl Drop for UdpPacket {
+    fn drop(&mut self) {
+        unsafe {
+            __ice_udp_release_packet(self.handle);
+        }
+    }
+}
+
+impl UdpPacket {
+    /// Returns the address of the peer that sent this packet.
+    pub fn addr(&self) -> String {
+        let mut buf: Vec<u8> = vec! [ 0; 64 ];
+        loop {
+            let buf_len = buf.len();
+            let len = unsafe { __ice_udp_packet_addr(
+                self.handle,
+                buf.as_mut_ptr(),
+                buf_len
+            ) } as usize;
+            if len <= buf.len() {
+                buf.truncate(len);
+                break;
+            }
+            buf = vec! [ 0; len ];
+        }
+        String::from_utf8(buf).unwrap()
+    }
+
+    pub fn take(self, out: &mut [u8]) -> IoResult<usize> {
+        let out_len = out.len();
+        let ret = unsafe { __ice_udp_take_packet(
+            self.handle,
+            out.as_mut_ptr(),
+            out_len
+        ) };
+        if ret >= 0 {
+            ::std::mem::forget(self);
+            Ok(ret as usize)
+        } else {
+            Err(error::Io::Generic)
+        }
+    }
+}
+
+impl UdpSocketImpl {
+    pub fn send_to<F: FnOnce(IoResult<i32>) + 'static>(&self, data: &[u8], addr: &str, cb: F) {
+        let cb: Box<FnBox(i32) -> i32> = Box::new(|a| {
+            cb(if a >= 0 {
+                Ok(a)
+            } else {
+                Err(error::Io::Generic)
+            });
+            0
+        });
+        let (cb, raw_ctx) = cb.wrap_callback();
+
+        unsafe {
+            let addr = addr.as_bytes();
+            __ice_udp_send_to(
+                self.handle,
+                data.as_ptr(),
+                data.len(),
+                addr.as_ptr(),
+                addr.len(),
+                cb,
+                raw_ctx
+            )
+        }
+    }
+
+    pub fn recv_from<F: FnOnce(IoResult<UdpPacket>) + 'static>(&self, max_len: usize, cb: F) {
+        let cb: Box<FnBox(i32) -> i32> = Box::new(|a| {
+            cb(if a >= 0 {
+                Ok(UdpPacket { handle: a })
+            } else {
+                Err(error::Io::Generic)
+            });
+            0
+        });
+        let (cb, raw_ctx) = cb.wrap_callback();
+
+        unsafe {
+            __ice_udp_recv_from(
+                self.handle,
+                max_len,
+                cb,
+                raw_ctx
+            )
+        }
+    }
+}
+
+pub fn bind_udp(addr: &str) -> IoResult<UdpSocket> {
+    let addr = addr.as_bytes();
+    let ret = unsafe {
+        __ice_udp_bind(
+            addr.as_ptr(),
+            addr.len()
+        )
+    };
+    if ret >= 0 {
+        Ok(UdpSocket {
+            inner: Rc::new(UdpSocketImpl {
+                handle: ret
+            })
+        })
+    } else {
+        Err(error::Io::Generic)
+    }
+}
+
+/// Resolves a host name to all of its IP addresses.
+///
+/// The application must have `TcpConnectAny` or a `TcpConnect` permission
+/// naming the host.
+pub fn dns_resolve<F: FnOnce(IoResult<Vec<String>>) + 'static>(host: &str, cb: F) {
+    let cb: Box<FnBox(i32) -> i32> = Box::new(|a| {
+        cb(if a >= 0 {
+            let addrs = fetch_bytes(|p, len| unsafe { __ice_dns_addresses(a, p, len) });
+            unsafe {
+                __ice_dns_release(a);
+            }
+            addrs.map(|v| {
+                String::from_utf8_lossy(&v)
+                    .split('\n')
+                    .filter(|v| v.len() > 0)
+                    .map(|v| v.to_string())
+                    .collect()
+            })
+        } else {
+            Err(error::Io::Generic)
+        });
+        0
+    });
+    let (cb, raw_ctx) = cb.wrap_callback();
+
+    unsafe {
+        __ice_dns_resolve(
+            host.as_ptr(),
+            host.len(),
+            cb,
+            raw_ctx
+        )
+    }
+}
+
+/// Calls a host function that writes at most `output_len` bytes to `output`
+/// and returns the full length of the data, growing the buffer until it fits.
+fn fetch_bytes<F: Fn(*mut u8, usize) -> i32>(f: F) -> IoResult<Vec<u8>> {
+    let mut buf: Vec<u8> = vec! [ 0; 256 ];
+    loop {
+        let buf_len = buf.len();
+        let ret = f(buf.as_mut_ptr(), buf_len);
+        if ret < 0 {
+            return Err(error::Io::Generic);
+        }
+        let len = ret as usize;
+        if len <= buf.len() {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+        buf = vec! [ 0; len ];
+    }
+}
+
+pub fn http_method(req: i32) -> IoResult<Vec<u8>> {
+    fetch_bytes(|p, len| unsafe { __ice_http_method(req, p, len) })
+}
+
+pub fn http_path(req: i32) -> IoResult<Vec<u8>> {
+    fetch_bytes(|p, len| unsafe { __ice_http_path(req, p, len) })
+}
+
+pub fn http_header(req: i32, name: &str) -> IoResult<Vec<u8>> {
+    let name = name.as_bytes();
+    fetch_bytes(|p, len| unsafe { __ice_http_header(req, name.as_ptr(), name.len(), p, len) })
+}
+
+pub fn http_headers(req: i32) -> IoResult<Vec<u8>> {
+    fetch_bytes(|p, len| unsafe { __ice_http_headers(req, p, len) })
+}
+
+pub fn http_body(req: i32) -> IoResult<Vec<u8>> {
+    fetch_bytes(|p, len| unsafe { __ice_http_body(req, p, len) })
+}
+
+pub fn http_set_status(req: i32, status: u16) -> IoResult<()> {
+    let ret = unsafe { __ice_http_set_status(req, status as i32) };
+    if ret >= 0 {
+        Ok(())
+    } else {
+        Err(error::Io::Generic)
+    }
+}
+
+pub fn http_add_header(req: i32, name: &str, value: &str) -> IoResult<()> {
+    let name = name.as_bytes();
+    let value = value.as_bytes();
+    let ret = unsafe { __ice_http_add_header(
+        req,
+        name.as_ptr(),
+        name.len(),
+        value.as_ptr(),
+        value.len()
+    ) };
+    if ret >= 0 {
+        Ok(())
+    } else {
+        Err(error::Io::Generic)
+    }
+}
+
+pub fn http_write_body(req: i32, data: &[u8]) -> IoResult<()> {
+    let ret = unsafe { __ice_http_write_body(req, data.as_ptr(), data.len()) };
+    if ret >= 0 {
+        Ok(())
+    } else {
+        Err(error::Io::Generic)
+    }
+}
+
+pub fn http_end(req: i32) {
+    unsafe {
+        __ice_http_end(req);
+    }
+}
+
+/// A response to an outbound HTTP request.
+pub struct HttpClientResponse {
+    handle: i32
+}
+
+impl Drop for HttpClientResponse {
+    fn drop(&mut self) {
+        unsafe {
+            __ice_http_client_release(self.handle);
+        }
+    }
+}
+
+impl HttpClientResponse {
+    pub fn status(&self) -> u16 {
+        unsafe { __ice_http_client_status(self.handle) as u16 }
+    }
+
+    /// Returns all headers in the format `Name: Value\r\n`.
+    pub fn headers(&self) -> IoResult<Vec<u8>> {
+        fetch_bytes(|p, len| unsafe { __ice_http_client_headers(self.handle, p, len) })
+    }
+
+    pub fn take_body(self) -> IoResult<Vec<u8>> {
+        let len = unsafe { __ice_http_client_body_len(self.handle) } as usize;
+        let mut buf: Vec<u8> = vec! [ 0; len ];
+        let ret = unsafe { __ice_http_client_take_body(
+            self.handle,
+            buf.as_mut_ptr(),
+            len
+        ) };
+        if ret >= 0 {
+            ::std::mem::forget(self);
+            buf.truncate(ret as usize);
+            Ok(buf)
+        } else {
+            Err(error::Io::Generic)
+        }
+    }
+}
+
+/// Sends an HTTP request to `url`.
+///
+/// `headers` contains lines in the format `Name: Value\r\n`.
+pub fn http_request<F: FnOnce(IoResult<HttpClientResponse>) + 'static>(
+    method: &str,
+    url: &str,
+    headers: &str,
+    body: &[u8],
+    cb: F
+) {
+    let cb: Box<FnBox(i32) -> i32> = Box::new(|a| {
+        cb(if a >= 0 {
+            Ok(HttpClientResponse { handle: a })
+        } else {
+            Err(error::Io::Generic)
+        });
+        0
+    });
+    let (cb, raw_ctx) = cb.wrap_callback();
+
+    unsafe {
+        __ice_http_client_request(
+            method.as_ptr(),
+            method.len(),
+            url.as_ptr(),
+            url.len(),
+            headers.as_ptr(),
+            headers.len(),
+            body.as_ptr(),
+            body.len(),
+            cb,
+            raw_ctx
+        )
+    }
+}
+
 pub fn file_open(path: &str, mode: &str) -> IoResult<i32> {
     let path = path.as_bytes();
     let mode = mode.as_bytes();
@@ -444,3 +1058,119 @@ pub fn file_seek(fd: i32, from: SeekFrom) -> IoResult<u64> {
         Err(error::Io::Generic)
     }
 }
+
+/// Reads at most `len` bytes without blocking the event loop.
+pub fn file_read_async<F: FnOnce(IoResult<Vec<u8>>) + 'static>(fd: i32, len: usize, cb: F) {
+    let cb: Box<FnBox(i32) -> i32> = Box::new(move |buffer_id| {
+        cb(if buffer_id >= 0 {
+            let mut buf: Vec<u8> = vec! [ 0; len ];
+            let ret = unsafe { __ice_file_take_buffer(
+                buffer_id,
+                buf.as_mut_ptr(),
+                len
+            ) };
+            if ret >= 0 {
+                buf.truncate(ret as usize);
+                Ok(buf)
+            } else {
+                Err(error::Io::Generic)
+            }
+        } else {
+            Err(error::Io::Generic)
+        });
+        0
+    });
+    let (cb, raw_ctx) = cb.wrap_callback();
+
+    unsafe {
+        __ice_file_read_async(
+            fd,
+            len,
+            cb,
+            raw_ctx
+        )
+    }
+}
+
+/// Writes `data` without blocking the event loop.
+pub fn file_write_async<F: FnOnce(IoResult<usize>) + 'static>(fd: i32, data: &[u8], cb: F) {
+    let cb: Box<FnBox(i32) -> i32> = Box::new(|a| {
+        cb(if a >= 0 {
+            Ok(a as usize)
+        } else {
+            Err(error::Io::Generic)
+        });
+        0
+    });
+    let (cb, raw_ctx) = cb.wrap_callback();
+
+    unsafe {
+        __ice_file_write_async(
+            fd,
+            data.as_ptr(),
+            data.len(),
+            cb,
+            raw_ctx
+        )
+    }
+}
+
+fn check_ret(ret: i32) -> IoResult<()> {
+    if ret >= 0 {
+        Ok(())
+    } else {
+        Err(error::Io::Generic)
+    }
+}
+
+/// Size of the record written by `__ice_file_stat`.
+pub const FILE_STAT_SIZE: usize = 24;
+
+/// Returns the raw stat record of `path`, decoded by `fs::metadata`.
+pub fn file_stat(path: &str) -> IoResult<[u8; FILE_STAT_SIZE]> {
+    let mut out = [0u8; FILE_STAT_SIZE];
+    check_ret(unsafe { __ice_file_stat(
+        path.as_ptr(),
+        path.len(),
+        out.as_mut_ptr(),
+        out.len()
+    ) })?;
+    Ok(out)
+}
+
+/// Returns the names of the entries in a directory.
+pub fn file_readdir(path: &str) -> IoResult<Vec<String>> {
+    let names = fetch_bytes(|p, len| unsafe {
+        __ice_file_readdir(path.as_ptr(), path.len(), p, len)
+    })?;
+    Ok(names.split(|b| *b == 0)
+        .filter(|v| v.len() > 0)
+        .map(|v| String::from_utf8_lossy(v).into_owned())
+        .collect())
+}
+
+pub fn file_mkdir(path: &str, recursive: bool) -> IoResult<()> {
+    check_ret(unsafe { __ice_file_mkdir(path.as_ptr(), path.len(), recursive as i32) })
+}
+
+/// `kind`: `0` file, `1` empty directory, `2` directory and its contents.
+pub fn file_remove(path: &str, kind: i32) -> IoResult<()> {
+    check_ret(unsafe { __ice_file_remove(path.as_ptr(), path.len(), kind) })
+}
+
+pub fn file_rename(from: &str, to: &str) -> IoResult<()> {
+    check_ret(unsafe { __ice_file_rename(
+        from.as_ptr(),
+        from.len(),
+        to.as_ptr(),
+        to.len()
+    ) })
+}
+
+pub fn file_truncate(fd: i32, len: u64) -> IoResult<()> {
+    check_ret(unsafe { __ice_file_truncate(fd, len as i64) })
+}
+
+pub fn file_sync_all(fd: i32) -> IoResult<()> {
+    check_ret(unsafe { __ice_file_sync_all(fd) })
+}
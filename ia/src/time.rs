@@ -0,0 +1,144 @@
+use futures::prelude::*;
+
+use std::rc::Rc;
+use std::cell::Cell;
+
+use error::Io;
+
+/// A `Future` that resolves after a given number of milliseconds.
+pub struct Delay {
+    ms: u64,
+    timer: Option<::raw::Timer>,
+    fired: Rc<Cell<bool>>
+}
+
+impl Delay {
+    pub fn new(ms: u64) -> Delay {
+        Delay {
+            ms: ms,
+            timer: None,
+            fired: Rc::new(Cell::new(false))
+        }
+    }
+}
+
+impl Future for Delay {
+    type Item = ();
+    type Error = Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<()>, Io> {
+        if self.fired.get() {
+            return Ok(Async::Ready(()));
+        }
+
+        if self.timer.is_none() {
+            let fired = self.fired.clone();
+            let task = ::executor::current_task();
+
+            self.timer = Some(::raw::set_timeout(self.ms as i64, move || {
+                fired.set(true);
+                ::executor::run_once_next_tick(&task);
+            })?);
+        }
+
+        Ok(Async::NotReady)
+    }
+}
+
+/// A `Stream` that yields every given number of milliseconds.
+///
+/// The underlying host timer is cancelled when this is dropped.
+pub struct Interval {
+    ms: u64,
+    timer: Option<::raw::Timer>,
+    ticks: Rc<Cell<usize>>
+}
+
+impl Interval {
+    pub fn new(ms: u64) -> Interval {
+        Interval {
+            ms: ms,
+            timer: None,
+            ticks: Rc::new(Cell::new(0))
+        }
+    }
+}
+
+impl Stream for Interval {
+    type Item = ();
+    type Error = Io;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<Option<()>>, Io> {
+        if self.timer.is_none() {
+            let ticks = self.ticks.clone();
+            let task = ::executor::current_task();
+
+            self.timer = Some(::raw::set_interval(self.ms as i64, move || {
+                ticks.set(ticks.get() + 1);
+                ::executor::run_once_next_tick(&task);
+            })?);
+        }
+
+        let ticks = self.ticks.get();
+        if ticks > 0 {
+            self.ticks.set(ticks - 1);
+            Ok(Async::Ready(Some(())))
+        } else {
+            Ok(Async::NotReady)
+        }
+    }
+}
+
+/// The error type of `Timeout`.
+#[derive(Debug)]
+pub enum TimeoutError<E> {
+    /// The inner future didn't complete in time.
+    Elapsed,
+
+    /// The timer couldn't be set up.
+    Timer(Io),
+
+    /// The inner future failed.
+    Inner(E)
+}
+
+/// Wraps a `Future` and fails with `TimeoutError::Elapsed` if it doesn't
+/// complete within a given number of milliseconds.
+pub struct Timeout<F: Future> {
+    inner: F,
+    delay: Delay
+}
+
+impl<F: Future> Timeout<F> {
+    pub fn new(inner: F, ms: u64) -> Timeout<F> {
+        Timeout {
+            inner: inner,
+            delay: Delay::new(ms)
+        }
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Item = F::Item;
+    type Error = TimeoutError<F::Error>;
+
+    fn poll(
+        &mut self
+    ) -> Result<Async<F::Item>, TimeoutError<F::Error>> {
+        match self.inner.poll() {
+            Ok(Async::Ready(v)) => return Ok(Async::Ready(v)),
+            Ok(Async::NotReady) => {},
+            Err(e) => return Err(TimeoutError::Inner(e))
+        }
+
+        match self.delay.poll() {
+            Ok(Async::Ready(())) => Err(TimeoutError::Elapsed),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => Err(TimeoutError::Timer(e))
+        }
+    }
+}
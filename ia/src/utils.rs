@@ -28,7 +28,7 @@ impl Future for NextTick {
             ::raw::schedule(move || {
                 notify.store(true, Ordering::Relaxed);
                 ::executor::run_once_next_tick(&task);
-            });
+            }).expect("unable to schedule the next tick");
         }
 
         Ok(Async::NotReady)
@@ -0,0 +1,381 @@
+//! Command-line client for the management API of `ice_core`.
+
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_yaml;
+extern crate bincode;
+extern crate chrono;
+
+#[path = "../management/protocol.rs"]
+#[allow(dead_code)]
+mod protocol;
+
+use std::io;
+use std::io::{Read, Write};
+use std::fs::{self, File};
+use std::process;
+
+use protocol::{Request, Response, Command};
+
+const USAGE: &'static str = "\
+Usage: ice-ctl [-c config.yaml] [-a address] [-t token] <command>
+
+The address and token default to the `management` section of the config file.
+
+Commands:
+    status                          List applications
+    stats [app]                     Show statistics, in detail for a single application
+    migrate-out <app> -o <file>     Stop an application and save its state
+    migrate-in <app> <file>         Start a stopped application from saved state
+    migrate-to <app> <host:port>    Move an application to another host
+    checkpoint <app>                Save the state of an application to its restore_from file
+    upgrade <app> <file.wasm>       Replace the code of a running application
+    load <app>                      Start an application, loading it from the config file
+    unload <app>                    Stop an application and free its resources
+    reload                          Re-read the config file
+    logs <app>                      Show recent log lines of an application";
+
+struct Options {
+    config: String,
+    address: Option<String>,
+    token: Option<String>,
+    args: Vec<String>
+}
+
+#[derive(Deserialize)]
+struct RootConfig {
+    #[serde(default)]
+    management: Option<ManagementConfig>
+}
+
+#[derive(Deserialize)]
+struct ManagementConfig {
+    listen: String,
+    token: String
+}
+
+trait Transport: Read + Write {}
+impl<T: Read + Write> Transport for T {}
+
+fn fail<M: AsRef<str>>(msg: M) -> ! {
+    eprintln!("ice-ctl: {}", msg.as_ref());
+    process::exit(1);
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+fn parse_options() -> Options {
+    let mut opts = Options {
+        config: "config.yaml".into(),
+        address: None,
+        token: None,
+        args: Vec::new()
+    };
+
+    let mut args = ::std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" => opts.config = args.next().unwrap_or_else(|| usage()),
+            "-a" => opts.address = Some(args.next().unwrap_or_else(|| usage())),
+            "-t" => opts.token = Some(args.next().unwrap_or_else(|| usage())),
+            "-h" | "--help" => usage(),
+            _ => {
+                opts.args.push(arg);
+                opts.args.extend(args);
+                break;
+            }
+        }
+    }
+
+    opts
+}
+
+/// Returns the address and token, falling back to the config file for
+/// those not given on the command line.
+fn endpoint(opts: &Options) -> (String, String) {
+    if let (Some(address), Some(token)) = (opts.address.clone(), opts.token.clone()) {
+        return (address, token);
+    }
+
+    let mut text = String::new();
+    File::open(&opts.config)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .unwrap_or_else(|e| fail(format!("Unable to read {}: {}", opts.config, e)));
+
+    let config: RootConfig = serde_yaml::from_str(&text)
+        .unwrap_or_else(|e| fail(format!("Unable to parse {}: {}", opts.config, e)));
+    let management = config.management
+        .unwrap_or_else(|| fail(format!("No `management` section in {}", opts.config)));
+
+    (
+        opts.address.clone().unwrap_or(management.listen),
+        opts.token.clone().unwrap_or(management.token)
+    )
+}
+
+fn connect(address: &str) -> io::Result<Box<Transport>> {
+    if address.starts_with("unix:") {
+        connect_unix(&address["unix:".len()..])
+    } else {
+        Ok(Box::new(::std::net::TcpStream::connect(address)?))
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> io::Result<Box<Transport>> {
+    Ok(Box::new(::std::os::unix::net::UnixStream::connect(path)?))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_path: &str) -> io::Result<Box<Transport>> {
+    Err(io::Error::new(io::ErrorKind::Other, "unix sockets are not supported on this platform"))
+}
+
+fn call(conn: &mut Transport, req: &Request) -> io::Result<Response> {
+    let payload = bincode::serialize(req)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    conn.write_all(&protocol::encode_frame(&payload))?;
+    conn.flush()?;
+
+    let mut header = [0u8; 4];
+    conn.read_exact(&mut header)?;
+    let len = protocol::decode_frame_len(&header, protocol::MAX_FRAME_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "frame too large"))?;
+
+    let mut data = vec! [ 0u8; len ];
+    conn.read_exact(&mut data)?;
+    bincode::deserialize(&data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    let mut data = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .unwrap_or_else(|e| fail(format!("Unable to read {}: {}", path, e)));
+    data
+}
+
+/// Where `migrate-out` saves the state of an application. It is written to
+/// `<path>.tmp`, which is created before the application is stopped, and
+/// renamed to `path` once complete.
+struct Output {
+    app: String,
+    path: String,
+    tmp_path: String,
+    file: File
+}
+
+impl Output {
+    fn create(app: &str, path: &str) -> Output {
+        let tmp_path = format!("{}.tmp", path);
+        let file = File::create(&tmp_path)
+            .unwrap_or_else(|e| fail(format!("Unable to create {}: {}", tmp_path, e)));
+        Output {
+            app: app.to_string(),
+            path: path.to_string(),
+            tmp_path: tmp_path,
+            file: file
+        }
+    }
+
+    fn discard(&self) {
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+
+    fn save(mut self, data: &[u8]) -> io::Result<()> {
+        let result = self.file.write_all(data)
+            .and_then(|_| self.file.sync_all())
+            .and_then(|_| fs::rename(&self.tmp_path, &self.path));
+        if result.is_err() {
+            self.discard();
+        }
+        result
+    }
+}
+
+fn format_time(millis: i64) -> String {
+    use chrono::TimeZone;
+
+    chrono::Local.timestamp_millis(millis).format("%Y-%m-%d %T").to_string()
+}
+
+fn print_app_stats(s: &protocol::AppStats) {
+    println!("Started:            {}", format_time(s.start_time));
+    println!("Running:            {}s{}", s.running_time / 1000, if s.crashed { " (crashed)" } else { "" });
+    println!("Memory:             {} KiB", s.memory / 1024);
+    println!("Peak memory:        {} KiB", s.memory_peak / 1024);
+    println!("Host memory:        {} KiB", s.host_memory / 1024);
+    println!("Callback timeouts:  {}", s.callback_timeouts);
+    println!("Open files:         {}", s.files);
+    println!("TCP streams:        {}", s.tcp_streams);
+    println!("Listeners:          {}", s.listeners);
+    println!("Pending callbacks:  {}", s.pending_callbacks);
+    println!("Buffers:            {}", s.buffers);
+    println!("UDP sockets:        {}", s.udp_sockets);
+}
+
+fn main() {
+    let opts = parse_options();
+    let args: Vec<&str> = opts.args.iter().map(|v| v.as_str()).collect();
+
+    // Only set for `migrate-out`.
+    let mut output: Option<Output> = None;
+
+    let command = match args.as_slice() {
+        ["status"] => Command::ListApps,
+        ["stats"] | ["stats", _] => Command::Stats,
+        ["migrate-out", app, "-o", file] | ["migrate-out", "-o", file, app] => {
+            output = Some(Output::create(app, file));
+            Command::MigrateOut { app: app.to_string() }
+        },
+        ["migrate-in", app, file] => Command::MigrateIn {
+            app: app.to_string(),
+            migration: read_file(file)
+        },
+        ["migrate-to", app, target] => Command::MigrateTo {
+            app: app.to_string(),
+            target: target.to_string()
+        },
+        ["checkpoint", app] => Command::Checkpoint { app: app.to_string() },
+        ["upgrade", app, file] => Command::Upgrade {
+            app: app.to_string(),
+            code: read_file(file)
+        },
+        ["load", app] => Command::LoadApp { app: app.to_string() },
+        ["unload", app] => Command::UnloadApp { app: app.to_string() },
+        ["reload"] => Command::Reload,
+        ["logs", app] => Command::Logs { app: app.to_string() },
+        _ => usage()
+    };
+
+    let (address, token) = endpoint(&opts);
+    let mut conn = connect(&address)
+        .unwrap_or_else(|e| fail(format!("Unable to connect to {}: {}", address, e)));
+    let req = Request {
+        token: token,
+        command: command
+    };
+
+    // The server only reads large requests once the connection has been
+    // authenticated by a small one.
+    if bincode::serialized_size(&req).unwrap_or(0) > protocol::MAX_REQUEST_LEN as u64 {
+        match call(&mut *conn, &Request {
+            token: req.token.clone(),
+            command: Command::Ping
+        }) {
+            Ok(Response::Ok) => {},
+            Ok(Response::Error(e)) => fail(e),
+            Ok(_) => fail("Unexpected response"),
+            Err(e) => fail(format!("Request failed: {}", e))
+        }
+    }
+
+    let resp = call(&mut *conn, &req);
+    match (&output, &resp) {
+        (&Some(ref output), &Ok(Response::Error(_))) | (&Some(ref output), &Err(_)) => output.discard(),
+        _ => {}
+    }
+    let resp = resp.unwrap_or_else(|e| fail(format!("Request failed: {}", e)));
+
+    match resp {
+        Response::Ok => {},
+        Response::Error(e) => fail(e),
+        Response::Apps(apps) => {
+            println!("{:<4} {:<24} {}", "ID", "NAME", "STATE");
+            for app in apps {
+                println!(
+                    "{:<4} {:<24} {}",
+                    app.id,
+                    app.name,
+                    if app.running {
+                        "running"
+                    } else if app.crashed {
+                        "crashed"
+                    } else {
+                        "stopped"
+                    }
+                );
+            }
+        },
+        Response::Stats(stats) => {
+            if let Some(name) = args.get(1) {
+                let s = stats.get(*name)
+                    .unwrap_or_else(|| fail(format!("Application `{}` is not running", name)));
+                print_app_stats(s);
+                return;
+            }
+
+            println!(
+                "{:<24} {:<20} {:<12} {:<10} {:<10} {:<10} {}",
+                "NAME", "STARTED", "RUNNING (s)", "MEM (KiB)", "PEAK", "HOST", "TIMEOUTS"
+            );
+            for (name, s) in &stats {
+                println!(
+                    "{:<24} {:<20} {:<12} {:<10} {:<10} {:<10} {}",
+                    name,
+                    format_time(s.start_time),
+                    if s.crashed {
+                        "crashed".to_string()
+                    } else {
+                        format!("{}", s.running_time / 1000)
+                    },
+                    s.memory / 1024,
+                    s.memory_peak / 1024,
+                    s.host_memory / 1024,
+                    s.callback_timeouts
+                );
+            }
+        },
+        Response::Migration(data) => {
+            let output = output.unwrap_or_else(|| fail("Unexpected migration data"));
+            let (app, path) = (output.app.clone(), output.path.clone());
+            if let Err(e) = output.save(&data) {
+                // The application has been stopped, so it gets its state back.
+                let restored = call(&mut *conn, &Request {
+                    token: req.token.clone(),
+                    command: Command::MigrateIn {
+                        app: app,
+                        migration: data
+                    }
+                });
+                fail(match restored {
+                    Ok(Response::Ok) => format!("Unable to write {}: {}; the application was resumed", path, e),
+                    Ok(Response::Error(re)) => format!("Unable to write {}: {}; unable to resume the application: {}", path, e, re),
+                    Ok(_) => format!("Unable to write {}: {}; unexpected response while resuming the application", path, e),
+                    Err(re) => format!("Unable to write {}: {}; unable to resume the application: {}", path, e, re)
+                });
+            }
+            println!("Saved {} bytes to {}", data.len(), path);
+        },
+        Response::Logs(lines) => {
+            for line in lines {
+                println!("{}", line);
+            }
+        },
+        Response::Reloaded(report) => {
+            for name in &report.started {
+                println!("Started {}", name);
+            }
+            for name in &report.stopped {
+                println!("Stopped {}", name);
+            }
+            for name in &report.updated {
+                println!("Updated {}", name);
+            }
+            for change in &report.restart_required {
+                println!("Restart required: {}", change);
+            }
+            for e in &report.errors {
+                eprintln!("Error: {}", e);
+            }
+            if !report.errors.is_empty() {
+                process::exit(1);
+            }
+        }
+    }
+}
@@ -1,12 +1,46 @@
 use std::collections::{BTreeSet, BTreeMap};
 use std::path::Path;
+use std::net::IpAddr;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
     #[serde(default)]
     pub applications: Vec<ApplicationConfig>,
     #[serde(default)]
-    pub services: Vec<ServiceConfig>
+    pub services: Vec<ServiceConfig>,
+
+    /// Enables the management API if present.
+    #[serde(default)]
+    pub management: Option<ManagementConfig>,
+
+    /// Enables moving applications between hosts if present.
+    #[serde(default)]
+    pub migration: Option<MigrationConfig>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ManagementConfig {
+    /// `host:port` on a loopback address, or `unix:/path/to/socket`.
+    pub listen: String,
+
+    /// Shared secret that every request must carry.
+    pub token: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MigrationConfig {
+    /// Address to accept applications on. Hosts that only send
+    /// applications away may leave it out.
+    #[serde(default)]
+    pub listen: Option<String>,
+
+    /// Hosts allowed to send applications. Connections from other
+    /// addresses are closed right away. Empty means any host.
+    #[serde(default)]
+    pub allow_from: Vec<IpAddr>,
+
+    /// Shared secret of all hosts that exchange applications.
+    pub token: String
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -21,10 +55,96 @@ pub struct ApplicationConfig {
     pub env: BTreeMap<String, String>,
     #[serde(default)]
     pub deferred: bool,
+    #[serde(default)]
+    pub tls: AppTlsConfig,
+
+    /// Snapshot to resume the application from at startup, if the file exists.
+    /// Checkpoints are written to the same file.
+    #[serde(default)]
+    pub restore_from: Option<String>,
+
+    /// Seconds between automatic checkpoints to `restore_from`.
+    #[serde(default)]
+    pub checkpoint_interval: Option<u64>,
+
+    /// Host directories visible to the application. If any are declared, paths
+    /// passed to the `file` namespace are guest paths resolved through them.
+    #[serde(default)]
+    pub mounts: Vec<MountConfig>,
+
+    /// What to do when the application crashes.
+    #[serde(default)]
+    pub restart: RestartPolicy,
+
+    /// Consecutive restarts after which a crashed application is left stopped.
+    #[serde(default = "default_max_restarts")]
+    pub max_restarts: u32,
+
+    /// Milliseconds a single call into the application may run before it is
+    /// interrupted and the application crashes. Unlimited if not set.
+    #[serde(default)]
+    pub max_callback_ms: Option<u64>,
+
+    /// Caps on handles, connections and pending callbacks.
+    #[serde(default)]
+    pub limits: AppLimits,
     #[serde(skip)]
     pub metadata: AppMetadata
 }
 
+fn default_max_restarts() -> u32 {
+    5
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum RestartPolicy {
+    /// Leave a crashed application stopped.
+    #[serde(rename = "never")]
+    Never,
+
+    /// Restart an application that crashed after it was initialized.
+    #[serde(rename = "on-failure")]
+    OnFailure,
+
+    /// Also restart an application whose initializer failed.
+    #[serde(rename = "always")]
+    Always
+}
+
+impl Default for RestartPolicy {
+    fn default() -> RestartPolicy {
+        RestartPolicy::Never
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MountConfig {
+    /// Absolute path seen by the application, e.g. `/data`.
+    pub guest: String,
+
+    /// Host directory, relative to the working directory of Ice like `path`.
+    pub host: String,
+
+    #[serde(default)]
+    pub readonly: bool
+}
+
+/// Paths to PEM files used by `tcp_connect_tls` and `tcp_listen_tls`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AppTlsConfig {
+    /// Certificate chain presented by `listen_tls`.
+    #[serde(default)]
+    pub cert: Option<String>,
+
+    /// PKCS#8 private key for `cert`.
+    #[serde(default)]
+    pub key: Option<String>,
+
+    /// An additional root certificate trusted by `connect_tls`.
+    #[serde(default)]
+    pub ca: Option<String>
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct AppMetadata {
     pub package_name: String,
@@ -33,17 +153,56 @@ pub struct AppMetadata {
     pub bin: String
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct AppMemoryConfig {
     pub min: usize,
-    pub max: usize
+    pub max: usize,
+
+    /// Bytes the host may hold on behalf of the application, such as
+    /// received data it hasn't taken yet. Reads are clamped to what is left.
+    #[serde(default = "default_host_max")]
+    pub host_max: usize
+}
+
+fn default_host_max() -> usize {
+    64 * 1048576
 }
 
 impl Default for AppMemoryConfig {
     fn default() -> AppMemoryConfig {
         AppMemoryConfig {
             min: 64 * 65536,
-            max: 256 * 65536
+            max: 256 * 65536,
+            host_max: default_host_max()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AppLimits {
+    pub files: usize,
+    pub tcp_streams: usize,
+    pub listeners: usize,
+
+    /// Timers and asynchronous operations that have yet to call back.
+    pub pending_callbacks: usize,
+
+    /// Received data not taken or released yet.
+    pub buffers: usize,
+
+    pub udp_sockets: usize
+}
+
+impl Default for AppLimits {
+    fn default() -> AppLimits {
+        AppLimits {
+            files: 1024,
+            tcp_streams: 1024,
+            listeners: 64,
+            pending_callbacks: 4096,
+            buffers: 1024,
+            udp_sockets: 64
         }
     }
 }
@@ -55,54 +214,123 @@ pub enum AppPermission {
     TcpListenAny,
     TcpConnect(String),
     TcpConnectAny,
+    UdpBind(String /* address */),
+    UdpBindAny,
+    UdpSendTo,
+    HttpRequest(String /* host */),
+    HttpRequestAny,
     FileOpenReadOnlyAny,
-    FileOpenReadWriteAny
+    FileOpenReadWriteAny,
+    FileRead(String /* path prefix */),
+    FileReadWrite(String /* path prefix */)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ServiceConfig {
-    pub kind: ServiceKind
+    pub kind: ServiceKind,
+    pub address: String,
+
+    /// Name of the application that handles requests to this service.
+    pub app: String,
+
+    /// The exported function to call. Defaults to `ServiceKind::default_entry()`.
+    #[serde(default)]
+    pub entry: Option<String>
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum ServiceKind {
     Tcp,
     Http
 }
 
+impl ServiceKind {
+    pub fn default_entry(&self) -> &'static str {
+        match *self {
+            ServiceKind::Tcp => "__app_tcp_accept",
+            ServiceKind::Http => "__app_http_handle"
+        }
+    }
+}
+
+impl ServiceConfig {
+    pub fn entry(&self) -> &str {
+        match self.entry {
+            Some(ref v) => v.as_str(),
+            None => self.kind.default_entry()
+        }
+    }
+}
+
 fn read_and_parse_yaml_config<
     P: AsRef<Path> + ::std::fmt::Display,
     T
->(path: P) -> T
+>(path: P) -> Result<T, String>
     where for<'de> T: ::serde::Deserialize<'de>
 {
     use std::fs::File;
     use std::io::Read;
 
-    let mut file = File::open(&path)
-        .unwrap_or_else(|e| {
-            panic!("Unable to open configuration file located at {}: {:?}", path, e)
-        });
-
     let mut text = String::new();
-    file.read_to_string(&mut text).unwrap();
+    File::open(&path)
+        .and_then(|mut file| file.read_to_string(&mut text))
+        .map_err(|e| format!("Unable to read configuration file located at {}: {:?}", path, e))?;
 
-    ::serde_yaml::from_str(&text).unwrap_or_else(|e| {
-        panic!("Unable to parse configuration: {:?}", e);
-    })
+    ::serde_yaml::from_str(&text)
+        .map_err(|e| format!("Unable to parse configuration {}: {:?}", path, e))
 }
 
 impl Config {
-    pub fn from_file(path: &str) -> Config {
-        let mut config: Config = read_and_parse_yaml_config(path);
+    pub fn from_file(path: &str) -> Result<Config, String> {
+        let mut config: Config = read_and_parse_yaml_config(path)?;
 
         for app in &mut config.applications {
             let app_root = app.path.clone();
             let metadata_path = Path::new(&app_root).join("config.yaml");
 
-            app.metadata = read_and_parse_yaml_config(metadata_path.to_str().unwrap());
+            app.metadata = read_and_parse_yaml_config(metadata_path.to_str().unwrap())?;
+        }
+
+        Ok(config)
+    }
+}
+
+impl ApplicationConfig {
+    /// Lists the fields that differ from `other` and only take effect when
+    /// the application is restarted.
+    ///
+    /// `env`, `mounts` and permissions are looked up on every use instead.
+    pub fn restart_required_changes(&self, other: &ApplicationConfig) -> Vec<&'static str> {
+        let mut changes = Vec::new();
+
+        if self.path != other.path {
+            changes.push("path");
+        }
+        if self.metadata.bin != other.metadata.bin {
+            changes.push("bin");
+        }
+        if self.memory != other.memory {
+            changes.push("memory");
+        }
+        if self.optimize != other.optimize {
+            changes.push("optimize");
+        }
+        if self.tls != other.tls {
+            changes.push("tls");
+        }
+        if self.restore_from != other.restore_from {
+            changes.push("restore_from");
+        }
+        if self.checkpoint_interval != other.checkpoint_interval {
+            changes.push("checkpoint_interval");
+        }
+        if self.max_callback_ms != other.max_callback_ms {
+            changes.push("max_callback_ms");
+        }
+        if self.limits != other.limits {
+            changes.push("limits");
         }
 
-        config
+        changes
     }
 }
@@ -3,10 +3,15 @@ use std::ops::Deref;
 use std::collections::BTreeMap;
 
 use config::Config;
+use dns::Resolver;
+use logging::LogBuffer;
 use lssa::control::Control;
 
 use futures::sync::mpsc::Sender;
 use futures::Sink;
+use futures_cpupool::{CpuPool, Builder as CpuPoolBuilder};
+
+const IO_THREADS: usize = 4;
 
 #[derive(Clone)]
 pub struct Container {
@@ -14,7 +19,16 @@ pub struct Container {
 }
 
 pub struct ContainerImpl {
+    /// Path of the root config file, re-read on reload.
+    pub config_path: String,
     pub config_state: RwLock<ConfigState>,
+    pub resolver: Resolver,
+
+    /// Runs blocking file I/O off the event loop.
+    pub io_pool: CpuPool,
+
+    /// Recent output of the `logging` namespace, per application.
+    pub app_logs: LogBuffer,
     control_dispatcher: Mutex<Option<ControlDispatcher>>
 }
 
@@ -28,7 +42,7 @@ pub struct ConfigState {
 }
 
 impl Container {
-    pub fn new(config: Config) -> Container {
+    pub fn new(config_path: String, config: Config) -> Container {
         let app_name_to_id = config.applications.iter()
             .enumerate()
             .map(|(i, app)| (app.name.clone(), i))
@@ -36,10 +50,17 @@ impl Container {
 
         Container {
             inner: Arc::new(ContainerImpl {
+                config_path: config_path,
                 config_state: RwLock::new(ConfigState {
                     config: config,
                     app_name_to_id: app_name_to_id
                 }),
+                resolver: Resolver::new(),
+                io_pool: CpuPoolBuilder::new()
+                    .pool_size(IO_THREADS)
+                    .name_prefix("io-")
+                    .create(),
+                app_logs: LogBuffer::new(),
                 control_dispatcher: Mutex::new(None)
             })
         }
@@ -0,0 +1,125 @@
+use std::io;
+use std::net::{SocketAddr, IpAddr, ToSocketAddrs};
+
+use futures;
+use futures::Future;
+use futures_cpupool::{CpuPool, Builder as CpuPoolBuilder};
+
+const RESOLVER_THREADS: usize = 4;
+
+/// Resolves host names on a thread pool, since the system resolver
+/// (which also honors `/etc/hosts`) is blocking.
+#[derive(Clone)]
+pub struct Resolver {
+    pool: CpuPool
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            pool: CpuPoolBuilder::new()
+                .pool_size(RESOLVER_THREADS)
+                .name_prefix("dns-")
+                .create()
+        }
+    }
+
+    /// Resolves an address in the format `host:port`.
+    ///
+    /// Socket address literals (including `[ipv6]:port`) are returned
+    /// without a lookup.
+    pub fn resolve_addr(&self, addr: &str) -> Box<Future<Item = Vec<SocketAddr>, Error = io::Error> + Send> {
+        if let Ok(v) = addr.parse::<SocketAddr>() {
+            return Box::new(futures::future::ok(vec! [ v ]));
+        }
+
+        let (host, port) = match split_host_port(addr) {
+            Some(v) => v,
+            None => return Box::new(futures::future::err(
+                io::Error::new(io::ErrorKind::InvalidInput, "invalid address")
+            ))
+        };
+
+        self.resolve(&host, port)
+    }
+
+    /// Resolves `host` and pairs each of its addresses with `port`.
+    pub fn resolve(&self, host: &str, port: u16) -> Box<Future<Item = Vec<SocketAddr>, Error = io::Error> + Send> {
+        if let Ok(v) = host.parse::<IpAddr>() {
+            return Box::new(futures::future::ok(vec! [ SocketAddr::new(v, port) ]));
+        }
+
+        Box::new(self.lookup(host.to_string(), port))
+    }
+
+    /// Resolves a host name to all of its IP addresses.
+    pub fn resolve_host(&self, host: &str) -> Box<Future<Item = Vec<IpAddr>, Error = io::Error> + Send> {
+        let host = host.trim_left_matches('[').trim_right_matches(']');
+
+        if let Ok(v) = host.parse::<IpAddr>() {
+            return Box::new(futures::future::ok(vec! [ v ]));
+        }
+
+        Box::new(self.lookup(host.to_string(), 0).map(|addrs| {
+            let mut ips: Vec<IpAddr> = Vec::new();
+            for addr in addrs {
+                if !ips.contains(&addr.ip()) {
+                    ips.push(addr.ip());
+                }
+            }
+            ips
+        }))
+    }
+
+    fn lookup(&self, host: String, port: u16) -> impl Future<Item = Vec<SocketAddr>, Error = io::Error> + Send {
+        self.pool.spawn_fn(move || -> io::Result<Vec<SocketAddr>> {
+            let addrs: Vec<SocketAddr> = (host.as_str(), port).to_socket_addrs()?.collect();
+            if addrs.is_empty() {
+                Err(io::Error::new(io::ErrorKind::NotFound, "host not found"))
+            } else {
+                Ok(addrs)
+            }
+        })
+    }
+}
+
+/// Splits `host:port` (or `[host]:port`) into its parts.
+pub fn split_host_port(addr: &str) -> Option<(String, u16)> {
+    let sep = addr.rfind(':')?;
+    let host = &addr[..sep];
+    let port: u16 = addr[sep + 1..].parse().ok()?;
+
+    let host = if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else {
+        host
+    };
+    if host.is_empty() {
+        return None;
+    }
+
+    Some((host.to_string(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("example.com:80"), Some(("example.com".to_string(), 80)));
+        assert_eq!(split_host_port("127.0.0.1:8080"), Some(("127.0.0.1".to_string(), 8080)));
+        assert_eq!(split_host_port("[::1]:443"), Some(("::1".to_string(), 443)));
+    }
+
+    #[test]
+    fn test_split_host_port_invalid() {
+        assert_eq!(split_host_port("example.com"), None);
+        assert_eq!(split_host_port("example.com:"), None);
+        assert_eq!(split_host_port("example.com:http"), None);
+        assert_eq!(split_host_port("example.com:65536"), None);
+        assert_eq!(split_host_port(":80"), None);
+        assert_eq!(split_host_port("[]:80"), None);
+        assert_eq!(split_host_port("[::1]"), None);
+    }
+}
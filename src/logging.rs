@@ -3,6 +3,12 @@ use ansi_term::Style;
 use ansi_term::Colour::*;
 use chrono;
 
+use std::sync::Mutex;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Number of lines kept per application by `LogBuffer`.
+const LOG_BUFFER_LINES: usize = 1000;
+
 #[derive(Clone)]
 pub struct Logger<'a> {
     module_name: &'a str
@@ -35,6 +41,44 @@ impl<'a> Logger<'a> {
     }
 }
 
+/// Keeps the most recent log lines of each application so that they can be
+/// fetched through the management API.
+pub struct LogBuffer {
+    lines: Mutex<BTreeMap<String, VecDeque<String>>>
+}
+
+impl LogBuffer {
+    pub fn new() -> LogBuffer {
+        LogBuffer {
+            lines: Mutex::new(BTreeMap::new())
+        }
+    }
+
+    pub fn push(&self, source: &str, level: Level, text: &str) {
+        let local_time: chrono::DateTime<chrono::Local> = chrono::Local::now();
+        let line = format!(
+            "{} [{}] {}",
+            local_time.format("%a %b %e %T %Y"),
+            level.name(),
+            text
+        );
+
+        let mut lines = self.lines.lock().unwrap();
+        let lines = lines.entry(source.to_string()).or_insert_with(VecDeque::new);
+        if lines.len() == LOG_BUFFER_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub fn recent(&self, source: &str) -> Vec<String> {
+        self.lines.lock().unwrap()
+            .get(source)
+            .map(|v| v.iter().cloned().collect())
+            .unwrap_or_else(|| Vec::new())
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Level {
     Info,
@@ -42,6 +86,16 @@ pub enum Level {
     Error
 }
 
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Info => "INFO",
+            Level::Warning => "WARNING",
+            Level::Error => "ERROR"
+        }
+    }
+}
+
 macro_rules! logger {
     ($name:expr) => (::logging::Logger::new($name))
 }
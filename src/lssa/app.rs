@@ -1,20 +1,35 @@
 use std::rc::Rc;
 use std::cell::{Cell, RefCell};
 use std::ops::Deref;
-use std::time::SystemTime;
+use std::fmt;
+use std::time::{Duration, SystemTime};
 use std::collections::BTreeMap;
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 
 use chrono;
+use futures::Future;
+use futures::future::Shared;
+use futures::unsync::oneshot;
+use tokio;
 
 use wasm_core::jit::compiler::{Compiler, ExecutionContext};
 use wasm_core::jit::runtime::RuntimeConfig;
-use wasm_core::module::Module;
+use wasm_core::module::{Module, DataSegment};
 use container::Container;
+use dns::Resolver;
+use tls::AppTls;
+use futures_cpupool::CpuPool;
 
 use super::resolver::{RcLssaResolver, LssaResolver, NullResolver};
 use super::stats::AppStats;
-use super::namespace::Migration;
-use config::AppPermission;
+use super::namespace::{Namespace, Migration};
+use super::control::Control;
+use super::watchdog;
+use super::host_memory::{HostMemory, Reservation};
+use super::limits::{Resources, Resource, Slot};
+use super::error::ErrorCode;
+use config::{AppPermission, MountConfig};
 
 // `inner` is intended to be used internally only and this should NOT be `Clone`.
 pub struct Application {
@@ -34,13 +49,89 @@ pub struct ApplicationImpl {
 
     start_time: SystemTime,
     resolvers: RefCell<BTreeMap<String, RcLssaResolver>>,
+    tls: AppTls,
 
     invoke0_fn: extern "C" fn (i64) -> i64,
     invoke1_fn: extern "C" fn (i64, i64) -> i64,
     invoke2_fn: extern "C" fn (i64, i64, i64) -> i64,
     invoke3_fn: extern "C" fn (i64, i64, i64, i64) -> i64,
     invoke4_fn: extern "C" fn (i64, i64, i64, i64, i64) -> i64,
-    pub(super) container: Container
+    pub(super) container: Container,
+    crash: CrashHandle,
+
+    // Highest size of the linear memory seen after a call into the application.
+    memory_peak: Cell<usize>,
+    host_memory: HostMemory,
+    resources: Resources,
+
+    // Resolves when the application is dropped, see `spawn`.
+    terminated: Shared<oneshot::Receiver<()>>,
+    _terminate: oneshot::Sender<()>
+}
+
+/// Contains panics, including traps, raised while running the application.
+#[derive(Clone)]
+struct CrashHandle {
+    name: String,
+    crashed: Rc<Cell<bool>>,
+
+    // Whether the crash was an interrupt by the watchdog.
+    timed_out: Rc<Cell<bool>>,
+    container: Container
+}
+
+/// The function of the application a call went into.
+#[derive(Copy, Clone, Debug)]
+enum EntryPoint<'a> {
+    Callback(i32),
+    Export(&'a str)
+}
+
+impl<'a> fmt::Display for EntryPoint<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EntryPoint::Callback(target) => write!(f, "callback {}", target),
+            EntryPoint::Export(name) => write!(f, "{}", name)
+        }
+    }
+}
+
+impl CrashHandle {
+    /// Runs `f`, which calls into the application at `entry`, returning
+    /// `None` if it panics or if the application has already crashed.
+    fn guard<T, F: FnOnce() -> T>(&self, entry: EntryPoint, f: F) -> Option<T> {
+        if self.crashed.get() {
+            return None;
+        }
+        match catch_unwind(AssertUnwindSafe(f)) {
+            Ok(v) => Some(v),
+            Err(payload) => {
+                if watchdog::take_interrupted() {
+                    derror!(logger!(&self.name), "{} exceeded max_callback_ms", entry);
+                    self.timed_out.set(true);
+                }
+                self.report(payload);
+                None
+            }
+        }
+    }
+
+    /// Marks the application as crashed and lets the manager release it.
+    fn report(&self, payload: Box<Any + Send>) {
+        let reason = payload.downcast_ref::<&str>().map(|v| v.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown error".into());
+        derror!(logger!(&self.name), "Application crashed: {}", reason);
+
+        if self.crashed.replace(true) {
+            return;
+        }
+        if let Some(app_id) = self.container.lookup_app_id_by_name(&self.name) {
+            let _ = self.container.dispatch_control(Control::AppCrashed {
+                app_id: app_id
+            });
+        }
+    }
 }
 
 struct AppInsideHandle<'a> {
@@ -119,6 +210,26 @@ impl Application {
         ) };
 
         let name = config.name.clone();
+        let (terminate_tx, terminate_rx) = oneshot::channel();
+        let crash = CrashHandle {
+            name: name.clone(),
+            crashed: Rc::new(Cell::new(false)),
+            timed_out: Rc::new(Cell::new(false)),
+            container: container.clone()
+        };
+
+        let host_memory = HostMemory::new(config.memory.host_max);
+        let resources = Resources::new(&config.limits);
+
+        // Bad certificate files are reported once here, and again on each
+        // use by `connect_tls` or `listen_tls`.
+        let tls = AppTls::new(&config.tls);
+        if let (Some(_), Err(e)) = (config.tls.ca.as_ref(), tls.connector()) {
+            derror!(logger!(&name), "Unable to set up TLS connector: {}", e);
+        }
+        if let (true, Err(e)) = (config.tls.cert.is_some() || config.tls.key.is_some(), tls.acceptor()) {
+            derror!(logger!(&name), "Unable to set up TLS acceptor: {}", e);
+        }
 
         let app = Rc::new(ApplicationImpl {
             name: name,
@@ -130,12 +241,19 @@ impl Application {
             execution: vm,
             start_time: SystemTime::now(),
             resolvers: RefCell::new(BTreeMap::new()),
+            tls: tls,
             invoke0_fn: invoke0,
             invoke1_fn: invoke1,
             invoke2_fn: invoke2,
             invoke3_fn: invoke3,
             invoke4_fn: invoke4,
-            container: container
+            container: container,
+            crash: crash,
+            memory_peak: Cell::new(0),
+            host_memory: host_memory,
+            resources: resources,
+            terminated: terminate_rx.shared(),
+            _terminate: terminate_tx
         });
 
         let mut cwa_resolver = LssaResolver::new(Rc::downgrade(&app), "cwa", "", NullResolver::new());
@@ -156,23 +274,30 @@ impl Application {
         }
     }
 
-    pub fn initialize(&self, initializer_name: Option<&str>) {
+    pub fn initialize(&self, initializer_name: Option<&str>) -> Result<(), String> {
         let _inside = AppInsideHandle::new(self);
 
         let initializer_name = initializer_name.unwrap_or("__app_init");
 
         let entry_id = match self.module.lookup_exported_func(initializer_name) {
             Some(v) => v,
-            None => return
+            None => return Ok(())
         };
 
         let entry: extern "C" fn () -> i64 = unsafe {
             self.execution.get_function_checked(entry_id)
         };
 
-        let ret = self.execution.rt.protected_call(|| entry());
-        if ret != 0 {
-            panic!("initialize: Initializer reported failure");
+        // A failed initializer is reported by the caller, not as a crash.
+        match catch_unwind(AssertUnwindSafe(|| self.protected_call(|| entry()))) {
+            Ok(0) => Ok(()),
+            Ok(ret) => Err(format!("Initializer reported failure: {}", ret)),
+            Err(_) => if watchdog::take_interrupted() {
+                self.crash.timed_out.set(true);
+                Err(format!("{} exceeded max_callback_ms", initializer_name))
+            } else {
+                Err("Initializer crashed".into())
+            }
         }
     }
 
@@ -181,9 +306,16 @@ impl Application {
         let diff: chrono::Duration = chrono::Duration::from_std(
             SystemTime::now().duration_since(self.start_time).unwrap()
         ).unwrap();
+        self.update_memory_peak();
         AppStats {
             start_time: dt.timestamp_millis(),
-            running_time: diff.num_milliseconds()
+            running_time: diff.num_milliseconds(),
+            callback_timeouts: 0,
+            memory: self.linear_memory(),
+            memory_peak: self.memory_peak.get(),
+            host_memory: self.host_memory.used(),
+            resources: self.resources.usage(),
+            crashed: false
         }
     }
 }
@@ -202,40 +334,225 @@ pub struct ModuleMigration {
 }
 
 impl ApplicationImpl {
-    pub fn check_permission(&self, perm: &AppPermission) -> Result<(), ()> {
+    /// Runs `f` with the current config of the application, which may have
+    /// changed since it was started.
+    fn with_current_config<R, F: FnOnce(&AppConfig) -> R>(&self, f: F) -> R {
         let id = self.container.lookup_app_id_by_name(&self.name).unwrap();
 
         let cs = self.container.config_state.read().unwrap();
-        let app_config = &cs.config.applications[id];
+        f(&cs.config.applications[id])
+    }
 
-        if !app_config.metadata.permissions.contains(perm) {
+    pub fn check_permission(&self, perm: &AppPermission) -> Result<(), ()> {
+        if !self.with_current_config(|c| c.metadata.permissions.contains(perm)) {
             Err(())
         } else {
             Ok(())
         }
     }
 
+    /// Returns whether any granted permission satisfies `f`.
+    pub fn has_permission_matching<F: Fn(&AppPermission) -> bool>(&self, f: F) -> bool {
+        self.with_current_config(|c| c.metadata.permissions.iter().any(f))
+    }
+
+    pub fn env_var(&self, key: &str) -> Option<String> {
+        self.with_current_config(|c| c.env.get(key).cloned())
+    }
+
+    pub fn mounts(&self) -> Vec<MountConfig> {
+        self.with_current_config(|c| c.mounts.clone())
+    }
+
+    /// Spawns a task on behalf of the application.
+    ///
+    /// The task is dropped along with the application, closing the sockets
+    /// and discarding the callbacks it holds. A panic in the task crashes
+    /// the application rather than the manager.
+    pub fn spawn<F: Future<Item = (), Error = ()> + 'static>(&self, f: F) {
+        let crash = self.crash.clone();
+
+        // `terminated` is polled first so that a task never calls into
+        // an application that is gone.
+        tokio::executor::current_thread::spawn(
+            self.terminated.clone().then(|_| Ok(())).select(
+                AssertUnwindSafe(f).catch_unwind().then(move |result| -> Result<(), ()> {
+                    if let Err(payload) = result {
+                        crash.report(payload);
+                    }
+                    Ok(())
+                })
+            ).then(|_| Ok(()))
+        );
+    }
+
+    /// Whether a call into the application panicked or trapped.
+    pub fn has_crashed(&self) -> bool {
+        self.crash.crashed.get()
+    }
+
+    /// Whether a call into the application was interrupted for running
+    /// longer than `max_callback_ms`.
+    pub fn has_timed_out(&self) -> bool {
+        self.crash.timed_out.get()
+    }
+
+    /// Runs `f`, which calls into the application, turning faults into panics
+    /// and interrupting it after `max_callback_ms`.
+    fn protected_call<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let limit = self.config.max_callback_ms.map(Duration::from_millis);
+        let ret = self.execution.rt.protected_call(|| watchdog::watch(limit, f));
+        self.update_memory_peak();
+        ret
+    }
+
+    fn update_memory_peak(&self) {
+        let len = self.linear_memory();
+        if len > self.memory_peak.get() {
+            self.memory_peak.set(len);
+        }
+    }
+
+    /// Size of the linear memory in bytes.
+    fn linear_memory(&self) -> usize {
+        unsafe { &*self.execution.rt.get_memory() }.len()
+    }
+
+    /// Memory held by namespaces on behalf of the application.
+    pub fn host_memory(&self) -> &HostMemory {
+        &self.host_memory
+    }
+
+    /// Handles, connections and callbacks held by namespaces on behalf of
+    /// the application.
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+
+    /// Takes one unit of `resource`, logging when the limit is reached.
+    pub fn acquire(&self, resource: Resource) -> Result<Slot, ErrorCode> {
+        self.resources.acquire(resource).map_err(|code| {
+            dwarning!(logger!(&self.name), "Limit of {:?} reached", resource);
+            code
+        })
+    }
+
+    /// Reserves room for a buffer of up to `len` bytes, counting it
+    /// against both `memory.host_max` and `limits.buffers`.
+    pub fn reserve_buffer(&self, len: usize) -> Result<Reservation, ErrorCode> {
+        let slot = self.acquire(Resource::Buffers)?;
+        self.host_memory.reserve(len, slot)
+    }
+
+    /// Like `spawn`, but counts the task as a pending callback until it completes.
+    pub fn spawn_callback<F: Future<Item = (), Error = ()> + 'static>(&self, slot: Slot, f: F) {
+        self.spawn(f.then(move |r| {
+            drop(slot);
+            r
+        }));
+    }
+
+    pub fn tls(&self) -> &AppTls {
+        &self.tls
+    }
+
+    pub fn resolver(&self) -> &Resolver {
+        &self.container.resolver
+    }
+
+    pub fn io_pool(&self) -> &CpuPool {
+        &self.container.io_pool
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     #[allow(dead_code)]
     pub fn id(&self) -> usize {
         self.container.lookup_app_id_by_name(&self.name).unwrap()
     }
 
-    pub fn start_migration(&self) -> AppMigration {
+    /// Runs `f` with the namespace `prefix` of the resolver for `module` ("ice" or "cwa").
+    pub fn with_namespace<T: Namespace, R, F: FnOnce(&T) -> R>(
+        &self,
+        module: &str,
+        prefix: &str,
+        f: F
+    ) -> Option<R> {
+        let resolvers = self.resolvers.borrow();
+        let ns = resolvers.get(module)?.inner.get_namespaces().get(prefix)?;
+        ns.as_any().downcast_ref::<T>().map(f)
+    }
+
+    /// Calls an exported function of type `() -> i32` by name.
+    ///
+    /// Returns `None` if the function is not exported, and -1 if the
+    /// application crashes.
+    pub fn call_exported0(&self, name: &str) -> Option<i32> {
+        let _inside = AppInsideHandle::new(self);
+
+        let entry_id = self.module.lookup_exported_func(name)?;
+        let entry: extern "C" fn () -> i64 = unsafe {
+            self.execution.get_function_checked(entry_id)
+        };
+
+        Some(self.crash.guard(EntryPoint::Export(name), || self.protected_call(|| {
+            entry() as _
+        })).unwrap_or(-1))
+    }
+
+    pub fn has_export(&self, name: &str) -> bool {
+        self.module.lookup_exported_func(name).is_some()
+    }
+
+    /// Calls an exported function of type `(i32) -> i32` by name.
+    ///
+    /// Returns `None` if the function is not exported, and -1 if the
+    /// application crashes.
+    pub fn call_exported1(&self, name: &str, arg1: i32) -> Option<i32> {
+        let _inside = AppInsideHandle::new(self);
+
+        let entry_id = self.module.lookup_exported_func(name)?;
+        let entry: extern "C" fn (i64) -> i64 = unsafe {
+            self.execution.get_function_checked(entry_id)
+        };
+
+        Some(self.crash.guard(EntryPoint::Export(name), || self.protected_call(|| {
+            entry((arg1 as u32) as _) as _
+        })).unwrap_or(-1))
+    }
+
+    /// Takes the state of the application out of its namespaces.
+    ///
+    /// If a namespace refuses to migrate, the namespaces that were already
+    /// taken out get their state back and an error is returned.
+    pub fn start_migration(&self) -> Result<AppMigration, String> {
         let resolvers = self.resolvers.borrow();
         let mut mig = AppMigration::default();
         for (k, r) in &*resolvers {
             let mut mm = ModuleMigration::default();
             for (name, ns) in r.inner.get_namespaces() {
-                mm.namespaces.insert(
-                    name.clone(),
-                    ns.start_migration().unwrap_or_else(|| {
-                        panic!("Unable to migrate namespace {}", name)
-                    })
-                );
+                match ns.start_migration() {
+                    Some(v) => {
+                        mm.namespaces.insert(name.clone(), v);
+                    },
+                    None => {
+                        mig.modules.insert(k.clone(), mm);
+                        self.restore_namespaces(&mig);
+                        return Err(format!("Unable to migrate namespace {}", name));
+                    }
+                }
             }
             mig.modules.insert(k.clone(), mm);
         }
 
+        self.capture_execution(&mut mig);
+        Ok(mig)
+    }
+
+    /// Copies the memory and globals of the application into `mig`.
+    fn capture_execution(&self, mig: &mut AppMigration) {
         let rt = &self.execution.rt;
         mig.memory = unsafe { &*rt.get_memory() }.to_vec();
         mig.globals = unsafe {
@@ -245,52 +562,160 @@ impl ApplicationImpl {
             )
         }.to_vec();
         mig.code_sha256 = self.code_sha256;
+    }
 
-        mig
+    /// Resumes the application with the state taken out by `start_migration`.
+    pub fn abort_migration(&self, mig: &AppMigration) {
+        self.restore_namespaces(mig);
     }
 
-    pub fn complete_migration(&self, mig: &AppMigration) {
-        if mig.code_sha256 != self.code_sha256 {
-            panic!("Checksum mismatch");
+    /// Captures the state of the application without stopping it or
+    /// disturbing its pending operations.
+    ///
+    /// Those operations fail when the application is restored from the
+    /// checkpoint, as they would after a migration.
+    pub fn checkpoint(&self) -> Result<AppMigration, String> {
+        let resolvers = self.resolvers.borrow();
+        let mut mig = AppMigration::default();
+        for (k, r) in &*resolvers {
+            let mut mm = ModuleMigration::default();
+            for (name, ns) in r.inner.get_namespaces() {
+                match ns.snapshot() {
+                    Some(v) => {
+                        mm.namespaces.insert(name.clone(), v);
+                    },
+                    None => return Err(format!("Unable to checkpoint namespace {}", name))
+                }
+            }
+            mig.modules.insert(k.clone(), mm);
         }
 
-        let rt = &self.execution.rt;
-        let mem_len = unsafe { &*rt.get_memory() }.len();
-        if mem_len < mig.memory.len() {
-            rt.grow_memory(mig.memory.len() - mem_len);
+        self.capture_execution(&mut mig);
+        Ok(mig)
+    }
+
+    /// Gives namespaces back the state in `mig`, skipping those without any.
+    fn restore_namespaces(&self, mig: &AppMigration) {
+        let resolvers = self.resolvers.borrow();
+        for (k, r) in &*resolvers {
+            let mm = match mig.modules.get(k) {
+                Some(v) => v,
+                None => continue
+            };
+            for (name, ns) in r.inner.get_namespaces() {
+                if let Some(ns_data) = mm.namespaces.get(name) {
+                    ns.cancel_migration(ns_data);
+                }
+            }
         }
-        let mem = unsafe { &mut *rt.get_memory_mut() };
-        mem[0..mig.memory.len()].copy_from_slice(&mig.memory);
+    }
 
-        if rt.source_module.globals.len() != mig.globals.len() {
-            panic!("Global len mismatch");
+    pub fn complete_migration(&self, mig: &AppMigration) -> Result<(), String> {
+        if mig.code_sha256 != self.code_sha256 {
+            return Err("Checksum mismatch".into());
         }
 
+        self.restore_state(mig)
+    }
+
+    /// Takes over the memory and globals of an older version of the application,
+    /// then lets `__app_upgrade(old_version)` adapt them.
+    ///
+    /// The data segments of the new code are written again over the old memory,
+    /// so constants and statics are those of the new version. Globals are carried
+    /// over by index as far as both versions have them; globals that only the new
+    /// version has keep their initial values.
+    ///
+    /// Namespace state is left behind: its callbacks point into the old code,
+    /// so timers, pending operations and open handles of the old version are dropped.
+    pub fn complete_upgrade(&self, mig: &AppMigration, old_version: i32) -> Result<(), String> {
+        let rt = &self.execution.rt;
+        self.restore_memory(&mig.memory)?;
+        apply_data_segments(
+            unsafe { &mut *rt.get_memory_mut() },
+            &rt.source_module.data_segments
+        )?;
+
         let globals = unsafe { ::std::slice::from_raw_parts_mut(
             (&*rt.get_jit_info()).global_begin,
             rt.source_module.globals.len()
         ) };
-        globals.copy_from_slice(&mig.globals);
+        let n = ::std::cmp::min(globals.len(), mig.globals.len());
+        globals[..n].copy_from_slice(&mig.globals[..n]);
 
+        match self.call_exported1("__app_upgrade", old_version) {
+            Some(0) => Ok(()),
+            Some(code) => Err(format!("__app_upgrade reported failure: {}", code)),
+            None => Err("__app_upgrade is not exported".into())
+        }
+    }
+
+    fn restore_state(&self, mig: &AppMigration) -> Result<(), String> {
+        // Everything is checked before anything is restored.
         let resolvers = self.resolvers.borrow();
+        let mut namespaces = Vec::new();
         for (k, r) in &*resolvers {
-            let mm = mig.modules.get(k).unwrap_or_else(|| {
-                panic!("Migration data not found for module {}", k);
-            });
+            let mm = mig.modules.get(k)
+                .ok_or_else(|| format!("Migration data not found for module {}", k))?;
             for (name, ns) in r.inner.get_namespaces() {
-                let ns_data = mm.namespaces.get(name).unwrap_or_else(|| {
-                    panic!("Migration data not found for namespace {}", name);
-                });
-                ns.complete_migration(ns_data);
+                let ns_data = mm.namespaces.get(name)
+                    .ok_or_else(|| format!("Migration data not found for namespace {}", name))?;
+                namespaces.push((ns, ns_data));
             }
         }
+
+        self.restore_execution(mig)?;
+
+        for (ns, ns_data) in namespaces {
+            ns.complete_migration(ns_data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Copies the memory and globals in `mig` into the application, failing
+    /// before touching anything if they don't fit.
+    fn restore_execution(&self, mig: &AppMigration) -> Result<(), String> {
+        let rt = &self.execution.rt;
+        if rt.source_module.globals.len() != mig.globals.len() {
+            return Err("Global len mismatch".into());
+        }
+        self.restore_memory(&mig.memory)?;
+
+        let globals = unsafe { ::std::slice::from_raw_parts_mut(
+            (&*rt.get_jit_info()).global_begin,
+            rt.source_module.globals.len()
+        ) };
+        globals.copy_from_slice(&mig.globals);
+
+        Ok(())
+    }
+
+    /// Copies `memory` over the start of the linear memory, growing it if needed.
+    fn restore_memory(&self, memory: &[u8]) -> Result<(), String> {
+        let rt = &self.execution.rt;
+        if memory.len() > self.config.memory.max {
+            return Err(format!(
+                "Memory of the migration ({} bytes) exceeds memory.max",
+                memory.len()
+            ));
+        }
+
+        let mem_len = unsafe { &*rt.get_memory() }.len();
+        if mem_len < memory.len() {
+            rt.grow_memory(memory.len() - mem_len);
+        }
+        let mem = unsafe { &mut *rt.get_memory_mut() };
+        mem[0..memory.len()].copy_from_slice(memory);
+
+        Ok(())
     }
 
     #[allow(dead_code)]
     pub fn invoke0(&self, target: i32) -> i32 {
-        self.execution.rt.protected_call(|| {
+        self.crash.guard(EntryPoint::Callback(target), || self.protected_call(|| {
             (self.invoke0_fn)((target as u32) as _) as _
-        })
+        })).unwrap_or(-1)
     }
 
     #[allow(dead_code)]
@@ -299,12 +724,12 @@ impl ApplicationImpl {
         target: i32,
         arg1: i32
     ) -> i32 {
-        self.execution.rt.protected_call(|| {
+        self.crash.guard(EntryPoint::Callback(target), || self.protected_call(|| {
             (self.invoke1_fn)(
                 (target as u32) as _,
                 (arg1 as u32) as _
             ) as _
-        })
+        })).unwrap_or(-1)
     }
 
     #[allow(dead_code)]
@@ -314,13 +739,13 @@ impl ApplicationImpl {
         arg1: i32,
         arg2: i32
     ) -> i32 {
-        self.execution.rt.protected_call(|| {
+        self.crash.guard(EntryPoint::Callback(target), || self.protected_call(|| {
             (self.invoke2_fn)(
                 (target as u32) as _,
                 (arg1 as u32) as _,
                 (arg2 as u32) as _
             ) as _
-        })
+        })).unwrap_or(-1)
     }
 
     #[allow(dead_code)]
@@ -331,14 +756,14 @@ impl ApplicationImpl {
         arg2: i32,
         arg3: i32
     ) -> i32 {
-        self.execution.rt.protected_call(|| {
+        self.crash.guard(EntryPoint::Callback(target), || self.protected_call(|| {
             (self.invoke3_fn)(
                 (target as u32) as _,
                 (arg1 as u32) as _,
                 (arg2 as u32) as _,
                 (arg3 as u32) as _
             ) as _
-        })
+        })).unwrap_or(-1)
     }
 
     #[allow(dead_code)]
@@ -350,7 +775,7 @@ impl ApplicationImpl {
         arg3: i32,
         arg4: i32
     ) -> i32 {
-        self.execution.rt.protected_call(|| {
+        self.crash.guard(EntryPoint::Callback(target), || self.protected_call(|| {
             (self.invoke4_fn)(
                 (target as u32) as _,
                 (arg1 as u32) as _,
@@ -358,6 +783,53 @@ impl ApplicationImpl {
                 (arg3 as u32) as _,
                 (arg4 as u32) as _
             ) as _
-        })
+        })).unwrap_or(-1)
+    }
+}
+
+/// Writes the data segments of a module into `mem`, as instantiating it does.
+fn apply_data_segments(mem: &mut [u8], segments: &[DataSegment]) -> Result<(), String> {
+    for seg in segments {
+        let begin = seg.offset as usize;
+        match mem.get_mut(begin..begin + seg.data.len()) {
+            Some(target) => target.copy_from_slice(&seg.data),
+            None => return Err(format!("Data segment at {} out of bounds", begin))
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(offset: u32, data: &[u8]) -> DataSegment {
+        DataSegment {
+            offset: offset,
+            data: data.to_vec()
+        }
+    }
+
+    #[test]
+    fn test_upgrade_data_segments() {
+        // Memory of the old version: its constant at 8, then heap data at 16.
+        let old = vec![
+            segment(8, b"old!")
+        ];
+        let mut mem = vec![0u8; 32];
+        apply_data_segments(&mut mem, &old).unwrap();
+        mem[16..20].copy_from_slice(b"heap");
+
+        // The new version moves its constant and adds a static.
+        let new = vec![
+            segment(4, b"new const"),
+            segment(24, b"st")
+        ];
+        apply_data_segments(&mut mem, &new).unwrap();
+        assert_eq!(&mem[4..13], b"new const");
+        assert_eq!(&mem[16..20], b"heap");
+        assert_eq!(&mem[24..26], b"st");
+
+        assert!(apply_data_segments(&mut mem, &[segment(30, b"too long")]).is_err());
     }
 }
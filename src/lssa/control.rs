@@ -1,12 +1,56 @@
 use super::event::EventInfo;
 use super::stats::StatsRequest;
-use super::app::AppMigration;
+use super::app::{AppMigration, AppConfig};
+use super::manager::{AppStatus, ReloadReport};
+use config::Config;
 use futures::sync::mpsc::Sender;
 
 #[allow(dead_code)]
 pub enum Control {
     Event(EventInfo),
     Stats(StatsRequest),
-    ActivateMigration { app_id: usize, migration: AppMigration },
-    MigrateAway { app_id: usize, sender: Sender<AppMigration> }
+    ListApps { sender: Sender<Vec<AppStatus>> },
+    ActivateMigration {
+        app_id: usize,
+        migration: AppMigration,
+        sender: Sender<Result<(), String>>
+    },
+    MigrateAway { app_id: usize, sender: Sender<Result<AppMigration, String>> },
+
+    /// Takes the state out of an application that is about to move to another host.
+    PrepareMigration { app_id: usize, sender: Sender<Result<AppMigration, String>> },
+
+    /// Stops an application after `PrepareMigration`, or resumes it with
+    /// its state if the migration failed.
+    FinishMigration { app_id: usize, migration: Option<AppMigration> },
+
+    /// Writes a snapshot of a running application to its `restore_from` file.
+    Checkpoint { app_id: usize, sender: Option<Sender<Result<(), String>>> },
+
+    /// Replaces the code of a running application, see `AppManager::upgrade`.
+    Upgrade { app_id: usize, code: Vec<u8>, sender: Sender<Result<(), String>> },
+
+    /// Applies a re-read config file, see `AppManager::reload`.
+    Reload { config: Config, sender: Sender<ReloadReport> },
+
+    /// Adds an application to the app set and starts it unless it is
+    /// `deferred`. Replies with its id.
+    LoadApp { config: AppConfig, sender: Sender<Result<usize, String>> },
+
+    /// Removes an application from the app set, tearing down everything it holds.
+    UnloadApp { app_id: usize, sender: Sender<Result<(), String>> },
+
+    /// Sent by an application that panicked or trapped.
+    AppCrashed { app_id: usize },
+
+    /// Restarts a crashed application, see `AppManager::crashed`.
+    ///
+    /// Ignored unless `crash` is still the latest crash of the application.
+    RestartApp { app_id: usize, crash: u64 },
+
+    /// Initializes an application that is not running, e.g. a `deferred` one.
+    StartApp { app_id: usize, sender: Sender<Result<(), String>> },
+
+    /// Stops a running application, discarding its state.
+    StopApp { app_id: usize, sender: Sender<Result<(), String>> }
 }
@@ -1,4 +1,4 @@
-use super::super::namespace::InvokeContext;
+use super::super::namespace::{InvokeContext, InvokeResult};
 use wasm_core::value::Value;
 use super::ErrorCode;
 
@@ -12,15 +12,15 @@ decl_namespace!(
 pub struct EnvImpl;
 
 impl EnvImpl {
-    pub fn get(&self, mut ctx: InvokeContext) -> Option<Value> {
-        let key = ctx.extract_str(0, 1);
-        let app = ctx.app.upgrade().unwrap();
+    pub fn get(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let val = {
+            let key = ctx.extract_str(0, 1)?;
+            ctx.app.upgrade().unwrap().env_var(key)
+        };
 
-        let val = app.config.env.get(key);
-
-        Some(match val {
+        Ok(Some(match val {
             Some(v) => {
-                let buf = ctx.extract_bytes_mut(2, 3);
+                let buf = ctx.extract_bytes_mut(2, 3)?;
                 let bytes = v.as_bytes();
 
                 if bytes.len() > buf.len() {
@@ -33,6 +33,6 @@ impl EnvImpl {
                 }
             },
             None => Value::I32(ErrorCode::NotFoundError.into())
-        })
+        }))
     }
 }
@@ -1,5 +1,4 @@
-use super::super::namespace::InvokeContext;
-use wasm_core::value::Value;
+use super::super::namespace::{InvokeContext, InvokeResult};
 
 decl_namespace!(
     LogNs,
@@ -11,11 +10,11 @@ decl_namespace!(
 pub struct LogImpl;
 
 impl LogImpl {
-    pub fn write(&self, ctx: InvokeContext) -> Option<Value> {
+    pub fn write(&self, ctx: InvokeContext) -> InvokeResult {
         let app = ctx.app.upgrade().unwrap();
 
-        let level = ctx.args[0].get_i32().unwrap();
-        let text = ctx.extract_str(1, 2);
+        let level = ctx.arg_i32(0)?;
+        let text = ctx.extract_str(1, 2)?;
 
         use logging::Level;
 
@@ -29,6 +28,6 @@ impl LogImpl {
         let logger = ::logging::Logger::new(&app.name);
         logger.log(level, text);
 
-        None
+        Ok(None)
     }
 }
@@ -1,4 +1,4 @@
-use super::super::namespace::InvokeContext;
+use super::super::namespace::{InvokeContext, InvokeResult};
 use wasm_core::value::Value;
 
 decl_namespace!(
@@ -13,23 +13,23 @@ decl_namespace!(
 pub struct RuntimeImpl;
 
 impl RuntimeImpl {
-    pub fn spec_major(&self, _: InvokeContext) -> Option<Value> {
-        Some(Value::I32(super::MAJOR_VERSION))
+    pub fn spec_major(&self, _: InvokeContext) -> InvokeResult {
+        Ok(Some(Value::I32(super::MAJOR_VERSION)))
     }
 
-    pub fn spec_minor(&self, _: InvokeContext) -> Option<Value> {
-        Some(Value::I32(super::MINOR_VERSION))
+    pub fn spec_minor(&self, _: InvokeContext) -> InvokeResult {
+        Ok(Some(Value::I32(super::MINOR_VERSION)))
     }
 
-    pub fn name(&self, mut ctx: InvokeContext) -> Option<Value> {
-        let out = ctx.extract_bytes_mut(0, 1);
+    pub fn name(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let out = ctx.extract_bytes_mut(0, 1)?;
         let name = "Ice".as_bytes();
 
         if out.len() < name.len() {
-            return Some(Value::I32(super::ErrorCode::InvalidArgumentError.into()));
+            return Ok(Some(Value::I32(super::ErrorCode::InvalidArgumentError.into())));
         }
 
         out[0..name.len()].copy_from_slice(name);
-        Some(Value::I32(name.len() as i32))
+        Ok(Some(Value::I32(name.len() as i32)))
     }
 }
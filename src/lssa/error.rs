@@ -15,7 +15,13 @@ pub enum ErrorCode {
     InvalidInput = 6,
     BindFail = 7,
 
-    NotFound = 8
+    NotFound = 8,
+
+    /// The host memory cap of the application has been reached.
+    OutOfMemory = 9,
+
+    /// A limit from the `limits` section of the application config has been reached.
+    ResourceExhausted = 10
 }
 
 impl ErrorCode {
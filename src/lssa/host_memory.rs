@@ -0,0 +1,133 @@
+//! Accounting of the memory the host holds on behalf of an application,
+//! such as received data waiting to be taken.
+
+use std::rc::Rc;
+use std::cell::Cell;
+use std::ops::Deref;
+
+use super::error::ErrorCode;
+use super::limits::Slot;
+
+#[derive(Clone)]
+pub struct HostMemory {
+    inner: Rc<HostMemoryImpl>
+}
+
+struct HostMemoryImpl {
+    used: Cell<usize>,
+    limit: usize
+}
+
+/// Room for a buffer that is being filled, e.g. by a pending read.
+///
+/// It also holds the `Buffers` slot of the buffer.
+pub struct Reservation {
+    memory: HostMemory,
+    len: usize,
+    _slot: Slot
+}
+
+/// A buffer that counts against the host memory of an application
+/// until it is dropped.
+pub struct HostBuffer {
+    data: Box<[u8]>,
+    _reservation: Reservation
+}
+
+impl HostMemory {
+    pub fn new(limit: usize) -> HostMemory {
+        HostMemory {
+            inner: Rc::new(HostMemoryImpl {
+                used: Cell::new(0),
+                limit: limit
+            })
+        }
+    }
+
+    /// Bytes currently held.
+    pub fn used(&self) -> usize {
+        self.inner.used.get()
+    }
+
+    /// Reserves `len` bytes, or as many as are left under the limit.
+    ///
+    /// Fails with `OutOfMemory` if nothing is left.
+    pub fn reserve(&self, len: usize, slot: Slot) -> Result<Reservation, ErrorCode> {
+        let used = self.inner.used.get();
+        let room = self.inner.limit.saturating_sub(used);
+        if room == 0 && len > 0 {
+            return Err(ErrorCode::OutOfMemory);
+        }
+        let len = ::std::cmp::min(len, room);
+
+        self.inner.used.set(used + len);
+        Ok(Reservation {
+            memory: self.clone(),
+            len: len,
+            _slot: slot
+        })
+    }
+
+    /// Accounts for `data` regardless of the limit, e.g. when restoring
+    /// buffers after a migration.
+    pub fn track(&self, data: Box<[u8]>, slot: Slot) -> HostBuffer {
+        self.inner.used.set(self.inner.used.get() + data.len());
+        HostBuffer {
+            _reservation: Reservation {
+                memory: self.clone(),
+                len: data.len(),
+                _slot: slot
+            },
+            data: data
+        }
+    }
+}
+
+impl Reservation {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Extends the reservation by `len` bytes, failing with `OutOfMemory`
+    /// unless all of them are left.
+    pub fn grow(&mut self, len: usize) -> Result<(), ErrorCode> {
+        let used = self.memory.inner.used.get();
+        if self.memory.inner.limit.saturating_sub(used) < len {
+            return Err(ErrorCode::OutOfMemory);
+        }
+
+        self.memory.inner.used.set(used + len);
+        self.len += len;
+        Ok(())
+    }
+
+    /// Turns the reservation into a buffer holding `data`, giving back
+    /// the bytes it doesn't need.
+    pub fn fill(mut self, data: Box<[u8]>) -> HostBuffer {
+        assert!(data.len() <= self.len);
+
+        let unused = self.len - data.len();
+        self.memory.inner.used.set(self.memory.inner.used.get() - unused);
+        self.len = data.len();
+
+        HostBuffer {
+            data: data,
+            _reservation: self
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let used = self.memory.inner.used.get();
+        self.memory.inner.used.set(used - self.len);
+    }
+}
+
+impl Deref for HostBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
@@ -0,0 +1,105 @@
+//! Per-application limits on open handles, connections and pending callbacks.
+
+use std::rc::Rc;
+use std::cell::Cell;
+
+use config::AppLimits;
+use super::error::ErrorCode;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Resource {
+    Files = 0,
+    TcpStreams = 1,
+    Listeners = 2,
+    PendingCallbacks = 3,
+
+    /// Received data waiting to be taken or released by the application.
+    Buffers = 4,
+    UdpSockets = 5
+}
+
+const N_RESOURCES: usize = 6;
+
+#[derive(Clone)]
+pub struct Resources {
+    inner: Rc<ResourcesImpl>
+}
+
+struct ResourcesImpl {
+    limits: [usize; N_RESOURCES],
+    used: [Cell<usize>; N_RESOURCES]
+}
+
+/// One unit of a resource, given back when dropped.
+pub struct Slot {
+    resources: Resources,
+    resource: Resource
+}
+
+/// Units of each resource in use.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ResourceUsage {
+    pub files: usize,
+    pub tcp_streams: usize,
+    pub listeners: usize,
+    pub pending_callbacks: usize,
+    pub buffers: usize,
+    pub udp_sockets: usize
+}
+
+impl Resources {
+    pub fn new(limits: &AppLimits) -> Resources {
+        Resources {
+            inner: Rc::new(ResourcesImpl {
+                limits: [
+                    limits.files,
+                    limits.tcp_streams,
+                    limits.listeners,
+                    limits.pending_callbacks,
+                    limits.buffers,
+                    limits.udp_sockets
+                ],
+                used: Default::default()
+            })
+        }
+    }
+
+    /// Takes one unit of `resource`, failing with `ResourceExhausted` at the limit.
+    pub fn acquire(&self, resource: Resource) -> Result<Slot, ErrorCode> {
+        let i = resource as usize;
+        if self.inner.used[i].get() >= self.inner.limits[i] {
+            return Err(ErrorCode::ResourceExhausted);
+        }
+        Ok(self.track(resource))
+    }
+
+    /// Takes one unit of `resource` regardless of the limit, e.g. when
+    /// restoring state after a migration.
+    pub fn track(&self, resource: Resource) -> Slot {
+        let used = &self.inner.used[resource as usize];
+        used.set(used.get() + 1);
+        Slot {
+            resources: self.clone(),
+            resource: resource
+        }
+    }
+
+    pub fn usage(&self) -> ResourceUsage {
+        let used = |r: Resource| self.inner.used[r as usize].get();
+        ResourceUsage {
+            files: used(Resource::Files),
+            tcp_streams: used(Resource::TcpStreams),
+            listeners: used(Resource::Listeners),
+            pending_callbacks: used(Resource::PendingCallbacks),
+            buffers: used(Resource::Buffers),
+            udp_sockets: used(Resource::UdpSockets)
+        }
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let used = &self.resources.inner.used[self.resource as usize];
+        used.set(used.get() - 1);
+    }
+}
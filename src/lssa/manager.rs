@@ -1,91 +1,388 @@
 use wasm_core::trans;
 use super::app::{Application, AppConfig, AppMigration};
 use container::Container;
+use config::{Config, RestartPolicy};
 use super::control::Control;
 use super::stats::{Stats, AppStats};
-use futures::Sink;
+use futures;
+use futures::{Future, Stream, Sink};
+use futures::unsync::oneshot;
 use sha2::Sha256;
+use bincode;
+use tokio;
 
-use std::collections::BTreeMap;
+use std::io;
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
 
 pub struct AppManager {
     container: Container,
-    apps: Vec<AppOrUninitialized>
+    apps: Vec<AppOrUninitialized>,
+
+    // Dropping a sender stops the periodic checkpoints of the application.
+    checkpoint_timers: BTreeMap<usize, oneshot::Sender<()>>,
+
+    // Restarts since the application last ran for `RESTART_RESET_SECS`.
+    restarts: BTreeMap<usize, u32>,
+
+    // Crashes of each application id, never reset. A scheduled restart carries
+    // the count it was scheduled for, so that it is dropped if the application
+    // was started by hand and crashed again in the meantime.
+    crashes: BTreeMap<usize, u64>,
+
+    // Calls interrupted by the watchdog since the application was loaded.
+    callback_timeouts: BTreeMap<usize, u64>
+}
+
+/// Delay before the first restart of a crashed application. It doubles
+/// with each consecutive restart, up to `MAX_RESTART_DELAY_SECS`.
+const RESTART_DELAY_SECS: u64 = 1;
+const MAX_RESTART_DELAY_SECS: u64 = 60;
+
+/// An application that runs this long before crashing starts over with
+/// a fresh restart count.
+const RESTART_RESET_SECS: i64 = 60;
+
+/// An entry of the `Control::ListApps` response.
+#[derive(Clone, Debug)]
+pub struct AppStatus {
+    pub id: usize,
+    pub name: String,
+    pub running: bool,
+    pub crashed: bool
+}
+
+/// What `AppManager::reload` did.
+#[derive(Clone, Debug, Default)]
+pub struct ReloadReport {
+    pub started: Vec<String>,
+    pub stopped: Vec<String>,
+
+    /// Applications whose `env`, `mounts` or permissions changed.
+    pub updated: Vec<String>,
+
+    /// Changes that take effect on the next restart, e.g. `hello_world: memory`.
+    pub restart_required: Vec<String>,
+    pub errors: Vec<String>
 }
 
 enum AppOrUninitialized {
     App(Application),
-    Uninitialized { code: Vec<u8>, config: AppConfig }
+
+    /// Its state has been taken out by `prepare_migration` and is on its
+    /// way to another host. Events are dropped until the migration either
+    /// completes or is aborted.
+    Migrating(Application),
+    Uninitialized { code: Vec<u8>, config: AppConfig },
+
+    /// Crashed and released. It is restarted according to its `restart` policy.
+    ///
+    /// `stats` are those of the application when it crashed.
+    Crashed { code: Vec<u8>, config: AppConfig, stats: AppStats },
+
+    /// Not loaded at all. The id stays reserved so that an application
+    /// loaded again under the same name gets it back.
+    Unloaded
+}
+
+fn code_sha256(code: &[u8]) -> [u8; 32] {
+    use sha2::Digest;
+
+    let mut hasher = Sha256::default();
+    hasher.input(code);
+    let mut out: [u8; 32] = [0; 32];
+    out.copy_from_slice(hasher.result().as_slice());
+    out
 }
 
 impl AppManager {
     pub fn new(container: Container) -> AppManager {
         AppManager {
             container: container,
-            apps: vec! []
+            apps: vec! [],
+            checkpoint_timers: BTreeMap::new(),
+            restarts: BTreeMap::new(),
+            crashes: BTreeMap::new(),
+            callback_timeouts: BTreeMap::new()
         }
     }
 
     fn add(&mut self, app_id: usize, app: AppOrUninitialized) {
-        assert_eq!(self.apps.len(), app_id);
-        self.apps.push(app);
+        while self.apps.len() <= app_id {
+            self.apps.push(AppOrUninitialized::Unloaded);
+        }
+        self.apps[app_id] = app;
     }
 
-    pub fn basic_activate(container: Container, code: &[u8], config: &AppConfig) -> Application {
-        use sha2::Digest;
+    fn is_loaded(&self, app_id: usize) -> bool {
+        match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::Unloaded) | None => false,
+            Some(_) => true
+        }
+    }
+
+    /// Loads an application that is not in the app set yet, or was unloaded.
+    ///
+    /// An application that was loaded before under the same name gets its
+    /// old id back. Returns the id of the application.
+    pub fn load_app(&mut self, config: AppConfig) -> Result<usize, String> {
+        let id = self.container.lookup_app_id_by_name(&config.name);
+        if let Some(id) = id {
+            if self.is_loaded(id) {
+                return Err(format!("Application {} is already loaded", config.name));
+            }
+        }
 
-        let mut hasher = Sha256::default();
-        hasher.input(code);
-        let mut code_sha256: [u8; 32] = [0; 32];
-        code_sha256.copy_from_slice(hasher.result().as_slice());
+        let code = Self::read_code(&config)
+            .map_err(|e| format!("Unable to load app `{}`: {:?}", config.name, e))?;
 
+        let app_id = {
+            let mut cs = self.container.config_state.write().unwrap();
+            match id {
+                Some(id) => {
+                    cs.config.applications[id] = config.clone();
+                    id
+                },
+                None => {
+                    let id = cs.config.applications.len();
+                    cs.config.applications.push(config.clone());
+                    cs.app_name_to_id.insert(config.name.clone(), id);
+                    id
+                }
+            }
+        };
+
+        // The config lock must not be held here since applications
+        // check their permissions while initializing.
+        self.load(&code, app_id, config)?;
+        Ok(app_id)
+    }
+
+    /// Drops an application along with its listeners, streams, file handles,
+    /// pending callbacks and memory.
+    pub fn unload(&mut self, app_id: usize) -> Result<(), String> {
+        if !self.is_loaded(app_id) {
+            return Err(format!("Application {} is not loaded", app_id));
+        }
+
+        self.checkpoint_timers.remove(&app_id);
+        self.restarts.remove(&app_id);
+        self.callback_timeouts.remove(&app_id);
+        self.apps[app_id] = AppOrUninitialized::Unloaded;
+
+        dinfo!(logger!("AppManager::unload"), "Application {} unloaded", app_id);
+        Ok(())
+    }
+
+    /// Reads the code of an application from `path`/`bin`.
+    pub fn read_code(config: &AppConfig) -> io::Result<Vec<u8>> {
+        use std::fs::File;
+        use std::io::Read;
+
+        let mut code: Vec<u8> = Vec::new();
+        File::open(::std::path::Path::new(&config.path).join(&config.metadata.bin))?
+            .read_to_end(&mut code)?;
+        Ok(code)
+    }
+
+    /// Applies a re-read config file to the running applications.
+    ///
+    /// New applications are started and removed ones are stopped. Changes
+    /// to `env`, `mounts` and permissions apply immediately; other changes
+    /// are only reported and don't apply until a restart.
+    pub fn reload(&mut self, new_config: Config) -> ReloadReport {
+        let logger = logger!("AppManager::reload");
+        let mut report = ReloadReport::default();
+
+        {
+            let cs = self.container.config_state.read().unwrap();
+            let old = &cs.config;
+            if old.services != new_config.services {
+                report.restart_required.push("services".into());
+            }
+            if old.management != new_config.management {
+                report.restart_required.push("management".into());
+            }
+            if old.migration != new_config.migration {
+                report.restart_required.push("migration".into());
+            }
+        }
+
+        let mut names = BTreeSet::new();
+
+        for app in new_config.applications {
+            names.insert(app.name.clone());
+
+            let id = self.container.lookup_app_id_by_name(&app.name);
+            if let Some(id) = id {
+                if self.is_loaded(id) {
+                    let mut cs = self.container.config_state.write().unwrap();
+                    let current = &mut cs.config.applications[id];
+
+                    let changes = current.restart_required_changes(&app);
+                    if !changes.is_empty() {
+                        report.restart_required.push(format!("{}: {}", app.name, changes.join(", ")));
+                    }
+
+                    if current.env != app.env
+                        || current.mounts != app.mounts
+                        || current.metadata.permissions != app.metadata.permissions
+                        || current.restart != app.restart
+                        || current.max_restarts != app.max_restarts {
+                        current.env = app.env;
+                        current.mounts = app.mounts;
+                        current.metadata.permissions = app.metadata.permissions;
+                        current.restart = app.restart;
+                        current.max_restarts = app.max_restarts;
+                        report.updated.push(app.name);
+                    }
+                    continue;
+                }
+            }
+
+            let name = app.name.clone();
+            match self.load_app(app) {
+                Ok(_) => report.started.push(name),
+                Err(e) => report.errors.push(e)
+            }
+        }
+
+        let gone: Vec<(String, usize)> = self.container.config_state.read().unwrap()
+            .app_name_to_id.iter()
+            .filter(|&(name, id)| !names.contains(name) && self.is_loaded(*id))
+            .map(|(name, id)| (name.clone(), *id))
+            .collect();
+        for (name, id) in gone {
+            let _ = self.unload(id);
+            report.stopped.push(name);
+        }
+
+        for name in &report.started {
+            dinfo!(logger, "Started {}", name);
+        }
+        for name in &report.stopped {
+            dinfo!(logger, "Stopped {}", name);
+        }
+        for name in &report.updated {
+            dinfo!(logger, "Updated {}", name);
+        }
+        for change in &report.restart_required {
+            dwarning!(logger, "Restart required to apply changes to {}", change);
+        }
+        for e in &report.errors {
+            derror!(logger, "{}", e);
+        }
+
+        report
+    }
+
+    pub fn basic_activate(container: Container, code: &[u8], config: &AppConfig) -> Application {
         Application::new(
             trans::translate_module_raw(
                 code,
                 Default::default()
             ),
             code,
-            code_sha256,
+            code_sha256(code),
             config.clone(),
             container
         )
     }
 
-    pub fn migrate_away(&mut self, app_id: usize) -> AppMigration {
-        let app: &Application = if let AppOrUninitialized::App(ref app) = self.apps[app_id] {
-            app
-        } else {
-            panic!("Expecting an initialized app");
+    pub fn migrate_away(&mut self, app_id: usize) -> Result<AppMigration, String> {
+        let mig = match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::App(ref app)) => app.start_migration()?,
+            _ => return Err(format!("Application {} is not running", app_id))
         };
+        self.stop(app_id)?;
+        Ok(mig)
+    }
 
-        let mig = app.start_migration();
-        let code = app.code.clone();
-        let config = app.config.clone();
+    /// Takes the state out of a running application and returns it along
+    /// with the code of the application.
+    ///
+    /// The application stays loaded until `finish_migration` is called so
+    /// that it can be resumed if the target host does not accept it.
+    pub fn prepare_migration(&mut self, app_id: usize) -> Result<AppMigration, String> {
+        let (mig, config) = match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::App(ref app)) => {
+                (app.start_migration()?, app.config.clone())
+            },
+            _ => return Err(format!("Application {} is not running", app_id))
+        };
 
-        self.apps[app_id] = AppOrUninitialized::Uninitialized {
-            code: code,
+        let placeholder = AppOrUninitialized::Uninitialized {
+            code: Vec::new(),
             config: config
         };
+        if let AppOrUninitialized::App(app) = ::std::mem::replace(&mut self.apps[app_id], placeholder) {
+            self.apps[app_id] = AppOrUninitialized::Migrating(app);
+        }
 
-        mig
+        Ok(mig)
     }
 
-    pub fn activate_migration(&self, app_id: usize, migration: &AppMigration) {
-        use std::time::Instant;
+    /// Stops an application prepared for migration if `migration` is `None`,
+    /// or gives it its state back otherwise.
+    pub fn finish_migration(&mut self, app_id: usize, migration: Option<AppMigration>) {
+        let logger = logger!("AppManager::finish_migration");
+
+        let stopped = match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::Migrating(ref app)) => AppOrUninitialized::Uninitialized {
+                code: app.code.clone(),
+                config: app.config.clone()
+            },
+            _ => {
+                dwarning!(logger, "Application {} is not being migrated", app_id);
+                return;
+            }
+        };
+
+        let app = match ::std::mem::replace(&mut self.apps[app_id], stopped) {
+            AppOrUninitialized::Migrating(app) => app,
+            _ => unreachable!()
+        };
 
+        match migration {
+            Some(mig) => {
+                app.abort_migration(&mig);
+                dinfo!(logger, "Migration of {} aborted", app.name);
+                self.apps[app_id] = AppOrUninitialized::App(app);
+            },
+            None => {
+                dinfo!(logger, "Application {} migrated away", app.name);
+            }
+        }
+    }
+
+    /// Activates a stopped application from `migration`, which must have
+    /// been taken from the same code as the one loaded here.
+    pub fn activate_migration(&mut self, app_id: usize, migration: &AppMigration) -> Result<(), String> {
         let logger = logger!("AppManager::activate_migration");
 
         let begin_time = Instant::now();
 
-        let app = if let AppOrUninitialized::Uninitialized { ref code, ref config } = self.apps[app_id] {
-            Self::basic_activate(self.container.clone(), code, config)
-        } else {
-            panic!("Attempting to migrate on an already initialized application");
+        let app = match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::Uninitialized { ref code, ref config }) => {
+                if code_sha256(code) != migration.code_sha256 {
+                    return Err("The migration was taken from different code than the one loaded here".into());
+                }
+                Self::basic_activate(
+                    self.container.clone(),
+                    code,
+                    config
+                )
+            },
+            Some(&AppOrUninitialized::Unloaded) | None => return Err(format!("Application {} not found", app_id)),
+            Some(_) => return Err(format!(
+                "Attempting to migrate on an already initialized application {}",
+                app_id
+            ))
         };
 
         dinfo!(logger, "Application {} loaded", app.name);
 
-        app.complete_migration(migration);
+        app.complete_migration(migration)?;
         dinfo!(
             logger,
             "Application {} migrated. Total time: {}ms",
@@ -96,30 +393,265 @@ impl AppManager {
                 repr
             }
         );
+
+        self.apps[app_id] = AppOrUninitialized::App(app);
+        Ok(())
     }
 
-    pub fn load(&mut self, code: &[u8], app_id: usize, config: AppConfig) {
-        use std::time::Instant;
+    /// Replaces the code of a running application.
+    ///
+    /// If the new code exports `__app_upgrade`, it takes over the memory and globals
+    /// of the old code and `__app_upgrade(old_version)` is called, with `old_version`
+    /// returned by `__app_version` of the old code (or 0). Otherwise, the new code
+    /// starts from scratch. Either way, timers, pending operations and open handles
+    /// of the old code are dropped.
+    ///
+    /// If the upgrade fails, the old code resumes with its state.
+    pub fn upgrade(&mut self, app_id: usize, code: Vec<u8>) -> Result<(), String> {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let logger = logger!("AppManager::upgrade");
 
+        let (mig, old_version, config) = match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::App(ref app)) => {
+                let old_version = app.call_exported0("__app_version").unwrap_or(0);
+                (app.start_migration()?, old_version, app.config.clone())
+            },
+            _ => return Err(format!("Application {} is not running", app_id))
+        };
+
+        // The old instance stays in place until the new one has started.
+        // Listeners bind asynchronously, so the new instance still takes over
+        // the addresses once the old one is dropped.
+        let container = self.container.clone();
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            Self::basic_activate(container, &code, &config)
+        })).map_err(|_| "Unable to load the new code".to_string()).and_then(|app| {
+            if app.has_export("__app_upgrade") {
+                app.complete_upgrade(&mig, old_version)?;
+            } else {
+                dwarning!(
+                    logger,
+                    "{} doesn't export __app_upgrade; starting it without its previous state",
+                    app.name
+                );
+                app.initialize(None)?;
+            }
+            if app.has_crashed() {
+                return Err("The new code crashed".into());
+            }
+            Ok(app)
+        });
+
+        match result {
+            Ok(app) => {
+                dinfo!(logger, "Application {} upgraded from version {}", app.name, old_version);
+                self.apps[app_id] = AppOrUninitialized::App(app);
+                Ok(())
+            },
+            Err(e) => {
+                derror!(logger, "Unable to upgrade {}: {}", config.name, e);
+
+                if let Some(&AppOrUninitialized::App(ref app)) = self.apps.get(app_id) {
+                    app.abort_migration(&mig);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Initializes an application that was loaded as `deferred`, stopped or crashed.
+    pub fn start(&mut self, app_id: usize) -> Result<(), String> {
+        self.restarts.remove(&app_id);
+        self.start_app(app_id)
+    }
+
+    fn start_app(&mut self, app_id: usize) -> Result<(), String> {
+        let app = match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::Uninitialized { ref code, ref config })
+                | Some(&AppOrUninitialized::Crashed { ref code, ref config, .. }) => {
+                Self::basic_activate(self.container.clone(), code, config)
+            },
+            Some(&AppOrUninitialized::Unloaded) | None => return Err(format!("Application {} not found", app_id)),
+            Some(_) => return Err(format!("Application {} is already running", app_id))
+        };
+
+        if let Err(e) = app.initialize(None) {
+            self.crashed(app_id, app, true);
+            return Err(e);
+        }
+        dinfo!(logger!("AppManager::start"), "Application {} started", app.name);
+
+        self.apps[app_id] = AppOrUninitialized::App(app);
+        Ok(())
+    }
+
+    /// Releases a crashed application and schedules its restart according
+    /// to its `restart` policy.
+    ///
+    /// `during_init` tells whether the initializer failed, as opposed to a
+    /// crash of an application that was running.
+    fn crashed(&mut self, app_id: usize, app: Application, during_init: bool) {
+        let logger = logger!("AppManager::crashed");
+
+        let stats = AppStats {
+            host_memory: 0,
+            resources: Default::default(),
+            crashed: true,
+            ..app.stats()
+        };
+        let uptime_ms = stats.running_time;
+        let name = app.name.clone();
+        if app.has_timed_out() {
+            *self.callback_timeouts.entry(app_id).or_insert(0) += 1;
+        }
+        self.apps[app_id] = AppOrUninitialized::Crashed {
+            code: app.code.clone(),
+            config: app.config.clone(),
+            stats: stats
+        };
+        drop(app);
+
+        let crash = {
+            let crashes = self.crashes.entry(app_id).or_insert(0);
+            *crashes += 1;
+            *crashes
+        };
+
+        // The policy may have changed since the application was started.
+        let (policy, max_restarts) = {
+            let cs = self.container.config_state.read().unwrap();
+            let config = &cs.config.applications[app_id];
+            (config.restart, config.max_restarts)
+        };
+
+        if uptime_ms >= RESTART_RESET_SECS * 1000 {
+            self.restarts.remove(&app_id);
+        }
+
+        let restart = match policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !during_init,
+            RestartPolicy::Always => true
+        };
+        if !restart {
+            derror!(logger, "Application {} crashed and is left stopped", name);
+            return;
+        }
+
+        let restarts = self.restarts.get(&app_id).cloned().unwrap_or(0);
+        if restarts >= max_restarts {
+            derror!(
+                logger,
+                "Application {} crashed after {} restarts and is left stopped",
+                name,
+                restarts
+            );
+            return;
+        }
+        self.restarts.insert(app_id, restarts + 1);
+
+        let delay = ::std::cmp::min(
+            RESTART_DELAY_SECS << ::std::cmp::min(restarts, 16),
+            MAX_RESTART_DELAY_SECS
+        );
+        dwarning!(logger, "Restarting {} in {}s", name, delay);
+
+        let container = self.container.clone();
+        tokio::executor::current_thread::spawn(
+            tokio::timer::Delay::new(Instant::now() + Duration::from_secs(delay)).then(move |_| {
+                let _ = container.dispatch_control(Control::RestartApp {
+                    app_id: app_id,
+                    crash: crash
+                });
+                Ok(())
+            })
+        );
+    }
+
+    /// Drops a running application, keeping its code and config so that
+    /// it can be started again.
+    pub fn stop(&mut self, app_id: usize) -> Result<(), String> {
+        let (code, config) = match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::App(ref app)) => (app.code.clone(), app.config.clone()),
+            _ => return Err(format!("Application {} is not running", app_id))
+        };
+
+        self.apps[app_id] = AppOrUninitialized::Uninitialized {
+            code: code,
+            config: config
+        };
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<AppStatus> {
+        self.apps.iter().enumerate().filter_map(|(id, app)| match *app {
+            AppOrUninitialized::App(ref app) | AppOrUninitialized::Migrating(ref app) => Some(AppStatus {
+                id: id,
+                name: app.name.clone(),
+                running: true,
+                crashed: false
+            }),
+            AppOrUninitialized::Uninitialized { ref config, .. } => Some(AppStatus {
+                id: id,
+                name: config.name.clone(),
+                running: false,
+                crashed: false
+            }),
+            AppOrUninitialized::Crashed { ref config, .. } => Some(AppStatus {
+                id: id,
+                name: config.name.clone(),
+                running: false,
+                crashed: true
+            }),
+            AppOrUninitialized::Unloaded => None
+        }).collect()
+    }
+
+    pub fn load(&mut self, code: &[u8], app_id: usize, config: AppConfig) -> Result<(), String> {
         let logger = logger!("AppManager::load");
 
+        if let Some(interval) = config.checkpoint_interval {
+            self.schedule_checkpoints(app_id, Duration::from_secs(interval));
+        }
+
         if config.deferred {
             self.add(app_id, AppOrUninitialized::Uninitialized {
                 code: code.to_vec(),
                 config: config
             });
-            return;
+            return Ok(());
         }
 
         let begin_time = Instant::now();
         let app = Self::basic_activate(self.container.clone(), code, &config);
         dinfo!(logger, "Application {} loaded", app.name);
 
-        app.initialize(None);
+        let restored = match Self::read_snapshot(&config) {
+            Some(mig) => match app.complete_migration(&mig) {
+                Ok(_) => true,
+                Err(e) => {
+                    derror!(logger, "Unable to restore {} from its snapshot: {}", app.name, e);
+                    false
+                }
+            },
+            None => false
+        };
+        if !restored {
+            if let Err(e) = app.initialize(None) {
+                derror!(logger, "Unable to initialize {}: {}", app.name, e);
+                self.restarts.remove(&app_id);
+                self.add(app_id, AppOrUninitialized::Unloaded);
+                self.crashed(app_id, app, true);
+                return Err(e);
+            }
+        }
+
         dinfo!(
             logger,
-            "Application {} initialized. Total time: {}ms",
+            "Application {} {}. Total time: {}ms",
             app.name,
+            if restored { "restored" } else { "initialized" },
             {
                 let elapsed = Instant::now().duration_since(begin_time);
                 let repr = elapsed.as_secs() * 1000 + (elapsed.subsec_nanos() / 1000000) as u64;
@@ -128,35 +660,204 @@ impl AppManager {
         );
 
         self.add(app_id, AppOrUninitialized::App(app));
+        Ok(())
+    }
+
+    /// Reads the snapshot at `restore_from`, if there is one.
+    fn read_snapshot(config: &AppConfig) -> Option<AppMigration> {
+        use std::fs::File;
+        use std::io::Read;
+
+        let logger = logger!("AppManager::read_snapshot");
+        let path = config.restore_from.as_ref()?;
+
+        let mut data = Vec::new();
+        match File::open(path).and_then(|mut f| f.read_to_end(&mut data)) {
+            Ok(_) => {},
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                dinfo!(logger, "No snapshot for {} at {}", config.name, path);
+                return None;
+            },
+            Err(e) => {
+                derror!(logger, "Unable to read snapshot {}: {:?}", path, e);
+                return None;
+            }
+        }
+
+        match bincode::deserialize(&data) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                derror!(logger, "Invalid snapshot {}: {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Writes the state of a running application to its `restore_from` file.
+    ///
+    /// The file is written on the I/O pool and replaced atomically.
+    pub fn checkpoint(&self, app_id: usize) -> Box<Future<Item = (), Error = String>> {
+        let (mig, path) = match self.apps.get(app_id) {
+            Some(&AppOrUninitialized::App(ref app)) => {
+                let path = match app.config.restore_from {
+                    Some(ref v) => v.clone(),
+                    None => return Box::new(futures::future::err(
+                        format!("Application {} has no restore_from path", app.name)
+                    ))
+                };
+                match app.checkpoint() {
+                    Ok(v) => (v, path),
+                    Err(e) => return Box::new(futures::future::err(e))
+                }
+            },
+            _ => return Box::new(futures::future::err(format!("Application {} is not running", app_id)))
+        };
+
+        let data = bincode::serialize(&mig).unwrap();
+
+        Box::new(self.container.io_pool.spawn_fn(move || -> io::Result<()> {
+            use std::fs::File;
+            use std::io::Write;
+
+            let tmp_path = format!("{}.tmp", path);
+            let mut f = File::create(&tmp_path)?;
+            f.write_all(&data)?;
+            f.sync_all()?;
+            ::std::fs::rename(&tmp_path, &path)
+        }).map_err(|e| format!("Unable to write snapshot: {:?}", e)))
+    }
+
+    fn schedule_checkpoints(&mut self, app_id: usize, interval: Duration) {
+        let container = self.container.clone();
+        let (tx, rx) = oneshot::channel();
+
+        // Replaces, and so cancels, the timer of a previous load.
+        self.checkpoint_timers.insert(app_id, tx);
+
+        tokio::executor::current_thread::spawn(
+            rx.select2(
+                tokio::timer::Interval::new(Instant::now() + interval, interval).for_each(move |_| {
+                    let _ = container.dispatch_control(Control::Checkpoint {
+                        app_id: app_id,
+                        sender: None
+                    });
+                    Ok(())
+                }).map_err(|e| {
+                    derror!(logger!("AppManager::schedule_checkpoints"), "Timer error: {:?}", e);
+                })
+            ).then(|_| Ok(()))
+        );
     }
 
     pub fn dispatch_control(&mut self, c: Control) {
         match c {
             Control::Event(ev) => {
-                let app = match self.apps[ev.app_id] {
-                    AppOrUninitialized::App(ref v) => v,
-                    _ => panic!("Not initialized")
+                let app = match self.apps.get(ev.app_id) {
+                    Some(&AppOrUninitialized::App(ref v)) => v,
+                    _ => {
+                        dwarning!(
+                            logger!("AppManager::dispatch_control"),
+                            "Dropping event for uninitialized application {}",
+                            ev.app_id
+                        );
+                        return;
+                    }
                 };
                 ev.notify(app);
             },
             Control::Stats(mut req) => {
                 let mut stats: BTreeMap<String, AppStats> = BTreeMap::new();
-                for app in &self.apps {
-                    if let AppOrUninitialized::App(ref app) = *app {
-                        let name = app.name.clone();
-                        stats.insert(name, app.stats());
-                    }
+                for (app_id, app) in self.apps.iter().enumerate() {
+                    let (name, mut app_stats) = match *app {
+                        AppOrUninitialized::App(ref app) => (app.name.clone(), app.stats()),
+                        AppOrUninitialized::Crashed { ref config, stats: ref last, .. } => (config.name.clone(), last.clone()),
+                        _ => continue
+                    };
+                    app_stats.callback_timeouts = self.callback_timeouts.get(&app_id).cloned().unwrap_or(0);
+                    stats.insert(name, app_stats);
                 }
                 req.feedback.start_send(Stats {
                     applications: stats
                 }).unwrap();
             },
-            Control::ActivateMigration { app_id, migration } => {
-                self.activate_migration(app_id, &migration);
+            Control::ListApps { mut sender } => {
+                let _ = sender.start_send(self.list());
+            },
+            Control::ActivateMigration { app_id, migration, mut sender } => {
+                let _ = sender.start_send(self.activate_migration(app_id, &migration));
+            },
+            Control::PrepareMigration { app_id, mut sender } => {
+                let _ = sender.start_send(self.prepare_migration(app_id));
+            },
+            Control::FinishMigration { app_id, migration } => {
+                self.finish_migration(app_id, migration);
             },
             Control::MigrateAway { app_id, mut sender } => {
-                let mig = self.migrate_away(app_id);
-                sender.start_send(mig).unwrap();
+                let _ = sender.start_send(self.migrate_away(app_id));
+            },
+            Control::Checkpoint { app_id, sender } => {
+                // Periodic checkpoints skip applications that are not running.
+                if sender.is_none() {
+                    match self.apps.get(app_id) {
+                        Some(&AppOrUninitialized::App(_)) => {},
+                        _ => return
+                    }
+                }
+
+                tokio::executor::current_thread::spawn(self.checkpoint(app_id).then(move |result| {
+                    match sender {
+                        Some(mut sender) => {
+                            let _ = sender.start_send(result);
+                        },
+                        None => if let Err(e) = result {
+                            derror!(logger!("AppManager::checkpoint"), "Checkpoint failed: {}", e);
+                        }
+                    }
+                    Ok(())
+                }));
+            },
+            Control::Upgrade { app_id, code, mut sender } => {
+                let _ = sender.start_send(self.upgrade(app_id, code));
+            },
+            Control::Reload { config, mut sender } => {
+                let _ = sender.start_send(self.reload(config));
+            },
+            Control::LoadApp { config, mut sender } => {
+                let _ = sender.start_send(self.load_app(config));
+            },
+            Control::UnloadApp { app_id, mut sender } => {
+                let _ = sender.start_send(self.unload(app_id));
+            },
+            Control::AppCrashed { app_id } => {
+                // Ignore reports from an instance that has been replaced since.
+                let crashed = match self.apps.get(app_id) {
+                    Some(&AppOrUninitialized::App(ref app)) => app.has_crashed(),
+                    _ => false
+                };
+                if crashed {
+                    if let AppOrUninitialized::App(app) = ::std::mem::replace(
+                        &mut self.apps[app_id],
+                        AppOrUninitialized::Unloaded
+                    ) {
+                        self.crashed(app_id, app, false);
+                    }
+                }
+            },
+            Control::RestartApp { app_id, crash } => {
+                if self.crashes.get(&app_id) != Some(&crash) {
+                    return;
+                }
+                if let Some(&AppOrUninitialized::Crashed { .. }) = self.apps.get(app_id) {
+                    if let Err(e) = self.start_app(app_id) {
+                        derror!(logger!("AppManager::restart"), "Unable to restart application {}: {}", app_id, e);
+                    }
+                }
+            },
+            Control::StartApp { app_id, mut sender } => {
+                let _ = sender.start_send(self.start(app_id));
+            },
+            Control::StopApp { app_id, mut sender } => {
+                let _ = sender.start_send(self.stop(app_id));
             }
         }
     }
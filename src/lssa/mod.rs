@@ -11,3 +11,6 @@ pub mod stats;
 pub mod ns;
 pub mod cwa;
 pub mod error;
+pub mod host_memory;
+pub mod limits;
+pub mod watchdog;
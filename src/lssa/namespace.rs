@@ -1,15 +1,21 @@
 use wasm_core::executor::{NativeEntry, GlobalStateProvider};
 use wasm_core::value::Value;
 use std::rc::Weak;
+use std::any::Any;
 use super::app::ApplicationImpl;
+use super::error::ErrorCode;
 use serde::{Serialize, Deserialize};
 use bincode;
+use slab::Slab;
 
 pub trait Namespace: 'static {
     fn prefix(&self) -> &str;
     fn dispatch(&self, field: &str) -> Option<NativeEntry>;
     fn start_migration(&self) -> Option<Migration>;
-    fn complete_migration(&self, migration: &Migration);
+    fn snapshot(&self) -> Option<Migration>;
+    fn complete_migration(&self, migration: &Migration) -> Result<(), String>;
+    fn cancel_migration(&self, migration: &Migration);
+    fn as_any(&self) -> &Any;
 }
 
 #[allow(dead_code)]
@@ -20,7 +26,25 @@ pub struct Migration {
 
 pub trait MigrationProvider<T: Namespace> {
     fn start_migration(target: &T) -> Option<Migration>;
-    fn complete_migration(target: &T, migration: &Migration);
+
+    /// Captures the state `start_migration` would take out, but leaves
+    /// `target` running as it is, e.g. for a checkpoint.
+    fn snapshot(target: &T) -> Option<Migration>;
+
+    /// Fails if `migration` can't be restored, e.g. because it is corrupt.
+    fn complete_migration(target: &T, migration: &Migration) -> Result<(), String>;
+
+    /// Gives `target` back the state taken out by `start_migration` when the
+    /// application keeps running on this instance.
+    fn cancel_migration(target: &T, migration: &Migration) {
+        if let Err(e) = Self::complete_migration(target, migration) {
+            derror!(
+                logger!("MigrationProvider::cancel_migration"),
+                "Unable to restore state: {}",
+                e
+            );
+        }
+    }
 }
 
 pub struct NullMigrationProvider;
@@ -28,7 +52,12 @@ impl<T: Namespace> MigrationProvider<T> for NullMigrationProvider {
     fn start_migration(_: &T) -> Option<Migration> {
         Some(Migration::empty())
     }
-    fn complete_migration(_: &T, _migration: &Migration) {}
+    fn snapshot(_: &T) -> Option<Migration> {
+        Some(Migration::empty())
+    }
+    fn complete_migration(_: &T, _migration: &Migration) -> Result<(), String> {
+        Ok(())
+    }
 }
 
 #[allow(dead_code)]
@@ -45,18 +74,73 @@ impl Migration {
         }
     }
 
-    pub fn extract<'a, T: Deserialize<'a>>(&'a self) -> Option<T> {
-        match bincode::deserialize(&self.state) {
-            Ok(v) => Some(v),
-            Err(e) => {
-                derror!(
-                    logger!("Migration::extract"),
-                    "Unable to extract state: {:?}",
-                    e
-                );
-                None
-            }
+    pub fn extract<'a, T: Deserialize<'a>>(&'a self) -> Result<T, String> {
+        bincode::deserialize(&self.state)
+            .map_err(|e| format!("Unable to extract state: {}", e))
+    }
+}
+
+/// Largest id `restore_slab` accepts. Every gap below an id takes a
+/// placeholder, so this bounds what bad migration data can allocate.
+pub const MAX_RESTORED_ID: usize = 1 << 20;
+
+/// Rebuilds a `Slab` with every value stored under its original key,
+/// so that ids handed out to the guest stay valid after a migration.
+///
+/// `filler` produces placeholders for the gaps, which are removed before returning.
+/// Duplicate ids and ids above `MAX_RESTORED_ID` are rejected.
+pub fn restore_slab<T, F: FnMut() -> T>(mut entries: Vec<(usize, T)>, mut filler: F) -> Result<Slab<T>, String> {
+    entries.sort_by_key(|&(k, _)| k);
+
+    if let Some(&(key, _)) = entries.last() {
+        if key > MAX_RESTORED_ID {
+            return Err(format!("Id {} out of range", key));
+        }
+    }
+    for w in entries.windows(2) {
+        if w[0].0 == w[1].0 {
+            return Err(format!("Duplicate id {}", w[0].0));
+        }
+    }
+
+    let mut slab = Slab::new();
+    let mut holes = Vec::new();
+
+    for (key, value) in entries {
+        while slab.len() < key {
+            holes.push(slab.insert(filler()));
         }
+        slab.insert(value);
+    }
+
+    for key in holes {
+        slab.remove(key);
+    }
+
+    Ok(slab)
+}
+
+/// Result of a namespace method.
+///
+/// Failures the application is expected to handle are returned as values.
+/// An `Err` traps instead, which crashes the calling application only.
+pub type InvokeResult = Result<Option<Value>, ErrorCode>;
+
+/// Looks up an entry by an id the application passed in.
+pub fn slab_get<T>(slab: &Slab<T>, id: usize) -> Result<&T, ErrorCode> {
+    slab.get(id).ok_or(ErrorCode::InvalidInput)
+}
+
+pub fn slab_get_mut<T>(slab: &mut Slab<T>, id: usize) -> Result<&mut T, ErrorCode> {
+    slab.get_mut(id).ok_or(ErrorCode::InvalidInput)
+}
+
+/// Removes an entry by an id the application passed in.
+pub fn slab_remove<T>(slab: &mut Slab<T>, id: usize) -> Result<T, ErrorCode> {
+    if slab.contains(id) {
+        Ok(slab.remove(id))
+    } else {
+        Err(ErrorCode::InvalidInput)
     }
 }
 
@@ -66,24 +150,66 @@ pub struct InvokeContext<'a> {
     pub app: &'a Weak<ApplicationImpl>
 }
 
+/// Arguments and memory of a call from an application.
+///
+/// Everything here comes from the application, so accessors fail with
+/// `InvalidInput` rather than panic.
 #[allow(dead_code)]
 impl<'a> InvokeContext<'a> {
-    pub fn extract_bytes(&self, ptr_arg_index: usize, len_arg_index: usize) -> &[u8] {
-        let base = self.args[ptr_arg_index].get_i32().unwrap() as usize;
-        let len = self.args[len_arg_index].get_i32().unwrap() as usize;
-        &self.state.get_memory()[base .. base + len]
+    pub fn arg_i32(&self, index: usize) -> Result<i32, ErrorCode> {
+        self.args.get(index)
+            .and_then(|v| v.get_i32().ok())
+            .ok_or(ErrorCode::InvalidInput)
+    }
+
+    pub fn arg_i64(&self, index: usize) -> Result<i64, ErrorCode> {
+        self.args.get(index)
+            .and_then(|v| v.get_i64().ok())
+            .ok_or(ErrorCode::InvalidInput)
+    }
+
+    /// Reads an `i32` argument as an unsigned id, length or pointer.
+    pub fn arg_usize(&self, index: usize) -> Result<usize, ErrorCode> {
+        self.arg_i32(index).map(|v| v as u32 as usize)
+    }
+
+    fn range(&self, ptr_arg_index: usize, len_arg_index: usize, mem_len: usize) -> Result<(usize, usize), ErrorCode> {
+        let base = self.arg_usize(ptr_arg_index)?;
+        let len = self.arg_usize(len_arg_index)?;
+        match base.checked_add(len) {
+            Some(end) if end <= mem_len => Ok((base, end)),
+            _ => Err(ErrorCode::InvalidInput)
+        }
     }
 
-    pub fn extract_bytes_mut(&mut self, ptr_arg_index: usize, len_arg_index: usize) -> &mut [u8] {
-        let base = self.args[ptr_arg_index].get_i32().unwrap() as usize;
-        let len = self.args[len_arg_index].get_i32().unwrap() as usize;
-        &mut self.state.get_memory_mut()[base .. base + len]
+    pub fn extract_bytes(&self, ptr_arg_index: usize, len_arg_index: usize) -> Result<&[u8], ErrorCode> {
+        let mem = self.state.get_memory();
+        let (begin, end) = self.range(ptr_arg_index, len_arg_index, mem.len())?;
+        Ok(&mem[begin .. end])
     }
 
-    pub fn extract_str(&self, ptr_arg_index: usize, len_arg_index: usize) -> &str {
+    pub fn extract_bytes_mut(&mut self, ptr_arg_index: usize, len_arg_index: usize) -> Result<&mut [u8], ErrorCode> {
+        let mem_len = self.state.get_memory().len();
+        let (begin, end) = self.range(ptr_arg_index, len_arg_index, mem_len)?;
+        Ok(&mut self.state.get_memory_mut()[begin .. end])
+    }
+
+    pub fn extract_str(&self, ptr_arg_index: usize, len_arg_index: usize) -> Result<&str, ErrorCode> {
         ::std::str::from_utf8(
-            self.extract_bytes(ptr_arg_index, len_arg_index)
-        ).unwrap()
+            self.extract_bytes(ptr_arg_index, len_arg_index)?
+        ).map_err(|_| ErrorCode::InvalidInput)
+    }
+
+    /// Copies `data` to the memory at `ptr`, failing if it doesn't fit.
+    pub fn write_bytes(&mut self, ptr: usize, data: &[u8]) -> Result<(), ErrorCode> {
+        let mem = self.state.get_memory_mut();
+        match ptr.checked_add(data.len()) {
+            Some(end) if end <= mem.len() => {
+                mem[ptr .. end].copy_from_slice(data);
+                Ok(())
+            },
+            _ => Err(ErrorCode::InvalidInput)
+        }
     }
 }
 
@@ -110,6 +236,10 @@ macro_rules! decl_namespace_with_migration_provider {
                     app: app
                 }
             }
+
+            pub fn provider(&self) -> &::std::rc::Rc<$inner_ty> {
+                &self.provider
+            }
         }
 
         impl $crate::lssa::namespace::Namespace for $name {
@@ -129,7 +259,14 @@ macro_rules! decl_namespace_with_migration_provider {
                                 args: args,
                                 app: &app
                             };
-                            Ok(provider.$case(ctx))
+                            $crate::lssa::watchdog::host_call(|| provider.$case(ctx).map_err(|code| {
+                                ::wasm_core::executor::ExecuteError::Custom(format!(
+                                    "{}_{}: {:?}",
+                                    $prefix,
+                                    stringify!($case),
+                                    code
+                                ))
+                            }))
                         })),
                     )*
                     _ => None
@@ -141,10 +278,24 @@ macro_rules! decl_namespace_with_migration_provider {
                 $mig::start_migration(self)
             }
 
-            fn complete_migration(&self, mig: &$crate::lssa::namespace::Migration) {
+            fn snapshot(&self) -> Option<$crate::lssa::namespace::Migration> {
+                use $crate::lssa::namespace::MigrationProvider;
+                $mig::snapshot(self)
+            }
+
+            fn complete_migration(&self, mig: &$crate::lssa::namespace::Migration) -> Result<(), String> {
                 use $crate::lssa::namespace::MigrationProvider;
                 $mig::complete_migration(self, mig)
             }
+
+            fn cancel_migration(&self, mig: &$crate::lssa::namespace::Migration) {
+                use $crate::lssa::namespace::MigrationProvider;
+                $mig::cancel_migration(self, mig)
+            }
+
+            fn as_any(&self) -> &::std::any::Any {
+                self
+            }
         }
     }
 }
@@ -161,3 +312,23 @@ macro_rules! decl_namespace {
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_slab() {
+        let slab = restore_slab(vec![(3, "c"), (0, "a")], || "").unwrap();
+        assert_eq!(slab.len(), 2);
+        assert_eq!(slab[0], "a");
+        assert_eq!(slab[3], "c");
+        assert!(slab.get(1).is_none());
+    }
+
+    #[test]
+    fn test_restore_slab_bad_ids() {
+        assert!(restore_slab(vec![(1, "a"), (1, "b")], || "").is_err());
+        assert!(restore_slab(vec![(MAX_RESTORED_ID + 1, "a")], || "").is_err());
+    }
+}
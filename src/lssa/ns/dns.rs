@@ -0,0 +1,225 @@
+use config::AppPermission;
+use super::super::namespace::{InvokeContext, InvokeResult, slab_get, slab_remove, MigrationProvider, Migration, restore_slab};
+use wasm_core::value::Value;
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use slab::Slab;
+
+use futures::Future;
+use super::super::error::ErrorCode;
+use super::super::app::ApplicationImpl;
+use super::super::limits::Resource;
+use super::super::host_memory::HostBuffer;
+use dns::split_host_port;
+
+decl_namespace_with_migration_provider!(
+    DnsNs,
+    "dns",
+    DnsImpl,
+    DnsMigrationProvider,
+    resolve,
+    addresses,
+    release
+);
+
+#[derive(Serialize, Deserialize, Clone)]
+struct DnsMigrationInfo {
+    results: Vec<(usize, Vec<u8>)>,
+    callbacks: Vec<ResolveCallback>
+}
+
+pub struct DnsMigrationProvider;
+impl MigrationProvider<DnsNs> for DnsMigrationProvider {
+    fn start_migration(target: &DnsNs) -> Option<Migration> {
+        let mig = Self::snapshot(target);
+        *target.provider.results.borrow_mut() = Slab::new();
+        *target.provider.callbacks.borrow_mut() = Slab::new();
+        mig
+    }
+
+    fn snapshot(target: &DnsNs) -> Option<Migration> {
+        Some(Migration::new(&DnsMigrationInfo {
+            results: target.provider.results.borrow().iter()
+                .map(|(id, r)| (id, r.to_vec()))
+                .collect(),
+            callbacks: target.provider.callbacks.borrow().iter()
+                .map(|(_, b)| *b)
+                .collect()
+        }))
+    }
+
+    fn complete_migration(target: &DnsNs, mig: &Migration) -> Result<(), String> {
+        let info: DnsMigrationInfo = mig.extract()?;
+        let app = target.provider.app.upgrade().unwrap();
+
+        let memory = app.host_memory();
+        let resources = app.resources();
+        *target.provider.results.borrow_mut() = restore_slab(
+            info.results.into_iter()
+                .map(|(id, r)| (id, memory.track(r.into_boxed_slice(), resources.track(Resource::Buffers))))
+                .collect(),
+            || memory.track(Vec::new().into_boxed_slice(), resources.track(Resource::Buffers))
+        )?;
+
+        for cb in &info.callbacks {
+            app.invoke2(
+                cb.cb_target,
+                cb.cb_data,
+                ErrorCode::Generic.to_i32()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+struct ResolveCallback {
+    cb_target: i32,
+    cb_data: i32
+}
+
+pub struct DnsImpl {
+    app: Weak<ApplicationImpl>,
+    results: Rc<RefCell<Slab<HostBuffer>>>,
+    callbacks: Rc<RefCell<Slab<ResolveCallback>>>
+}
+
+impl DnsImpl {
+    pub fn new(app: Weak<ApplicationImpl>) -> DnsImpl {
+        DnsImpl {
+            app: app,
+            results: Rc::new(RefCell::new(Slab::new())),
+            callbacks: Rc::new(RefCell::new(Slab::new()))
+        }
+    }
+
+    /// Looking up a host is allowed if the application may connect to it,
+    /// i.e. it has `TcpConnectAny` or a `TcpConnect` permission naming the host.
+    fn check_host(&self, app: &ApplicationImpl, host: &str) -> Result<(), ErrorCode> {
+        let allowed = app.check_permission(&AppPermission::TcpConnectAny).is_ok()
+            || app.has_permission_matching(|p| match *p {
+                AppPermission::TcpConnect(ref addr) => split_host_port(addr)
+                    .map(|(h, _)| h.eq_ignore_ascii_case(host))
+                    .unwrap_or(false),
+                _ => false
+            });
+
+        if allowed {
+            Ok(())
+        } else {
+            derror!(
+                logger!(&app.name),
+                "TcpConnectAny or TcpConnect({}:*) permission is required",
+                host
+            );
+            Err(ErrorCode::PermissionDenied)
+        }
+    }
+
+    /// Arguments: host name, callback.
+    ///
+    /// The callback receives a result id, or an error code.
+    pub fn resolve(&self, ctx: InvokeContext) -> InvokeResult {
+        let host = ctx.extract_str(0, 1)?
+            .trim_left_matches('[')
+            .trim_right_matches(']')
+            .to_string();
+        let cb_target = ctx.arg_i32(2)?;
+        let cb_data = ctx.arg_i32(3)?;
+
+        let app = ctx.app.upgrade().unwrap();
+
+        if let Err(code) = self.check_host(&app, &host) {
+            app.invoke2(
+                cb_target,
+                cb_data,
+                code.to_i32()
+            );
+            return Ok(None);
+        }
+
+        let cb_slot = match app.acquire(Resource::PendingCallbacks) {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(
+                    cb_target,
+                    cb_data,
+                    code.to_i32()
+                );
+                return Ok(None);
+            }
+        };
+
+        let cb_id = self.callbacks.borrow_mut().insert(ResolveCallback {
+            cb_target: cb_target,
+            cb_data: cb_data
+        });
+        let callbacks = self.callbacks.clone();
+        let results = self.results.clone();
+        let app_weak = ctx.app.clone();
+
+        app.spawn_callback(
+            cb_slot,
+            app.resolver().resolve_host(&host).then(move |result| {
+                if !callbacks.borrow().contains(cb_id) {
+                    return Ok(());
+                }
+                callbacks.borrow_mut().remove(cb_id);
+
+                let app = app_weak.upgrade().unwrap();
+                let ret = match result {
+                    Ok(addrs) => {
+                        let data = addrs.iter()
+                            .map(|a| format!("{}", a))
+                            .collect::<Vec<_>>()
+                            .join("\n")
+                            .into_bytes()
+                            .into_boxed_slice();
+
+                        // The result is kept until it is released.
+                        match app.reserve_buffer(data.len()) {
+                            Ok(ref r) if r.len() < data.len() => ErrorCode::OutOfMemory.to_i32(),
+                            Ok(r) => results.borrow_mut().insert(r.fill(data)) as i32,
+                            Err(code) => code.to_i32()
+                        }
+                    },
+                    Err(e) => {
+                        derror!(logger!("(app)"), "Unable to resolve {}: {:?}", host, e);
+                        ErrorCode::from(e.kind()).to_i32()
+                    }
+                };
+                app.invoke2(
+                    cb_target,
+                    cb_data,
+                    ret
+                );
+                Ok(())
+            })
+        );
+
+        Ok(None)
+    }
+
+    /// Writes all addresses of a result, one per line.
+    pub fn addresses(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let results = self.results.borrow();
+        let bytes: &[u8] = slab_get(&results, id)?;
+
+        let out = ctx.extract_bytes_mut(1, 2)?;
+
+        // The caller should retry with a larger buffer if the returned
+        // length is greater than the length of its buffer.
+        if bytes.len() <= out.len() {
+            out[0..bytes.len()].copy_from_slice(bytes);
+        }
+        Ok(Some(Value::I32(bytes.len() as i32)))
+    }
+
+    pub fn release(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        slab_remove(&mut self.results.borrow_mut(), id)?;
+        Ok(None)
+    }
+}
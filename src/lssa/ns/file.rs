@@ -1,12 +1,22 @@
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 
 use slab::Slab;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf, Component};
+use std::time::UNIX_EPOCH;
+use std::env;
+use std::io;
 
-use super::super::namespace::{InvokeContext, MigrationProvider, Migration};
+use futures::Future;
+
+use super::super::namespace::{InvokeContext, InvokeResult, slab_get, slab_remove, MigrationProvider, Migration, restore_slab};
 use super::super::error::ErrorCode;
 use wasm_core::value::Value;
-use config::AppPermission;
+use config::{AppPermission, MountConfig};
+use super::super::app::ApplicationImpl;
+use super::super::host_memory::HostBuffer;
+use super::super::limits::{Resource, Slot};
 
 decl_namespace_with_migration_provider!(
     FileNs,
@@ -18,54 +28,474 @@ decl_namespace_with_migration_provider!(
     read,
     write,
     flush,
-    seek
+    seek,
+    read_async,
+    write_async,
+    take_buffer,
+    release_buffer,
+    stat,
+    readdir,
+    mkdir,
+    remove,
+    rename,
+    truncate,
+    sync_all
 );
 
+/// Size of the record written by `stat`:
+///
+/// - `u64` length
+/// - `i64` modification time in milliseconds since the Unix epoch
+/// - `u32` kind (`0` file, `1` directory, `2` symlink, `3` other)
+/// - `u32` `1` if read-only
+///
+/// All fields are little-endian.
+const STAT_SIZE: usize = 24;
+
+/// Resolves `path` to an absolute path without `.`, `..` or symlinks.
+///
+/// If `follow` is false, the last component is kept as is so that a symlink
+/// itself can be inspected, removed or renamed. Components that don't exist
+/// yet (e.g. a file to be created) are appended to the resolved path of their
+/// closest existing ancestor, and must not be `..`.
+fn resolve_path(path: &Path, follow: bool) -> io::Result<PathBuf> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        env::current_dir()?.join(path)
+    };
+
+    let mut rest = Vec::new();
+    let mut base = path.as_path();
+
+    if !follow {
+        if let (Some(name), Some(parent)) = (base.file_name(), base.parent()) {
+            rest.push(name);
+            base = parent;
+        }
+    }
+
+    loop {
+        match fs::canonicalize(base) {
+            Ok(mut resolved) => {
+                for name in rest.iter().rev() {
+                    resolved.push(name);
+                }
+                return Ok(resolved);
+            },
+            Err(e) => {
+                // A dangling symlink could point anywhere once its target is created.
+                if fs::symlink_metadata(base).is_ok() {
+                    return Err(io::Error::new(io::ErrorKind::PermissionDenied, "dangling symlink"));
+                }
+                match (base.file_name(), base.parent()) {
+                    (Some(name), Some(parent)) => {
+                        rest.push(name);
+                        base = parent;
+                    },
+                    _ => return Err(e)
+                }
+            }
+        }
+    }
+}
+
+/// Normalizes a guest path lexically into an absolute path.
+///
+/// Like in a chroot, `..` at the root stays at the root.
+fn normalize_guest_path(path: &str) -> PathBuf {
+    let mut out = PathBuf::from("/");
+    for c in Path::new(path).components() {
+        match c {
+            Component::Normal(name) => out.push(name),
+            Component::ParentDir => {
+                out.pop();
+            },
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Finds the mount with the longest guest path containing `guest`.
+fn find_mount<'a>(mounts: &'a [MountConfig], guest: &Path) -> Option<&'a MountConfig> {
+    mounts.iter()
+        .filter(|m| guest.starts_with(normalize_guest_path(&m.guest)))
+        .max_by_key(|m| normalize_guest_path(&m.guest).components().count())
+}
+
+/// Returns whether the resolved path `target` lies within `prefix`.
+fn path_within(target: &Path, prefix: &str) -> bool {
+    match fs::canonicalize(prefix) {
+        Ok(prefix) => target.starts_with(prefix),
+        Err(_) => false
+    }
+}
+
+/// Maps the normalized guest path `guest` to a host path through the mount
+/// that contains it.
+fn map_guest_path(
+    app_name: &str,
+    mounts: &[MountConfig],
+    guest: &Path,
+    write: bool,
+    follow: bool
+) -> Result<PathBuf, ErrorCode> {
+    let mount = match find_mount(mounts, guest) {
+        Some(v) => v,
+        None => return Err(ErrorCode::NotFound)
+    };
+    if write && mount.readonly {
+        derror!(
+            logger!(app_name),
+            "{} is mounted read-only",
+            mount.guest
+        );
+        return Err(ErrorCode::PermissionDenied);
+    }
+
+    let host_root = fs::canonicalize(&mount.host).map_err(|e| {
+        derror!(
+            logger!(app_name),
+            "Unable to resolve mount {} -> {}: {:?}",
+            mount.guest,
+            mount.host,
+            e
+        );
+        ErrorCode::from(e.kind())
+    })?;
+    let host_path = host_root.join(
+        guest.strip_prefix(normalize_guest_path(&mount.guest)).unwrap()
+    );
+
+    // Symlinks inside a mount must not lead out of it.
+    match resolve_path(&host_path, follow) {
+        Ok(ref v) if v.starts_with(&host_root) => Ok(v.clone()),
+        Ok(_) => Err(ErrorCode::PermissionDenied),
+        Err(e) => Err(ErrorCode::from(e.kind()))
+    }
+}
+
+fn write_le(out: &mut [u8], v: u64) {
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = (v >> (i * 8)) as u8;
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct FileMigrationInfo {
+    handles: Vec<(usize, MigratedFile)>,
+    buffers: Vec<(usize, Vec<u8>)>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct MigratedFile {
+    path: String,
+    mode: String,
+    offset: u64
+}
+
 pub struct FileMigrationProvider;
 impl MigrationProvider<FileNs> for FileMigrationProvider {
     fn start_migration(target: &FileNs) -> Option<Migration> {
-        if target.provider.handles.borrow().len() > 0 {
-            None
-        } else {
-            Some(Migration::empty())
-        }
+        Self::snapshot(target)
     }
 
-    fn complete_migration(target: &FileNs, _: &Migration) {
-        if target.provider.handles.borrow().len() > 0 {
-            panic!("handles.len() > 0");
+    fn snapshot(target: &FileNs) -> Option<Migration> {
+        use std::io::{Seek, SeekFrom};
+
+        // Files owned by an asynchronous operation have no known offset.
+        if target.provider.pending.get() > 0 {
+            return None;
+        }
+
+        let handles = target.provider.handles.borrow();
+        let mut migrated = Vec::new();
+        for (id, entry) in handles.iter() {
+            let offset = match entry.file {
+                Some(ref f) => match (&*f).seek(SeekFrom::Current(0)) {
+                    Ok(v) => v,
+                    Err(_) => return None
+                },
+                None => 0
+            };
+            migrated.push((id, MigratedFile {
+                path: entry.path.clone(),
+                mode: entry.mode.clone(),
+                offset: offset
+            }));
         }
+
+        Some(Migration::new(&FileMigrationInfo {
+            handles: migrated,
+            buffers: target.provider.buffers.borrow().iter()
+                .map(|(id, buf)| (id, buf.to_vec()))
+                .collect()
+        }))
     }
+
+    fn complete_migration(target: &FileNs, mig: &Migration) -> Result<(), String> {
+        let info: FileMigrationInfo = mig.extract()?;
+        let app = target.app.upgrade().unwrap();
+        let provider = &target.provider;
+
+        let handles = info.handles.into_iter()
+            .map(|(id, f)| {
+                let (file, lost) = match provider.reopen(&app, &f) {
+                    Ok(v) => (Some(v), None),
+                    Err(code) => {
+                        derror!(
+                            logger!(&app.name),
+                            "Unable to reopen {} (file {}) after migration: {:?}",
+                            f.path,
+                            id,
+                            code
+                        );
+                        (None, Some(code))
+                    }
+                };
+                (id, FileEntry {
+                    serial: provider.next_serial(),
+                    path: f.path,
+                    mode: f.mode,
+                    file: file,
+                    lost: lost,
+                    _slot: app.resources().track(Resource::Files)
+                })
+            })
+            .collect();
+        let handles = restore_slab(handles, || FileEntry {
+            serial: ::std::u64::MAX,
+            path: String::new(),
+            mode: String::new(),
+            file: None,
+            lost: None,
+            _slot: app.resources().track(Resource::Files)
+        })?;
+
+        let memory = app.host_memory();
+        let resources = app.resources();
+        let buffers = restore_slab(
+            info.buffers.into_iter()
+                .map(|(id, buf)| (id, memory.track(buf.into_boxed_slice(), resources.track(Resource::Buffers))))
+                .collect(),
+            || memory.track(Vec::new().into_boxed_slice(), resources.track(Resource::Buffers))
+        )?;
+
+        *provider.handles.borrow_mut() = handles;
+        *provider.buffers.borrow_mut() = buffers;
+
+        Ok(())
+    }
+
+    fn cancel_migration(_: &FileNs, _: &Migration) {
+        // `start_migration` leaves files and buffers in place.
+    }
+}
+
+struct FileEntry {
+    serial: u64,
+
+    // Path and mode as passed to `open`, so that the file can be reopened
+    // after a migration.
+    path: String,
+    mode: String,
+
+    // `None` while an asynchronous operation owns the file.
+    file: Option<File>,
+
+    // Set if the file could not be reopened after a migration.
+    lost: Option<ErrorCode>,
+
+    _slot: Slot
 }
 
 pub struct FileImpl {
-    handles: RefCell<Slab<File>>
+    handles: Rc<RefCell<Slab<FileEntry>>>,
+    buffers: Rc<RefCell<Slab<HostBuffer>>>,
+    pending: Rc<Cell<usize>>,
+    next_serial: Cell<u64>
 }
 
 impl FileImpl {
     pub fn new() -> FileImpl {
         FileImpl {
-            handles: RefCell::new(Slab::new())
+            handles: Rc::new(RefCell::new(Slab::new())),
+            buffers: Rc::new(RefCell::new(Slab::new())),
+            pending: Rc::new(Cell::new(0)),
+            next_serial: Cell::new(0)
+        }
+    }
+
+    /// Checks that the application may access `path`, and returns the host path to use
+    /// for the actual operation.
+    fn check_path(app: &ApplicationImpl, path: &str, write: bool, follow: bool) -> Result<PathBuf, ErrorCode> {
+        let mounts = app.mounts();
+        if mounts.is_empty() {
+            Self::check_host_path(app, path, write, follow)
+        } else {
+            Self::check_guest_path(app, &mounts, path, write, follow)
+        }
+    }
+
+    /// Maps a guest path to a host path through the application's mounts.
+    ///
+    /// Permissions are checked against the guest path, so that they don't depend on
+    /// where the mounts live on a particular host.
+    fn check_guest_path(
+        app: &ApplicationImpl,
+        mounts: &[MountConfig],
+        path: &str,
+        write: bool,
+        follow: bool
+    ) -> Result<PathBuf, ErrorCode> {
+        let guest = normalize_guest_path(path);
+
+        let any = if write {
+            app.check_permission(&AppPermission::FileOpenReadWriteAny)
+        } else {
+            app.check_permission(&AppPermission::FileOpenReadOnlyAny)
+                .or_else(|_| app.check_permission(&AppPermission::FileOpenReadWriteAny))
+        };
+        let allowed = any.is_ok() || app.has_permission_matching(|p| match *p {
+            AppPermission::FileRead(ref prefix) if !write => guest.starts_with(normalize_guest_path(prefix)),
+            AppPermission::FileReadWrite(ref prefix) => guest.starts_with(normalize_guest_path(prefix)),
+            _ => false
+        });
+        if !allowed {
+            derror!(
+                logger!(&app.name),
+                "No file permission covers {}{}",
+                guest.display(),
+                if write { " for writing" } else { "" }
+            );
+            return Err(ErrorCode::PermissionDenied);
+        }
+
+        map_guest_path(&app.name, mounts, &guest, write, follow)
+    }
+
+    /// Checks that the application may access the host path `path`, which is
+    /// how paths are interpreted when it has no mounts.
+    ///
+    /// With `FileOpenReadOnlyAny`/`FileOpenReadWriteAny`, `path` is used as is.
+    /// Otherwise, it is resolved with `resolve_path` and must lie within the prefix
+    /// of a `FileRead`/`FileReadWrite` permission.
+    fn check_host_path(app: &ApplicationImpl, path: &str, write: bool, follow: bool) -> Result<PathBuf, ErrorCode> {
+        let any = if write {
+            app.check_permission(&AppPermission::FileOpenReadWriteAny)
+        } else {
+            app.check_permission(&AppPermission::FileOpenReadOnlyAny)
+                .or_else(|_| app.check_permission(&AppPermission::FileOpenReadWriteAny))
+        };
+        if any.is_ok() {
+            return Ok(PathBuf::from(path));
+        }
+
+        let resolved = resolve_path(Path::new(path), follow);
+        let allowed = match resolved {
+            Ok(ref target) => app.has_permission_matching(|p| match *p {
+                AppPermission::FileRead(ref prefix) if !write => path_within(target, prefix),
+                AppPermission::FileReadWrite(ref prefix) => path_within(target, prefix),
+                _ => false
+            }),
+            Err(_) => false
+        };
+
+        if allowed {
+            Ok(resolved.unwrap())
+        } else {
+            if write {
+                derror!(
+                    logger!(&app.name),
+                    "FileOpenReadWriteAny or FileReadWrite permission covering {} is required",
+                    path
+                );
+            } else {
+                derror!(
+                    logger!(&app.name),
+                    "FileOpenReadOnlyAny, FileOpenReadWriteAny, FileRead or FileReadWrite permission covering {} is required",
+                    path
+                );
+            }
+            Err(ErrorCode::PermissionDenied)
+        }
+    }
+
+    fn with_file<T, F: FnOnce(&mut File) -> T>(&self, id: usize, f: F) -> Result<T, ErrorCode> {
+        let mut handles = self.handles.borrow_mut();
+        let entry = match handles.get_mut(id) {
+            Some(v) => v,
+            None => return Err(ErrorCode::InvalidInput)
+        };
+        if let Some(code) = entry.lost {
+            return Err(code);
+        }
+        match entry.file {
+            Some(ref mut file) => Ok(f(file)),
+            None => Err(ErrorCode::OngoingIo)
+        }
+    }
+
+    /// Takes a file out of the table for an asynchronous operation.
+    fn take_file(&self, id: usize) -> Result<(u64, File), ErrorCode> {
+        let mut handles = self.handles.borrow_mut();
+        let entry = match handles.get_mut(id) {
+            Some(v) => v,
+            None => return Err(ErrorCode::InvalidInput)
+        };
+        if let Some(code) = entry.lost {
+            return Err(code);
+        }
+        match entry.file.take() {
+            Some(file) => Ok((entry.serial, file)),
+            None => Err(ErrorCode::OngoingIo)
         }
     }
 
-    pub fn open(&self, ctx: InvokeContext) -> Option<Value> {
-        let path = ctx.extract_str(0, 1);
-        let mode = ctx.extract_str(2, 3);
+    fn next_serial(&self) -> u64 {
+        let serial = self.next_serial.get();
+        self.next_serial.set(serial + 1);
+        serial
+    }
+
+    /// Reopens a migrated file and seeks to its previous offset.
+    ///
+    /// Files are neither created nor truncated again, so a missing file is
+    /// reported as `NotFound`.
+    fn reopen(&self, app: &ApplicationImpl, f: &MigratedFile) -> Result<File, ErrorCode> {
+        use std::io::{Seek, SeekFrom};
+
         let mut opt = OpenOptions::new();
+        let mut need_write = false;
 
-        let app = ctx.app.upgrade().unwrap();
-        match app.check_permission(&AppPermission::FileOpenReadOnlyAny)
-            .or_else(|_| app.check_permission(&AppPermission::FileOpenReadWriteAny)) {
-                Ok(_) => {},
-                Err(_) => {
-                    derror!(
-                        logger!(&app.name),
-                        "FileOpenReadOnlyAny or FileOpenReadWriteAny permissions are required"
-                    );
-                    return Some(ErrorCode::PermissionDenied.to_ret());
-                }
+        for ch in f.mode.chars() {
+            match ch {
+                'r' => {
+                    opt.read(true);
+                },
+                'w' => {
+                    opt.write(true);
+                    need_write = true;
+                },
+                'a' => {
+                    opt.append(true);
+                    need_write = true;
+                },
+                _ => {}
             }
+        }
+
+        let path = Self::check_path(app, &f.path, need_write, true)?;
+        let mut file = opt.open(path).map_err(|e| ErrorCode::from(e.kind()))?;
+        file.seek(SeekFrom::Start(f.offset)).map_err(|e| ErrorCode::from(e.kind()))?;
+        Ok(file)
+    }
+
+    pub fn open(&self, ctx: InvokeContext) -> InvokeResult {
+        let path = ctx.extract_str(0, 1)?;
+        let mode = ctx.extract_str(2, 3)?;
+        let mut opt = OpenOptions::new();
 
         let mut need_write = false;
 
@@ -94,105 +524,605 @@ impl FileImpl {
                     opt.create_new(true);
                     need_write = true;
                 },
-                _ => return Some(ErrorCode::InvalidInput.to_ret())
+                _ => return Ok(Some(ErrorCode::InvalidInput.to_ret()))
             }
         }
 
-        if need_write {
-            match app.check_permission(&AppPermission::FileOpenReadWriteAny) {
-                Ok(_) => {},
-                Err(_) => {
-                    derror!(
-                        logger!(&app.name),
-                        "FileOpenReadWriteAny permission is required"
-                    );
-                    return Some(ErrorCode::PermissionDenied.to_ret());
-                }
-            }
-        }
+        let app = ctx.app.upgrade().unwrap();
+        let host_path = match Self::check_path(&app, path, need_write, true) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
 
-        let f = match opt.open(path) {
+        let slot = match app.acquire(Resource::Files) {
             Ok(v) => v,
-            Err(e) => return Some(ErrorCode::from(e.kind()).to_ret())
+            Err(code) => return Ok(Some(code.to_ret()))
         };
 
-        let id = self.handles.borrow_mut().insert(f);
+        let f = match opt.open(host_path) {
+            Ok(v) => v,
+            Err(e) => return Ok(Some(ErrorCode::from(e.kind()).to_ret()))
+        };
 
-        Some(Value::I32(id as i32))
+        let id = self.handles.borrow_mut().insert(FileEntry {
+            serial: self.next_serial(),
+            path: path.to_string(),
+            mode: mode.to_string(),
+            file: Some(f),
+            lost: None,
+            _slot: slot
+        });
+
+        Ok(Some(Value::I32(id as i32)))
     }
 
-    pub fn close(&self, ctx: InvokeContext) -> Option<Value> {
-        let id = ctx.args[0].get_i32().unwrap() as usize;
-        self.handles.borrow_mut().remove(id);
-        None
+    pub fn close(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        slab_remove(&mut self.handles.borrow_mut(), id)?;
+        Ok(None)
     }
 
-    pub fn read(&self, mut ctx: InvokeContext) -> Option<Value> {
+    pub fn read(&self, mut ctx: InvokeContext) -> InvokeResult {
         use std::io::Read;
 
-        let id = ctx.args[0].get_i32().unwrap() as usize;
-        let buf = ctx.extract_bytes_mut(1, 2);
-
-        let mut handles = self.handles.borrow_mut();
-        let file = handles.get_mut(id).unwrap();
+        let id = ctx.arg_usize(0)?;
+        let buf = ctx.extract_bytes_mut(1, 2)?;
 
-        Some(match file.read(buf) {
-            Ok(n) => Value::I32(n as i32),
-            Err(e) => ErrorCode::from(e.kind()).to_ret()
-        })
+        Ok(Some(match self.with_file(id, |file| file.read(buf)) {
+            Ok(Ok(n)) => Value::I32(n as i32),
+            Ok(Err(e)) => ErrorCode::from(e.kind()).to_ret(),
+            Err(code) => code.to_ret()
+        }))
     }
 
-    pub fn write(&self, ctx: InvokeContext) -> Option<Value> {
+    pub fn write(&self, ctx: InvokeContext) -> InvokeResult {
         use std::io::Write;
 
-        let id = ctx.args[0].get_i32().unwrap() as usize;
-        let buf = ctx.extract_bytes(1, 2);
+        let id = ctx.arg_usize(0)?;
+        let buf = ctx.extract_bytes(1, 2)?;
 
-        let mut handles = self.handles.borrow_mut();
-        let file = handles.get_mut(id).unwrap();
-
-        Some(match file.write(buf) {
-            Ok(n) => Value::I32(n as i32),
-            Err(e) => ErrorCode::from(e.kind()).to_ret()
-        })
+        Ok(Some(match self.with_file(id, |file| file.write(buf)) {
+            Ok(Ok(n)) => Value::I32(n as i32),
+            Ok(Err(e)) => ErrorCode::from(e.kind()).to_ret(),
+            Err(code) => code.to_ret()
+        }))
     }
 
-    pub fn flush(&self, ctx: InvokeContext) -> Option<Value> {
+    pub fn flush(&self, ctx: InvokeContext) -> InvokeResult {
         use std::io::Write;
 
-        let id = ctx.args[0].get_i32().unwrap() as usize;
+        let id = ctx.arg_usize(0)?;
 
-        let mut handles = self.handles.borrow_mut();
-        let file = handles.get_mut(id).unwrap();
-
-        Some(match file.flush() {
-            Ok(()) => ErrorCode::Success.to_ret(),
-            Err(e) => ErrorCode::from(e.kind()).to_ret()
-        })
+        Ok(Some(match self.with_file(id, |file| file.flush()) {
+            Ok(Ok(())) => ErrorCode::Success.to_ret(),
+            Ok(Err(e)) => ErrorCode::from(e.kind()).to_ret(),
+            Err(code) => code.to_ret()
+        }))
     }
 
-    pub fn seek(&self, ctx: InvokeContext) -> Option<Value> {
+    pub fn seek(&self, ctx: InvokeContext) -> InvokeResult {
         use std::io::{Seek, SeekFrom};
 
-        let id = ctx.args[0].get_i32().unwrap() as usize;
-        let from = ctx.args[1].get_i32().unwrap();
-        let offset = ctx.args[2].get_i64().unwrap();
+        let id = ctx.arg_usize(0)?;
+        let from = ctx.arg_i32(1)?;
+        let offset = ctx.arg_i64(2)?;
 
         let from = match from {
             0 => SeekFrom::Start(offset as u64),
             1 => SeekFrom::End(offset),
             2 => SeekFrom::Current(offset),
-            _ => return Some(
+            _ => return Ok(Some(
                 Value::I64(ErrorCode::InvalidInput.to_i32() as i64)
-            )
+            ))
         };
 
-        let mut handles = self.handles.borrow_mut();
-        let file = handles.get_mut(id).unwrap();
+        Ok(Some(Value::I64(match self.with_file(id, |file| file.seek(from)) {
+            Ok(Ok(v)) => v as i64,
+            Ok(Err(e)) => ErrorCode::from(e.kind()).to_i32() as i64,
+            Err(code) => code.to_i32() as i64
+        })))
+    }
+
+    /// Runs `f` with the file `id` on the I/O pool and calls back with
+    /// the value returned by `on_done`.
+    fn run_async<T, F, D>(&self, ctx: &InvokeContext, id: usize, cb_target: i32, cb_data: i32, f: F, on_done: D)
+        where T: Send + 'static,
+              F: FnOnce(&mut File) -> T + Send + 'static,
+              D: FnOnce(T) -> i32 + 'static
+    {
+        let app = ctx.app.upgrade().unwrap();
+
+        let slot = match app.acquire(Resource::PendingCallbacks) {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(
+                    cb_target,
+                    cb_data,
+                    code.to_i32()
+                );
+                return;
+            }
+        };
+
+        let (serial, mut file) = match self.take_file(id) {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(
+                    cb_target,
+                    cb_data,
+                    code.to_i32()
+                );
+                return;
+            }
+        };
+
+        let handles = self.handles.clone();
+        let pending = self.pending.clone();
+        let app_weak = ctx.app.clone();
+
+        pending.set(pending.get() + 1);
 
-        Some(Value::I64(match file.seek(from) {
-            Ok(v) => v as i64,
-            Err(e) => ErrorCode::from(e.kind()).to_i32() as i64
+        app.spawn_callback(
+            slot,
+            app.io_pool().spawn_fn(move || -> Result<_, ()> {
+                let ret = f(&mut file);
+                Ok((file, ret))
+            }).map(move |(file, ret)| {
+                pending.set(pending.get() - 1);
+
+                // The file may have been closed in the meantime.
+                if let Some(entry) = handles.borrow_mut().get_mut(id) {
+                    if entry.serial == serial {
+                        entry.file = Some(file);
+                    }
+                }
+
+                app_weak.upgrade().unwrap().invoke2(
+                    cb_target,
+                    cb_data,
+                    on_done(ret)
+                );
+            })
+        );
+    }
+
+    /// Reads at most `len` bytes on the I/O pool.
+    ///
+    /// The callback receives a buffer id for `take_buffer`, or an error code.
+    pub fn read_async(&self, ctx: InvokeContext) -> InvokeResult {
+        use std::io::Read;
+
+        let id = ctx.arg_usize(0)?;
+        let len = ctx.arg_usize(1)?;
+        let cb_target = ctx.arg_i32(2)?;
+        let cb_data = ctx.arg_i32(3)?;
+
+        // Reads are clamped to the host memory left to the application.
+        let app = ctx.app.upgrade().unwrap();
+        let reservation = match app.reserve_buffer(len) {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(cb_target, cb_data, code.to_i32());
+                return Ok(None);
+            }
+        };
+        let len = reservation.len();
+
+        let buffers = self.buffers.clone();
+
+        self.run_async(&ctx, id, cb_target, cb_data, move |file| {
+            let mut buf = vec! [ 0; len ];
+            file.read(&mut buf).map(|n| {
+                buf.truncate(n);
+                buf
+            })
+        }, move |ret| match ret {
+            Ok(buf) => buffers.borrow_mut().insert(reservation.fill(buf.into_boxed_slice())) as i32,
+            Err(e) => ErrorCode::from(e.kind()).to_i32()
+        });
+
+        Ok(None)
+    }
+
+    /// Writes the data on the I/O pool.
+    ///
+    /// The callback receives the number of bytes written, or an error code.
+    pub fn write_async(&self, ctx: InvokeContext) -> InvokeResult {
+        use std::io::Write;
+
+        let id = ctx.arg_usize(0)?;
+        let data = ctx.extract_bytes(1, 2)?.to_vec();
+        let cb_target = ctx.arg_i32(3)?;
+        let cb_data = ctx.arg_i32(4)?;
+
+        self.run_async(&ctx, id, cb_target, cb_data, move |file| {
+            file.write(&data)
+        }, |ret| match ret {
+            Ok(n) => n as i32,
+            Err(e) => ErrorCode::from(e.kind()).to_i32()
+        });
+
+        Ok(None)
+    }
+
+    pub fn take_buffer(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let buffer_id = ctx.arg_usize(0)?;
+        let target_ptr = ctx.arg_usize(1)?;
+        let max_len = ctx.arg_usize(2)?;
+
+        let mut buffers = self.buffers.borrow_mut();
+        let len = slab_get(&buffers, buffer_id)?.len();
+        if len > max_len {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
+        }
+
+        ctx.write_bytes(target_ptr, &buffers[buffer_id])?;
+        buffers.remove(buffer_id);
+
+        Ok(Some(Value::I32(len as i32)))
+    }
+
+    pub fn release_buffer(&self, ctx: InvokeContext) -> InvokeResult {
+        let buffer_id = ctx.arg_usize(0)?;
+        slab_remove(&mut self.buffers.borrow_mut(), buffer_id)?;
+        Ok(None)
+    }
+
+    /// Arguments: path, output buffer of at least `STAT_SIZE` bytes.
+    pub fn stat(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let app = ctx.app.upgrade().unwrap();
+        let path = match Self::check_path(&app, ctx.extract_str(0, 1)?, false, false) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
+
+        let meta = match fs::symlink_metadata(&path) {
+            Ok(v) => v,
+            Err(e) => return Ok(Some(ErrorCode::from(e.kind()).to_ret()))
+        };
+
+        let modified: i64 = meta.modified().ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| (d.as_secs() * 1000 + d.subsec_millis() as u64) as i64)
+            .unwrap_or(0);
+        let kind: u32 = if meta.file_type().is_file() {
+            0
+        } else if meta.file_type().is_dir() {
+            1
+        } else if meta.file_type().is_symlink() {
+            2
+        } else {
+            3
+        };
+
+        let mut record = [0u8; STAT_SIZE];
+        write_le(&mut record[0..8], meta.len());
+        write_le(&mut record[8..16], modified as u64);
+        write_le(&mut record[16..20], kind as u64);
+        write_le(&mut record[20..24], meta.permissions().readonly() as u64);
+
+        let out = ctx.extract_bytes_mut(2, 3)?;
+        if out.len() < STAT_SIZE {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
+        }
+        out[0..STAT_SIZE].copy_from_slice(&record);
+
+        Ok(Some(ErrorCode::Success.to_ret()))
+    }
+
+    /// Writes the names of all entries in a directory, each terminated by `\0`.
+    pub fn readdir(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let app = ctx.app.upgrade().unwrap();
+        let path = match Self::check_path(&app, ctx.extract_str(0, 1)?, false, true) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
+
+        let mut names: Vec<u8> = Vec::new();
+        let result = fs::read_dir(&path).and_then(|entries| {
+            for entry in entries {
+                names.extend_from_slice(entry?.file_name().to_string_lossy().as_bytes());
+                names.push(0);
+            }
+            Ok(())
+        });
+        if let Err(e) = result {
+            return Ok(Some(ErrorCode::from(e.kind()).to_ret()));
+        }
+
+        let out = ctx.extract_bytes_mut(2, 3)?;
+
+        // The caller should retry with a larger buffer if the returned
+        // length is greater than the length of its buffer.
+        if names.len() <= out.len() {
+            out[0..names.len()].copy_from_slice(&names);
+        }
+        Ok(Some(Value::I32(names.len() as i32)))
+    }
+
+    /// Arguments: path, whether to create missing parents.
+    pub fn mkdir(&self, ctx: InvokeContext) -> InvokeResult {
+        let recursive = ctx.arg_i32(2)? != 0;
+
+        let app = ctx.app.upgrade().unwrap();
+        let path = match Self::check_path(&app, ctx.extract_str(0, 1)?, true, false) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
+
+        let result = if recursive {
+            fs::create_dir_all(&path)
+        } else {
+            fs::create_dir(&path)
+        };
+
+        Ok(Some(match result {
+            Ok(()) => ErrorCode::Success.to_ret(),
+            Err(e) => ErrorCode::from(e.kind()).to_ret()
+        }))
+    }
+
+    /// Arguments: path, kind (`0` file, `1` empty directory, `2` directory and its contents).
+    pub fn remove(&self, ctx: InvokeContext) -> InvokeResult {
+        let kind = ctx.arg_i32(2)?;
+
+        let app = ctx.app.upgrade().unwrap();
+        let path = match Self::check_path(&app, ctx.extract_str(0, 1)?, true, false) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
+
+        let result = match kind {
+            0 => fs::remove_file(&path),
+            1 => fs::remove_dir(&path),
+            2 => fs::remove_dir_all(&path),
+            _ => return Ok(Some(ErrorCode::InvalidInput.to_ret()))
+        };
+
+        Ok(Some(match result {
+            Ok(()) => ErrorCode::Success.to_ret(),
+            Err(e) => ErrorCode::from(e.kind()).to_ret()
+        }))
+    }
+
+    pub fn rename(&self, ctx: InvokeContext) -> InvokeResult {
+        let app = ctx.app.upgrade().unwrap();
+        let paths = Self::check_path(&app, ctx.extract_str(0, 1)?, true, false)
+            .and_then(|from| Ok((from, Self::check_path(&app, ctx.extract_str(2, 3)?, true, false)?)));
+        let (from, to) = match paths {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
+
+        Ok(Some(match fs::rename(from, to) {
+            Ok(()) => ErrorCode::Success.to_ret(),
+            Err(e) => ErrorCode::from(e.kind()).to_ret()
+        }))
+    }
+
+    /// Sets the length of an open file.
+    pub fn truncate(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let len = ctx.arg_i64(1)?;
+
+        if len < 0 {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
+        }
+
+        Ok(Some(match self.with_file(id, |file| file.set_len(len as u64)) {
+            Ok(Ok(())) => ErrorCode::Success.to_ret(),
+            Ok(Err(e)) => ErrorCode::from(e.kind()).to_ret(),
+            Err(code) => code.to_ret()
         }))
     }
+
+    pub fn sync_all(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+
+        Ok(Some(match self.with_file(id, |file| file.sync_all()) {
+            Ok(Ok(())) => ErrorCode::Success.to_ret(),
+            Ok(Err(e)) => ErrorCode::from(e.kind()).to_ret(),
+            Err(code) => code.to_ret()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::symlink;
+
+    /// A scratch directory that is removed when dropped.
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> TestDir {
+            let dir = env::temp_dir().join(format!("ice-file-test-{}-{}", name, ::std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TestDir(fs::canonicalize(&dir).unwrap())
+        }
+
+        fn path(&self, rel: &str) -> PathBuf {
+            self.0.join(rel)
+        }
+
+        fn mkdir(&self, rel: &str) -> PathBuf {
+            let path = self.path(rel);
+            fs::create_dir_all(&path).unwrap();
+            path
+        }
+
+        fn touch(&self, rel: &str) -> PathBuf {
+            let path = self.path(rel);
+            File::create(&path).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn mount(guest: &str, host: &Path, readonly: bool) -> MountConfig {
+        MountConfig {
+            guest: guest.to_string(),
+            host: host.to_str().unwrap().to_string(),
+            readonly: readonly
+        }
+    }
+
+    fn map(mounts: &[MountConfig], path: &str, write: bool) -> Result<PathBuf, ErrorCode> {
+        map_guest_path("test", mounts, &normalize_guest_path(path), write, true)
+    }
+
+    fn is_denied<T>(result: Result<T, ErrorCode>) -> bool {
+        match result {
+            Err(ErrorCode::PermissionDenied) => true,
+            _ => false
+        }
+    }
+
+    #[test]
+    fn test_host_parent_escape() {
+        let dir = TestDir::new("host-parent");
+        let allowed = dir.mkdir("allowed");
+        dir.touch("secret");
+
+        let prefix = allowed.to_str().unwrap();
+        let inside = resolve_path(&allowed.join("new_file"), true).unwrap();
+        assert!(path_within(&inside, prefix));
+
+        let outside = resolve_path(&allowed.join("../secret"), true).unwrap();
+        assert!(!path_within(&outside, prefix));
+
+        // `..` after a component that doesn't exist can't be resolved safely.
+        assert!(resolve_path(&allowed.join("missing/../../secret"), true).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_host_symlink_escape() {
+        let dir = TestDir::new("host-symlink");
+        let allowed = dir.mkdir("allowed");
+        let secret = dir.touch("secret");
+        symlink(&secret, allowed.join("link")).unwrap();
+        symlink(dir.path(""), allowed.join("dir_link")).unwrap();
+
+        let prefix = allowed.to_str().unwrap();
+        assert!(!path_within(&resolve_path(&allowed.join("link"), true).unwrap(), prefix));
+        assert!(!path_within(&resolve_path(&allowed.join("dir_link/secret"), true).unwrap(), prefix));
+
+        // The link itself lies within the prefix.
+        assert!(path_within(&resolve_path(&allowed.join("link"), false).unwrap(), prefix));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_host_dangling_symlink() {
+        let dir = TestDir::new("host-dangling");
+        let allowed = dir.mkdir("allowed");
+        symlink(dir.path("not_yet"), allowed.join("link")).unwrap();
+
+        assert_eq!(
+            resolve_path(&allowed.join("link"), true).err().unwrap().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert!(resolve_path(&allowed.join("link/file"), true).is_err());
+
+        // Removing the link doesn't follow it.
+        assert_eq!(resolve_path(&allowed.join("link"), false).unwrap(), allowed.join("link"));
+    }
+
+    #[test]
+    fn test_normalize_guest_path() {
+        assert_eq!(normalize_guest_path("/data/a/../b"), Path::new("/data/b"));
+        assert_eq!(normalize_guest_path("../../etc/passwd"), Path::new("/etc/passwd"));
+        assert_eq!(normalize_guest_path("/data/../../.."), Path::new("/"));
+        assert_eq!(normalize_guest_path("./a//b/."), Path::new("/a/b"));
+    }
+
+    #[test]
+    fn test_guest_parent_escape() {
+        let dir = TestDir::new("guest-parent");
+        let data = dir.mkdir("data");
+        dir.touch("secret");
+        let mounts = [mount("/data", &data, false)];
+
+        // `..` stops at the guest root, which is outside of every mount.
+        match map(&mounts, "/data/../../secret", false) {
+            Err(ErrorCode::NotFound) => {},
+            _ => panic!("escaped the mount")
+        }
+        assert_eq!(map(&mounts, "/data/a/../b", false).ok().unwrap(), data.join("b"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_guest_symlink_escape() {
+        let dir = TestDir::new("guest-symlink");
+        let data = dir.mkdir("data");
+        let secret = dir.touch("secret");
+        symlink(&secret, data.join("link")).unwrap();
+        symlink(dir.path(""), data.join("dir_link")).unwrap();
+        let mounts = [mount("/data", &data, false)];
+
+        assert!(is_denied(map(&mounts, "/data/link", false)));
+        assert!(is_denied(map(&mounts, "/data/dir_link/secret", false)));
+
+        // The link itself lies within the mount.
+        assert_eq!(
+            map_guest_path("test", &mounts, &normalize_guest_path("/data/link"), false, false).ok().unwrap(),
+            data.join("link")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_guest_dangling_symlink() {
+        let dir = TestDir::new("guest-dangling");
+        let data = dir.mkdir("data");
+        symlink(dir.path("not_yet"), data.join("link")).unwrap();
+        let mounts = [mount("/data", &data, false)];
+
+        assert!(is_denied(map(&mounts, "/data/link", true)));
+        assert!(is_denied(map(&mounts, "/data/link/file", true)));
+    }
+
+    #[test]
+    fn test_nested_mounts() {
+        let dir = TestDir::new("nested");
+        let outer = dir.mkdir("outer");
+        let inner = dir.mkdir("inner");
+        let mounts = [
+            mount("/data", &outer, false),
+            mount("/data/cache", &inner, false)
+        ];
+
+        assert_eq!(map(&mounts, "/data/file", false).ok().unwrap(), outer.join("file"));
+        assert_eq!(map(&mounts, "/data/cache/file", false).ok().unwrap(), inner.join("file"));
+        assert_eq!(map(&mounts, "/data/cache2", false).ok().unwrap(), outer.join("cache2"));
+        match map(&mounts, "/other", false) {
+            Err(ErrorCode::NotFound) => {},
+            _ => panic!("mapped a path outside of every mount")
+        }
+    }
+
+    #[test]
+    fn test_readonly_mount() {
+        let dir = TestDir::new("readonly");
+        let data = dir.mkdir("data");
+        let scratch = dir.mkdir("scratch");
+        let mounts = [
+            mount("/data", &data, true),
+            mount("/data/scratch", &scratch, false)
+        ];
+
+        assert_eq!(map(&mounts, "/data/file", false).ok().unwrap(), data.join("file"));
+        assert!(is_denied(map(&mounts, "/data/file", true)));
+        assert_eq!(map(&mounts, "/data/scratch/file", true).ok().unwrap(), scratch.join("file"));
+    }
 }
@@ -0,0 +1,337 @@
+use std::cell::RefCell;
+
+use slab::Slab;
+use futures::sync::oneshot;
+
+use super::super::namespace::{InvokeContext, InvokeResult, slab_get, slab_get_mut, slab_remove};
+use super::super::error::ErrorCode;
+use super::super::event::Event;
+use super::super::app::Application;
+use super::super::host_memory::Reservation;
+use wasm_core::value::Value;
+
+decl_namespace!(
+    HttpNs,
+    "http",
+    HttpImpl,
+    method,
+    path,
+    header,
+    headers,
+    body,
+    set_status,
+    add_header,
+    write_body,
+    end
+);
+
+/// A request received by a host-side HTTP service.
+#[derive(Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+/// A response built by an application.
+#[derive(Debug, Clone)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>
+}
+
+impl HttpResponse {
+    pub fn new(status: u16) -> HttpResponse {
+        HttpResponse {
+            status: status,
+            headers: Vec::new(),
+            body: Vec::new()
+        }
+    }
+}
+
+struct PendingRequest {
+    request: HttpRequest,
+    response: HttpResponse,
+    sender: oneshot::Sender<HttpResponse>,
+
+    // Covers the request body and the response body written so far. Its
+    // `Buffers` slot caps the number of requests the application has yet to `end`.
+    memory: Reservation
+}
+
+impl PendingRequest {
+    fn write_body(&mut self, data: &[u8]) -> Result<(), ErrorCode> {
+        self.memory.grow(data.len())?;
+        self.response.body.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+/// Delivers an `HttpRequest` to the exported handler `entry` of an application.
+///
+/// The response is sent back through `sender`, which is dropped if the application
+/// doesn't handle the request.
+pub struct HttpRequestEvent {
+    inner: RefCell<Option<(HttpRequest, oneshot::Sender<HttpResponse>)>>,
+    entry: String
+}
+
+impl HttpRequestEvent {
+    pub fn new(
+        req: HttpRequest,
+        sender: oneshot::Sender<HttpResponse>,
+        entry: String
+    ) -> HttpRequestEvent {
+        HttpRequestEvent {
+            inner: RefCell::new(Some((req, sender))),
+            entry: entry
+        }
+    }
+}
+
+impl Event for HttpRequestEvent {
+    fn notify(&self, app: &Application) {
+        let (req, sender) = match self.inner.borrow_mut().take() {
+            Some(v) => v,
+            None => return
+        };
+
+        // Dropping the sender answers with 503.
+        let memory = match app.reserve_buffer(req.body.len()) {
+            Ok(ref r) if r.len() < req.body.len() => Err(ErrorCode::OutOfMemory),
+            other => other
+        };
+        let memory = match memory {
+            Ok(v) => v,
+            Err(code) => {
+                dwarning!(
+                    logger!(&app.name),
+                    "Unable to take an HTTP request: {:?}",
+                    code
+                );
+                return;
+            }
+        };
+
+        let id = match app.with_namespace("ice", "http", |ns: &HttpNs| {
+            ns.provider().add_request(req, sender, memory)
+        }) {
+            Some(v) => v,
+            None => return
+        };
+
+        if app.call_exported1(&self.entry, id as i32).is_none() {
+            derror!(
+                logger!(&app.name),
+                "HTTP handler `{}` is not exported",
+                self.entry
+            );
+            app.with_namespace("ice", "http", |ns: &HttpNs| {
+                ns.provider().requests.borrow_mut().remove(id);
+            });
+        }
+    }
+}
+
+pub struct HttpImpl {
+    requests: RefCell<Slab<PendingRequest>>
+}
+
+fn write_if_fits(ctx: &mut InvokeContext, ptr_arg_index: usize, len_arg_index: usize, data: &[u8]) -> InvokeResult {
+    let out = ctx.extract_bytes_mut(ptr_arg_index, len_arg_index)?;
+
+    // The caller should retry with a larger buffer if the returned
+    // length is greater than the length of its buffer.
+    if data.len() <= out.len() {
+        out[0..data.len()].copy_from_slice(data);
+    }
+    Ok(Some(Value::I32(data.len() as i32)))
+}
+
+/// Rejects anything that would allow injecting headers.
+fn is_valid_header(name: &str, value: &str) -> bool {
+    !name.is_empty() && !name.contains(|c| c == ':' || c == '\r' || c == '\n')
+        && !value.contains(|c| c == '\r' || c == '\n')
+}
+
+impl HttpImpl {
+    pub fn new() -> HttpImpl {
+        HttpImpl {
+            requests: RefCell::new(Slab::new())
+        }
+    }
+
+    /// Adds a request whose body is covered by `memory`.
+    pub fn add_request(
+        &self,
+        req: HttpRequest,
+        sender: oneshot::Sender<HttpResponse>,
+        memory: Reservation
+    ) -> usize {
+        self.requests.borrow_mut().insert(PendingRequest {
+            request: req,
+            response: HttpResponse::new(200),
+            sender: sender,
+            memory: memory
+        })
+    }
+
+    pub fn method(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let method = slab_get(&self.requests.borrow(), id)?.request.method.clone();
+
+        write_if_fits(&mut ctx, 1, 2, method.as_bytes())
+    }
+
+    pub fn path(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let path = slab_get(&self.requests.borrow(), id)?.request.path.clone();
+
+        write_if_fits(&mut ctx, 1, 2, path.as_bytes())
+    }
+
+    pub fn header(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let value = {
+            let name = ctx.extract_str(1, 2)?;
+            let requests = self.requests.borrow();
+            slab_get(&requests, id)?.request.headers.iter()
+                .find(|&&(ref k, _)| k.eq_ignore_ascii_case(name))
+                .map(|&(_, ref v)| v.clone())
+        };
+
+        match value {
+            Some(v) => write_if_fits(&mut ctx, 3, 4, v.as_bytes()),
+            None => Ok(Some(ErrorCode::NotFound.to_ret()))
+        }
+    }
+
+    /// Writes all headers in the format `Name: Value\r\n`.
+    pub fn headers(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+
+        let mut out = String::new();
+        for &(ref k, ref v) in &slab_get(&self.requests.borrow(), id)?.request.headers {
+            out.push_str(k);
+            out.push_str(": ");
+            out.push_str(v);
+            out.push_str("\r\n");
+        }
+
+        write_if_fits(&mut ctx, 1, 2, out.as_bytes())
+    }
+
+    pub fn body(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let requests = self.requests.borrow();
+
+        write_if_fits(&mut ctx, 1, 2, &slab_get(&requests, id)?.request.body)
+    }
+
+    pub fn set_status(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let status = ctx.arg_i32(1)?;
+
+        if status < 100 || status > 999 {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
+        }
+
+        slab_get_mut(&mut self.requests.borrow_mut(), id)?.response.status = status as u16;
+        Ok(Some(ErrorCode::Success.to_ret()))
+    }
+
+    pub fn add_header(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let name = ctx.extract_str(1, 2)?;
+        let value = ctx.extract_str(3, 4)?;
+
+        if !is_valid_header(name, value) {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
+        }
+
+        slab_get_mut(&mut self.requests.borrow_mut(), id)?.response.headers.push(
+            (name.to_string(), value.to_string())
+        );
+        Ok(Some(ErrorCode::Success.to_ret()))
+    }
+
+    pub fn write_body(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let data = ctx.extract_bytes(1, 2)?;
+
+        Ok(Some(match slab_get_mut(&mut self.requests.borrow_mut(), id)?.write_body(data) {
+            Ok(_) => Value::I32(data.len() as i32),
+            Err(code) => code.to_ret()
+        }))
+    }
+
+    pub fn end(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let req = slab_remove(&mut self.requests.borrow_mut(), id)?;
+
+        // The connection may have been closed in the meantime.
+        let _ = req.sender.send(req.response);
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::AppLimits;
+    use lssa::limits::{Resources, Resource};
+    use lssa::host_memory::HostMemory;
+    use futures::Future;
+
+    fn request(body: &[u8]) -> HttpRequest {
+        HttpRequest {
+            method: "POST".into(),
+            path: "/".into(),
+            headers: Vec::new(),
+            body: body.to_vec()
+        }
+    }
+
+    #[test]
+    fn test_body_memory() {
+        let resources = Resources::new(&AppLimits::default());
+        let memory = HostMemory::new(16);
+        let http = HttpImpl::new();
+
+        let (tx, rx) = oneshot::channel();
+        let id = http.add_request(
+            request(b"hello"),
+            tx,
+            memory.reserve(5, resources.track(Resource::Buffers)).unwrap()
+        );
+        assert_eq!(memory.used(), 5);
+
+        {
+            let mut requests = http.requests.borrow_mut();
+            let req = &mut requests[id];
+            req.write_body(b"0123456789").unwrap();
+            assert_eq!(memory.used(), 15);
+            assert_eq!(req.write_body(b"01").unwrap_err() as i32, ErrorCode::OutOfMemory as i32);
+            assert_eq!(req.response.body.len(), 10);
+        }
+
+        {
+            let req = http.requests.borrow_mut().remove(id);
+            req.sender.send(req.response).unwrap();
+        }
+        assert_eq!(memory.used(), 0);
+        assert_eq!(resources.usage().buffers, 0);
+        assert_eq!(rx.wait().unwrap().body, b"0123456789");
+    }
+
+    #[test]
+    fn test_header_injection() {
+        assert!(is_valid_header("X-Foo", "bar baz"));
+        assert!(!is_valid_header("", "bar"));
+        assert!(!is_valid_header("X-Foo: x", "bar"));
+        assert!(!is_valid_header("X-Foo", "bar\r\nSet-Cookie: a=b"));
+    }
+}
@@ -0,0 +1,571 @@
+use config::AppPermission;
+use super::super::namespace::{InvokeContext, InvokeResult, slab_get, slab_remove, MigrationProvider, Migration};
+use wasm_core::value::Value;
+use std::io;
+use std::io::Read;
+use std::rc::{Rc, Weak};
+use std::cell::RefCell;
+use std::time::Duration;
+use slab::Slab;
+
+use futures::Future;
+use tokio;
+use tokio::net::TcpStream;
+use httparse;
+use super::super::error::ErrorCode;
+use super::super::app::ApplicationImpl;
+use super::super::host_memory::HostBuffer;
+use super::super::limits::Resource;
+use super::tcp::{connect_any, with_timeout};
+
+/// Upper bound of a response, including its head. Responses are also bounded
+/// by the host memory left to the application when the request is made.
+const MAX_RESPONSE_LEN: usize = 16 * 1048576;
+
+/// Time a request may take from resolving the host to reading the response.
+const REQUEST_TIMEOUT_SECS: u64 = 30;
+
+decl_namespace_with_migration_provider!(
+    HttpClientNs,
+    "http_client",
+    HttpClientImpl,
+    HttpClientMigrationProvider,
+    request,
+    status,
+    headers,
+    body_len,
+    take_body,
+    release
+);
+
+#[derive(Serialize, Deserialize, Clone)]
+struct HttpClientMigrationInfo {
+    callbacks: Vec<RequestCallback>
+}
+
+pub struct HttpClientMigrationProvider;
+impl MigrationProvider<HttpClientNs> for HttpClientMigrationProvider {
+    fn start_migration(target: &HttpClientNs) -> Option<Migration> {
+        let mig = Self::snapshot(target);
+
+        // Requests in flight won't call back on this instance once their
+        // callbacks are taken out.
+        *target.provider.callbacks.borrow_mut() = Slab::new();
+        mig
+    }
+
+    fn snapshot(target: &HttpClientNs) -> Option<Migration> {
+        Some(Migration::new(&HttpClientMigrationInfo {
+            callbacks: target.provider.callbacks.borrow().iter()
+                .map(|(_, b)| *b)
+                .collect()
+        }))
+    }
+
+    fn complete_migration(target: &HttpClientNs, mig: &Migration) -> Result<(), String> {
+        let info: HttpClientMigrationInfo = mig.extract()?;
+        let app = target.provider.app.upgrade().unwrap();
+
+        for cb in &info.callbacks {
+            app.invoke2(
+                cb.cb_target,
+                cb.cb_data,
+                ErrorCode::Generic.to_i32()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+struct RequestCallback {
+    cb_target: i32,
+    cb_data: i32
+}
+
+/// A response waiting to be taken or released by the application.
+struct ClientResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: HostBuffer
+}
+
+/// A response as read off the connection.
+struct ParsedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Box<[u8]>
+}
+
+pub struct HttpClientImpl {
+    app: Weak<ApplicationImpl>,
+    responses: Rc<RefCell<Slab<ClientResponse>>>,
+    callbacks: Rc<RefCell<Slab<RequestCallback>>>
+}
+
+struct Url {
+    host: String,
+    port: u16,
+
+    /// `host[:port]` as written in the URL.
+    authority: String,
+    path: String
+}
+
+impl Url {
+    fn parse(url: &str) -> Option<Url> {
+        let rest = if url.starts_with("http://") {
+            &url["http://".len()..]
+        } else {
+            return None;
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/")
+        };
+        if authority.is_empty() || authority.contains('@') {
+            return None;
+        }
+
+        let (host, port) = match authority.rfind(':') {
+            // An IPv6 literal without a port, e.g. `[::1]`.
+            Some(i) if authority[i..].contains(']') => (authority, 80),
+            Some(i) => (&authority[..i], authority[i + 1..].parse().ok()?),
+            None => (authority, 80)
+        };
+        let host = host.trim_left_matches('[').trim_right_matches(']');
+
+        Some(Url {
+            host: host.to_string(),
+            port: port,
+            authority: authority.to_string(),
+            path: path.to_string()
+        })
+    }
+}
+
+fn write_if_fits(ctx: &mut InvokeContext, ptr_arg_index: usize, len_arg_index: usize, data: &[u8]) -> InvokeResult {
+    let out = ctx.extract_bytes_mut(ptr_arg_index, len_arg_index)?;
+
+    // The caller should retry with a larger buffer if the returned
+    // length is greater than the length of its buffer.
+    if data.len() <= out.len() {
+        out[0..data.len()].copy_from_slice(data);
+    }
+    Ok(Some(Value::I32(data.len() as i32)))
+}
+
+impl HttpClientImpl {
+    pub fn new(app: Weak<ApplicationImpl>) -> HttpClientImpl {
+        HttpClientImpl {
+            app: app,
+            responses: Rc::new(RefCell::new(Slab::new())),
+            callbacks: Rc::new(RefCell::new(Slab::new()))
+        }
+    }
+
+    fn check_url(&self, app: &ApplicationImpl, url: &Url) -> Result<(), ErrorCode> {
+        app.check_permission(&AppPermission::HttpRequestAny)
+            .or_else(|_| app.check_permission(&AppPermission::HttpRequest(url.host.clone())))
+            .or_else(|_| app.check_permission(&AppPermission::HttpRequest(url.authority.clone())))
+            .map_err(|_| {
+                derror!(
+                    logger!(&app.name),
+                    "HttpRequestAny or HttpRequest({}) permission is required",
+                    url.host
+                );
+                ErrorCode::PermissionDenied
+            })
+    }
+
+    /// Arguments: method, url, headers (`Name: Value\r\n` lines), body, callback.
+    ///
+    /// The callback receives a response id, or an error code.
+    pub fn request(&self, ctx: InvokeContext) -> InvokeResult {
+        let method = ctx.extract_str(0, 1)?.to_string();
+        let url = ctx.extract_str(2, 3)?.to_string();
+        let headers = ctx.extract_str(4, 5)?.to_string();
+        let body = ctx.extract_bytes(6, 7)?.to_vec();
+        let cb_target = ctx.arg_i32(8)?;
+        let cb_data = ctx.arg_i32(9)?;
+
+        let app = ctx.app.upgrade().unwrap();
+
+        let req = Url::parse(&url)
+            .ok_or(ErrorCode::InvalidInput)
+            .and_then(|url| {
+                self.check_url(&app, &url)?;
+                if method.is_empty() || method.contains(|c: char| !c.is_ascii_uppercase()) {
+                    return Err(ErrorCode::InvalidInput);
+                }
+                let req = build_request(&method, &url, &headers, &body)?;
+                Ok((url, req))
+            });
+        let (url, req) = match req {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(
+                    cb_target,
+                    cb_data,
+                    code.to_i32()
+                );
+                return Ok(None);
+            }
+        };
+
+        // The response is read into room reserved up front.
+        let slots = app.acquire(Resource::PendingCallbacks)
+            .and_then(|cb_slot| Ok((cb_slot, app.reserve_buffer(MAX_RESPONSE_LEN)?)));
+        let (cb_slot, reservation) = match slots {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(
+                    cb_target,
+                    cb_data,
+                    code.to_i32()
+                );
+                return Ok(None);
+            }
+        };
+        let limit = reservation.len();
+
+        let cb_id = self.callbacks.borrow_mut().insert(RequestCallback {
+            cb_target: cb_target,
+            cb_data: cb_data
+        });
+        let callbacks = self.callbacks.clone();
+        let responses = self.responses.clone();
+        let app_weak = ctx.app.clone();
+        let head_only = method == "HEAD";
+
+        let exchange = app.resolver().resolve(&url.host, url.port)
+            .and_then(connect_any)
+            .and_then(move |stream| exchange(stream, req, head_only, limit));
+
+        app.spawn_callback(
+            cb_slot,
+            with_timeout(exchange, Duration::from_secs(REQUEST_TIMEOUT_SECS))
+                .then(move |result| {
+                    if !callbacks.borrow().contains(cb_id) {
+                        return Ok(());
+                    }
+                    callbacks.borrow_mut().remove(cb_id);
+
+                    let ret = match result {
+                        Ok(resp) => responses.borrow_mut().insert(ClientResponse {
+                            status: resp.status,
+                            headers: resp.headers,
+                            body: reservation.fill(resp.body)
+                        }) as i32,
+                        Err(e) => {
+                            derror!(logger!("(app)"), "HTTP request error: {:?}", e);
+                            ErrorCode::from(e.kind()).to_i32()
+                        }
+                    };
+                    app_weak.upgrade().unwrap().invoke2(
+                        cb_target,
+                        cb_data,
+                        ret
+                    );
+                    Ok(())
+                })
+        );
+
+        Ok(None)
+    }
+
+    pub fn status(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        Ok(Some(Value::I32(slab_get(&self.responses.borrow(), id)?.status as i32)))
+    }
+
+    /// Writes all headers in the format `Name: Value\r\n`.
+    pub fn headers(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+
+        let mut out = String::new();
+        for &(ref k, ref v) in &slab_get(&self.responses.borrow(), id)?.headers {
+            out.push_str(k);
+            out.push_str(": ");
+            out.push_str(v);
+            out.push_str("\r\n");
+        }
+
+        write_if_fits(&mut ctx, 1, 2, out.as_bytes())
+    }
+
+    pub fn body_len(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        Ok(Some(Value::I32(slab_get(&self.responses.borrow(), id)?.body.len() as i32)))
+    }
+
+    /// Copies the body into guest memory and releases the response.
+    pub fn take_body(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        let target_ptr = ctx.arg_usize(1)?;
+        let max_len = ctx.arg_usize(2)?;
+
+        let mut responses = self.responses.borrow_mut();
+        let len = slab_get(&responses, id)?.body.len();
+        if len > max_len {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
+        }
+
+        ctx.write_bytes(target_ptr, &responses[id].body)?;
+        responses.remove(id);
+
+        Ok(Some(Value::I32(len as i32)))
+    }
+
+    pub fn release(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+        slab_remove(&mut self.responses.borrow_mut(), id)?;
+        Ok(None)
+    }
+}
+
+fn build_request(method: &str, url: &Url, headers: &str, body: &[u8]) -> Result<Vec<u8>, ErrorCode> {
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        url.path,
+        url.authority,
+        body.len()
+    );
+
+    for line in headers.split("\r\n").filter(|v| v.len() > 0) {
+        let mut parts = line.splitn(2, ':');
+        let (k, v) = match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) => (k.trim(), v.trim()),
+            _ => return Err(ErrorCode::InvalidInput)
+        };
+        if k.is_empty() || k.contains('\n') || v.contains('\n') {
+            return Err(ErrorCode::InvalidInput);
+        }
+
+        // Framing is controlled by the host.
+        if k.eq_ignore_ascii_case("Host") || k.eq_ignore_ascii_case("Connection")
+            || k.eq_ignore_ascii_case("Content-Length") || k.eq_ignore_ascii_case("Transfer-Encoding") {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", k, v));
+    }
+    head.push_str("\r\n");
+
+    let mut out = head.into_bytes();
+    out.extend_from_slice(body);
+    Ok(out)
+}
+
+/// Sends `req` and reads the response until the server closes the connection,
+/// failing if it is longer than `limit` bytes.
+fn exchange(
+    stream: TcpStream,
+    req: Vec<u8>,
+    head_only: bool,
+    limit: usize
+) -> impl Future<Item = ParsedResponse, Error = io::Error> {
+    tokio::io::write_all(stream, req)
+        .and_then(move |(stream, _)| {
+            tokio::io::read_to_end(stream.take(limit as u64 + 1), Vec::new())
+        })
+        .and_then(move |(_, data)| {
+            if data.len() > limit {
+                return Err(bad_response("response too large"));
+            }
+            parse_response(&data, head_only)
+        })
+}
+
+fn bad_response<E: Into<Box<::std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Parses a complete response, read until the server closed the connection.
+///
+/// Interim (1xx) responses are skipped. Responses to `HEAD` requests and
+/// 204 and 304 responses have no body whatever their headers say.
+fn parse_response(mut data: &[u8], head_only: bool) -> io::Result<ParsedResponse> {
+    let (status, headers) = loop {
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut resp = httparse::Response::new(&mut headers);
+
+        let head_len = match resp.parse(data) {
+            Ok(httparse::Status::Complete(n)) => n,
+            Ok(httparse::Status::Partial) => return Err(bad_response("incomplete response")),
+            Err(e) => return Err(bad_response(format!("{:?}", e)))
+        };
+        let status = resp.code.unwrap_or(0);
+        data = &data[head_len..];
+
+        if status >= 100 && status < 200 {
+            continue;
+        }
+
+        let headers: Vec<(String, String)> = resp.headers.iter()
+            .map(|h| (h.name.to_string(), String::from_utf8_lossy(h.value).into_owned()))
+            .collect();
+        break (status, headers);
+    };
+
+    if head_only || status == 204 || status == 304 {
+        return Ok(ParsedResponse {
+            status: status,
+            headers: headers,
+            body: Vec::new().into_boxed_slice()
+        });
+    }
+
+    let mut body = data;
+    let mut chunked = false;
+
+    for &(ref k, ref v) in &headers {
+        if k.eq_ignore_ascii_case("Transfer-Encoding") && v.to_lowercase().contains("chunked") {
+            chunked = true;
+        } else if k.eq_ignore_ascii_case("Content-Length") {
+            let len: usize = v.trim().parse().map_err(|_| bad_response("invalid content length"))?;
+            if len > body.len() {
+                return Err(bad_response("incomplete body"));
+            }
+            body = &body[..len];
+        }
+    }
+
+    let body = if chunked {
+        decode_chunked(body)?
+    } else {
+        body.to_vec()
+    };
+
+    Ok(ParsedResponse {
+        status: status,
+        headers: headers,
+        body: body.into_boxed_slice()
+    })
+}
+
+fn decode_chunked(mut data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+
+    loop {
+        let (start, size) = match httparse::parse_chunk_size(data) {
+            Ok(httparse::Status::Complete(v)) => v,
+            _ => return Err(bad_response("invalid chunk"))
+        };
+        let size = size as usize;
+        if size == 0 {
+            return Ok(out);
+        }
+        if data.len() < start + size + 2 {
+            return Err(bad_response("incomplete chunk"));
+        }
+        out.extend_from_slice(&data[start .. start + size]);
+        data = &data[start + size + 2 ..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+    use tokio::runtime::current_thread::Runtime;
+
+    /// Serves `response` to the first connection after reading the request.
+    fn serve_once(response: &'static [u8]) -> ::std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf);
+            stream.write_all(response).unwrap();
+        });
+        addr
+    }
+
+    fn fetch(response: &'static [u8], head_only: bool, limit: usize) -> io::Result<ParsedResponse> {
+        let addr = serve_once(response);
+        let req = b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n".to_vec();
+        Runtime::new().unwrap().block_on(
+            TcpStream::connect(&addr).and_then(move |stream| exchange(stream, req, head_only, limit))
+        )
+    }
+
+    #[test]
+    fn test_exchange() {
+        let resp = fetch(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Test: 1\r\n\r\nhello", false, 1024).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(&*resp.body, b"hello");
+        assert!(resp.headers.iter().any(|&(ref k, ref v)| k == "X-Test" && v == "1"));
+    }
+
+    #[test]
+    fn test_exchange_too_large() {
+        let err = fetch(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello", false, 16).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Accepts the connection and keeps it open without responding.
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = stream.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+            }
+        });
+
+        let req = b"GET / HTTP/1.1\r\nHost: test\r\n\r\n".to_vec();
+        let err = Runtime::new().unwrap().block_on(with_timeout(
+            TcpStream::connect(&addr).and_then(move |stream| exchange(stream, req, false, 1024)),
+            Duration::from_millis(100)
+        )).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_parse_chunked() {
+        let resp = parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n", false).unwrap();
+        assert_eq!(&*resp.body, b"abcde");
+    }
+
+    #[test]
+    fn test_parse_no_body() {
+        let resp = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n", true).unwrap();
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body.len(), 0);
+
+        let resp = parse_response(b"HTTP/1.1 204 No Content\r\nContent-Length: 100\r\n\r\n", false).unwrap();
+        assert_eq!(resp.status, 204);
+        assert_eq!(resp.body.len(), 0);
+
+        let resp = parse_response(b"HTTP/1.1 304 Not Modified\r\nTransfer-Encoding: chunked\r\n\r\n", false).unwrap();
+        assert_eq!(resp.status, 304);
+        assert_eq!(resp.body.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_interim() {
+        let resp = parse_response(b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\nContent-Length: 2\r\n\r\nok", false).unwrap();
+        assert_eq!(resp.status, 201);
+        assert_eq!(&*resp.body, b"ok");
+    }
+
+    #[test]
+    fn test_parse_incomplete() {
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc", false).is_err());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n", false).is_err());
+    }
+}
@@ -1,5 +1,5 @@
-use super::super::namespace::InvokeContext;
-use wasm_core::value::Value;
+use super::super::namespace::{InvokeContext, InvokeResult};
+use logging::Level;
 
 decl_namespace!(
     LoggingNs,
@@ -12,19 +12,21 @@ decl_namespace!(
 pub struct LoggingImpl;
 
 impl LoggingImpl {
-    pub fn info(&self, ctx: InvokeContext) -> Option<Value> {
-        let text = ctx.extract_str(0, 1);
+    pub fn info(&self, ctx: InvokeContext) -> InvokeResult {
+        let text = ctx.extract_str(0, 1)?;
         let app = ctx.app.upgrade().unwrap();
 
         dinfo!(logger!(&app.name), "{}", text);
-        None
+        app.container.app_logs.push(&app.name, Level::Info, text);
+        Ok(None)
     }
 
-    pub fn warning(&self, ctx: InvokeContext) -> Option<Value> {
-        let text = ctx.extract_str(0, 1);
+    pub fn warning(&self, ctx: InvokeContext) -> InvokeResult {
+        let text = ctx.extract_str(0, 1)?;
         let app = ctx.app.upgrade().unwrap();
 
         dwarning!(logger!(&app.name), "{}", text);
-        None
+        app.container.app_logs.push(&app.name, Level::Warning, text);
+        Ok(None)
     }
 }
@@ -1,4 +1,8 @@
 pub mod timer;
 pub mod logging;
 pub mod tcp;
+pub mod udp;
+pub mod dns;
 pub mod file;
+pub mod http;
+pub mod http_client;
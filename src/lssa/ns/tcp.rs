@@ -1,20 +1,27 @@
 use config::AppPermission;
-use super::super::namespace::{InvokeContext, MigrationProvider, Migration};
+use super::super::namespace::{InvokeContext, InvokeResult, slab_get_mut, slab_remove, MigrationProvider, Migration};
 use wasm_core::value::Value;
+use std::io;
 use std::net::SocketAddr;
 use std::rc::{Rc, Weak};
 use std::cell::RefCell;
 use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 use slab::Slab;
 
 use futures;
 use futures::{Future, Stream};
+use futures::future::{Either, Loop};
 use tokio;
-use tokio::prelude::AsyncRead;
+use tokio::prelude::{AsyncRead, AsyncWrite};
 use tokio_io::io::{ReadHalf, WriteHalf};
 use tokio::net::TcpStream;
+use tokio::timer::Delay;
 use super::super::error::ErrorCode;
 use super::super::app::ApplicationImpl;
+use super::super::host_memory::HostBuffer;
+use super::super::limits::{Resource, Slot};
+use dns::{Resolver, split_host_port};
 
 decl_namespace_with_migration_provider!(
     TcpNs,
@@ -24,7 +31,9 @@ decl_namespace_with_migration_provider!(
     release_buffer,
     take_buffer,
     connect,
+    connect_tls,
     listen,
+    listen_tls,
     read,
     write,
     destroy
@@ -33,12 +42,16 @@ decl_namespace_with_migration_provider!(
 #[derive(Serialize, Deserialize, Clone)]
 struct TcpMigrationInfo {
     rw_callbacks: Vec<RwCallback>,
-    listening_addresses: Vec<(String, RwCallback)>
+    listening_addresses: Vec<(String, ListenInfo)>
 }
 
 pub struct TcpMigrationProvider;
 impl MigrationProvider<TcpNs> for TcpMigrationProvider {
     fn start_migration(target: &TcpNs) -> Option<Migration> {
+        Self::snapshot(target)
+    }
+
+    fn snapshot(target: &TcpNs) -> Option<Migration> {
         Some(Migration::new(&TcpMigrationInfo {
             rw_callbacks: target.provider.rw_callbacks.borrow().iter()
                 .map(|(_, b)| *b)
@@ -49,14 +62,15 @@ impl MigrationProvider<TcpNs> for TcpMigrationProvider {
         }))
     }
 
-    fn complete_migration(target: &TcpNs, mig: &Migration) {
-        let info: TcpMigrationInfo = mig.extract().unwrap();
-        for (addr, cb) in &info.listening_addresses {
-            target.provider.listen_with_cb(
+    fn complete_migration(target: &TcpNs, mig: &Migration) -> Result<(), String> {
+        let info: TcpMigrationInfo = mig.extract()?;
+        for (addr, listener) in &info.listening_addresses {
+            let _ = target.provider.listen_with_cb(
                 target.provider.app.clone(),
                 addr,
-                cb.cb_target,
-                cb.cb_data
+                listener.cb.cb_target,
+                listener.cb.cb_data,
+                listener.tls
             );
         }
         let app = target.provider.app.upgrade().unwrap();
@@ -67,18 +81,64 @@ impl MigrationProvider<TcpNs> for TcpMigrationProvider {
                 -1
             );
         }
+
+        Ok(())
+    }
+
+    fn cancel_migration(_: &TcpNs, _: &Migration) {
+        // `start_migration` leaves listeners and streams running.
+    }
+}
+
+/// A plain or TLS stream that can be kept in the stream table.
+pub trait IoStream: AsyncRead + AsyncWrite {}
+impl<T: AsyncRead + AsyncWrite> IoStream for T {}
+
+type BoxedStream = Box<IoStream>;
+
+struct StreamEntry {
+    // Differs from that of any earlier stream with the same id, so that a read
+    // or write that completes after its stream was destroyed doesn't put its
+    // half into a new stream.
+    serial: u64,
+
+    // `None` while a read or write owns the half.
+    read: Option<ReadHalf<BoxedStream>>,
+    write: Option<WriteHalf<BoxedStream>>,
+
+    _slot: Slot
+}
+
+struct StreamTable {
+    entries: Slab<StreamEntry>,
+    next_serial: u64
+}
+
+impl StreamTable {
+    fn new() -> StreamTable {
+        StreamTable {
+            entries: Slab::new(),
+            next_serial: 0
+        }
+    }
+
+    /// Gives a half back to the stream it was taken from, unless that
+    /// stream has been destroyed in the meantime.
+    fn put_back<F: FnOnce(&mut StreamEntry)>(&mut self, stream_id: usize, serial: u64, f: F) {
+        if let Some(entry) = self.entries.get_mut(stream_id) {
+            if entry.serial == serial {
+                f(entry);
+            }
+        }
     }
 }
 
 pub struct TcpImpl {
     app: Weak<ApplicationImpl>,
-    streams: Rc<RefCell<Slab<(
-        Option<ReadHalf<TcpStream>>,
-        Option<WriteHalf<TcpStream>>
-    )>>>,
-    buffers: Rc<RefCell<Slab<Box<[u8]>>>>,
+    streams: Rc<RefCell<StreamTable>>,
+    buffers: Rc<RefCell<Slab<HostBuffer>>>,
     rw_callbacks: Rc<RefCell<Slab<RwCallback>>>,
-    listening: Rc<RefCell<BTreeMap<String, RwCallback>>>
+    listening: Rc<RefCell<BTreeMap<String, ListenInfo>>>
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -87,17 +147,49 @@ struct RwCallback {
     cb_data: i32
 }
 
+#[derive(Serialize, Deserialize, Copy, Clone)]
+struct ListenInfo {
+    cb: RwCallback,
+    tls: bool
+}
+
+fn insert_stream(streams: &RefCell<StreamTable>, stream: BoxedStream, slot: Slot) -> usize {
+    let (rh, wh) = stream.split();
+    let mut streams = streams.borrow_mut();
+    let serial = streams.next_serial;
+    streams.next_serial += 1;
+    streams.entries.insert(StreamEntry {
+        serial: serial,
+        read: Some(rh),
+        write: Some(wh),
+        _slot: slot
+    })
+}
+
 impl TcpImpl {
     pub fn new(app: Weak<ApplicationImpl>) -> TcpImpl {
         TcpImpl {
             app: app,
-            streams: Rc::new(RefCell::new(Slab::new())),
+            streams: Rc::new(RefCell::new(StreamTable::new())),
             buffers: Rc::new(RefCell::new(Slab::new())),
             rw_callbacks: Rc::new(RefCell::new(Slab::new())),
             listening: Rc::new(RefCell::new(BTreeMap::new()))
         }
     }
 
+    /// Adds an established stream and returns its id.
+    ///
+    /// Fails with `ResourceExhausted` if the application has as many streams
+    /// as it may.
+    pub fn add_stream<S: AsyncRead + AsyncWrite + 'static>(&self, stream: S) -> Result<usize, ErrorCode> {
+        let slot = self.app.upgrade().unwrap().acquire(Resource::TcpStreams)?;
+        Ok(insert_stream(&self.streams, Box::new(stream), slot))
+    }
+
+    pub fn remove_stream(&self, stream_id: usize) {
+        self.streams.borrow_mut().entries.remove(stream_id);
+    }
+
     fn do_connect(
         &self,
         weak_app: Weak<ApplicationImpl>,
@@ -109,6 +201,8 @@ impl TcpImpl {
         let app_weak2 = weak_app.clone();
 
         let addr1 = addr.clone();
+        let addr2 = addr.clone();
+        let resolver = weak_app.upgrade().unwrap().resolver().clone();
 
         futures::future::lazy(move || {
             let app = app_weak1.upgrade().unwrap();
@@ -122,11 +216,10 @@ impl TcpImpl {
                     );
                     ErrorCode::PermissionDenied
                 })
-                .and_then(|_| -> Result<SocketAddr, ErrorCode> {
-                    addr1.parse().map_err(|_| ErrorCode::InvalidInput)
-                })
-        }).and_then(move |addr| {
-            tokio::net::TcpStream::connect(&addr)
+        }).and_then(move |_| {
+            resolve(&resolver, &addr2)
+        }).and_then(move |addrs| {
+            connect_any(addrs)
                 .map_err(|e| {
                     derror!(logger!("(app)"), "Connect error: {:?}", e);
                     ErrorCode::Generic
@@ -134,23 +227,82 @@ impl TcpImpl {
         })
     }
 
-    pub fn connect(&self, ctx: InvokeContext) -> Option<Value> {
-        let addr = ctx.extract_str(0, 1);
-        let cb_target = ctx.args[2].get_i32().unwrap();
-        let cb_data = ctx.args[3].get_i32().unwrap();
+    pub fn connect(&self, ctx: InvokeContext) -> InvokeResult {
+        self.connect_with(ctx, false)
+    }
+
+    /// Like `connect`, but performs a TLS handshake, verifying the
+    /// certificate against the host part of the address.
+    pub fn connect_tls(&self, ctx: InvokeContext) -> InvokeResult {
+        self.connect_with(ctx, true)
+    }
+
+    fn connect_with(&self, ctx: InvokeContext, tls: bool) -> InvokeResult {
+        let addr = ctx.extract_str(0, 1)?;
+        let cb_target = ctx.arg_i32(2)?;
+        let cb_data = ctx.arg_i32(3)?;
+
+        let connector = if tls {
+            let app = ctx.app.upgrade().unwrap();
+            let domain = split_host_port(addr)
+                .map(|(host, _)| host)
+                .unwrap_or_default();
+
+            match app.tls().connector() {
+                Ok(v) => Some((v, domain)),
+                Err(e) => {
+                    derror!(
+                        logger!(&app.name),
+                        "Unable to set up TLS: {:?}",
+                        e
+                    );
+                    app.invoke2(
+                        cb_target,
+                        cb_data,
+                        ErrorCode::Generic.to_i32()
+                    );
+                    return Ok(None);
+                }
+            }
+        } else {
+            None
+        };
+
+        let app = ctx.app.upgrade().unwrap();
+        let slots = app.acquire(Resource::PendingCallbacks)
+            .and_then(|cb_slot| Ok((cb_slot, app.acquire(Resource::TcpStreams)?)));
+        let (cb_slot, stream_slot) = match slots {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(
+                    cb_target,
+                    cb_data,
+                    code.to_i32()
+                );
+                return Ok(None);
+            }
+        };
 
         let streams = self.streams.clone();
         let app_weak1 = ctx.app.clone();
         let app_weak2 = ctx.app.clone();
 
-        tokio::executor::current_thread::spawn(
+        app.spawn_callback(
+            cb_slot,
             self.do_connect(ctx.app.clone(), addr)
+                .and_then(move |stream| match connector {
+                    Some((connector, domain)) => Either::A(
+                        connector.connect(&domain, stream)
+                            .map(|s| Box::new(s) as BoxedStream)
+                            .map_err(|e| {
+                                derror!(logger!("(app)"), "TLS handshake error: {:?}", e);
+                                ErrorCode::Generic
+                            })
+                    ),
+                    None => Either::B(futures::future::ok(Box::new(stream) as BoxedStream))
+                })
                 .and_then(move |stream| {
-                    let (rh, wh) = stream.split();
-                    let stream_id = streams.borrow_mut().insert((
-                        Some(rh),
-                        Some(wh)
-                    ));
+                    let stream_id = insert_stream(&streams, stream, stream_slot);
                     app_weak1.upgrade().unwrap().invoke2(
                         cb_target,
                         cb_data,
@@ -168,7 +320,7 @@ impl TcpImpl {
                 })
         );
 
-        None
+        Ok(None)
     }
 
     fn do_listen(
@@ -181,6 +333,8 @@ impl TcpImpl {
         let app_weak2 = weak_app.clone();
 
         let addr1 = addr.clone();
+        let addr2 = addr.clone();
+        let resolver = weak_app.upgrade().unwrap().resolver().clone();
 
         futures::future::lazy(move || {
             let app = app_weak1.upgrade().unwrap();
@@ -194,11 +348,10 @@ impl TcpImpl {
                     );
                     ErrorCode::PermissionDenied
                 })
-                .and_then(|_| -> Result<SocketAddr, ErrorCode> {
-                    addr1.parse().map_err(|_| ErrorCode::InvalidInput)
-                })
-        }).and_then(move |addr| {
-            tokio::net::TcpListener::bind(&addr)
+        }).and_then(move |_| {
+            resolve(&resolver, &addr2)
+        }).and_then(move |addrs| {
+            tokio::net::TcpListener::bind(&addrs[0])
                 .map_err(move |e| {
                     let app = app_weak2.upgrade().unwrap();
                     derror!(
@@ -217,32 +370,87 @@ impl TcpImpl {
         })
     }
 
-    fn listen_with_cb(&self, app: Weak<ApplicationImpl>, addr0: &str, cb_target: i32, cb_data: i32) {
+    fn listen_with_cb(
+        &self,
+        app: Weak<ApplicationImpl>,
+        addr0: &str,
+        cb_target: i32,
+        cb_data: i32,
+        tls: bool
+    ) -> Result<(), ErrorCode> {
+        // The entry belongs to the listener that is already running.
+        if self.listening.borrow().contains_key(addr0) {
+            return Err(ErrorCode::BindFail);
+        }
+
+        let slot = app.upgrade().unwrap().acquire(Resource::Listeners)?;
         let streams = self.streams.clone();
 
-        self.listening.borrow_mut().insert(addr0.into(), RwCallback {
-            cb_target: cb_target,
-            cb_data: cb_data
+        let acceptor = if tls {
+            let app = app.upgrade().unwrap();
+            match app.tls().acceptor() {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    derror!(
+                        logger!(&app.name),
+                        "Unable to set up TLS: {:?}",
+                        e
+                    );
+                    return Err(ErrorCode::Generic);
+                }
+            }
+        } else {
+            None
+        };
+
+        self.listening.borrow_mut().insert(addr0.into(), ListenInfo {
+            cb: RwCallback {
+                cb_target: cb_target,
+                cb_data: cb_data
+            },
+            tls: tls
         });
         let addr = addr0.to_string();
         let listening = self.listening.clone();
         let app_weak1 = app.clone();
+        let owner = app.upgrade().unwrap();
 
-        tokio::executor::current_thread::spawn(
+        owner.spawn(
             self.do_listen(app, addr0)
                 .and_then(move |feed| {
                     feed.for_each(move |stream| {
-                        let (rh, wh) = stream.split();
-                        let stream_id = streams.borrow_mut().insert((
-                            Some(rh),
-                            Some(wh)
-                        ));
-
-                        app_weak1.upgrade().unwrap().invoke2(
-                            cb_target,
-                            cb_data,
-                            stream_id as _
-                        );
+                        let streams = streams.clone();
+                        let app_weak1 = app_weak1.clone();
+                        let owner = app_weak1.upgrade().unwrap();
+                        let on_accept = move |stream: BoxedStream| {
+                            let app = app_weak1.upgrade().unwrap();
+
+                            // The connection is closed right away.
+                            let slot = match app.acquire(Resource::TcpStreams) {
+                                Ok(v) => v,
+                                Err(_) => return
+                            };
+                            let stream_id = insert_stream(&streams, stream, slot);
+
+                            app.invoke2(
+                                cb_target,
+                                cb_data,
+                                stream_id as _
+                            );
+                        };
+
+                        match acceptor {
+                            // The handshake runs on its own so that a slow client
+                            // doesn't hold up the listener.
+                            Some(ref acceptor) => owner.spawn(
+                                acceptor.accept(stream)
+                                    .map(move |s| on_accept(Box::new(s)))
+                                    .map_err(|e| {
+                                        dwarning!(logger!("(app)"), "TLS handshake error: {:?}", e);
+                                    })
+                            ),
+                            None => on_accept(Box::new(stream))
+                        }
                         Ok(())
                     }).map(|_| ())
                 })
@@ -250,57 +458,81 @@ impl TcpImpl {
                     Ok(())
                 })
                 .then(move |v| {
-                    listening.borrow_mut().remove(&addr).unwrap();
+                    listening.borrow_mut().remove(&addr);
+                    drop(slot);
                     v
                 })
         );
+        Ok(())
     }
 
-    pub fn listen(&self, ctx: InvokeContext) -> Option<Value> {
-        let addr0 = ctx.extract_str(0, 1);
-        let cb_target = ctx.args[2].get_i32().unwrap();
-        let cb_data = ctx.args[3].get_i32().unwrap();
+    pub fn listen(&self, ctx: InvokeContext) -> InvokeResult {
+        let addr0 = ctx.extract_str(0, 1)?;
+        let cb_target = ctx.arg_i32(2)?;
+        let cb_data = ctx.arg_i32(3)?;
 
-        self.listen_with_cb(ctx.app.clone(), addr0, cb_target, cb_data);
-        Some(ErrorCode::Success.to_ret())
+        Ok(Some(match self.listen_with_cb(ctx.app.clone(), addr0, cb_target, cb_data, false) {
+            Ok(_) => ErrorCode::Success.to_ret(),
+            Err(code) => code.to_ret()
+        }))
     }
 
-    pub fn destroy(&self, ctx: InvokeContext) -> Option<Value> {
-        let stream_id = ctx.args[0].get_i32().unwrap() as usize;
-        self.streams.borrow_mut().remove(stream_id);
-        None
+    /// Like `listen`, but performs a TLS handshake on each accepted stream
+    /// with the certificate and key from the application's `tls` config.
+    pub fn listen_tls(&self, ctx: InvokeContext) -> InvokeResult {
+        let addr0 = ctx.extract_str(0, 1)?;
+        let cb_target = ctx.arg_i32(2)?;
+        let cb_data = ctx.arg_i32(3)?;
+
+        Ok(Some(match self.listen_with_cb(ctx.app.clone(), addr0, cb_target, cb_data, true) {
+            Ok(_) => ErrorCode::Success.to_ret(),
+            Err(code) => code.to_ret()
+        }))
+    }
+
+    pub fn destroy(&self, ctx: InvokeContext) -> InvokeResult {
+        let stream_id = ctx.arg_usize(0)?;
+        slab_remove(&mut self.streams.borrow_mut().entries, stream_id)?;
+        Ok(None)
     }
 
-    pub fn release_buffer(&self, ctx: InvokeContext) -> Option<Value> {
-        let buffer_id = ctx.args[0].get_i32().unwrap() as usize;
-        self.buffers.borrow_mut().remove(buffer_id);
-        None
+    pub fn release_buffer(&self, ctx: InvokeContext) -> InvokeResult {
+        let buffer_id = ctx.arg_usize(0)?;
+        slab_remove(&mut self.buffers.borrow_mut(), buffer_id)?;
+        Ok(None)
     }
 
-    pub fn take_buffer(&self, ctx: InvokeContext) -> Option<Value> {
-        let buffer_id = ctx.args[0].get_i32().unwrap() as usize;
-        let target_ptr = ctx.args[1].get_i32().unwrap() as usize;
-        let max_len = ctx.args[2].get_i32().unwrap() as usize;
+    pub fn take_buffer(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let buffer_id = ctx.arg_usize(0)?;
+        let target_ptr = ctx.arg_usize(1)?;
+        let max_len = ctx.arg_usize(2)?;
 
-        let buf = self.buffers.borrow_mut().remove(buffer_id);
+        let mut buffers = self.buffers.borrow_mut();
+        let len = slab_get_mut(&mut buffers, buffer_id)?.len();
 
-        if buf.len() > max_len {
-            panic!("take_buffer: buf.len() > max_len");
+        // The buffer is kept so that the caller can retry with a larger one.
+        if len > max_len {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
         }
 
-        let target_mem = &mut ctx.state.get_memory_mut()[target_ptr .. target_ptr + buf.len()];
-        target_mem.copy_from_slice(&buf);
+        ctx.write_bytes(target_ptr, &buffers[buffer_id])?;
+        buffers.remove(buffer_id);
 
-        Some(Value::I32(buf.len() as i32))
+        Ok(Some(Value::I32(len as i32)))
     }
 
-    pub fn read(&self, ctx: InvokeContext) -> Option<Value> {
-        let stream_id = ctx.args[0].get_i32().unwrap() as usize;
-        let read_len = ctx.args[1].get_i32().unwrap() as usize;
-        let cb_target = ctx.args[2].get_i32().unwrap();
-        let cb_data = ctx.args[3].get_i32().unwrap();
+    pub fn read(&self, ctx: InvokeContext) -> InvokeResult {
+        let stream_id = ctx.arg_usize(0)?;
+        let read_len = ctx.arg_usize(1)?;
+        let cb_target = ctx.arg_i32(2)?;
+        let cb_data = ctx.arg_i32(3)?;
 
-        let conn = match self.streams.borrow_mut()[stream_id].0.take() {
+        let taken = {
+            let mut streams = self.streams.borrow_mut();
+            let entry = slab_get_mut(&mut streams.entries, stream_id)?;
+            entry.read.take().map(|conn| (entry.serial, conn))
+        };
+        let (serial, conn) = match taken {
             Some(v) => v,
             None => {
                 ctx.app.upgrade().unwrap().invoke2(
@@ -308,20 +540,35 @@ impl TcpImpl {
                     cb_data,
                     ErrorCode::OngoingIo.to_i32()
                 );
-                return None;
+                return Ok(None);
+            }
+        };
+        let app = ctx.app.upgrade().unwrap();
+
+        // Reads are clamped to the host memory left to the application.
+        let slots = app.acquire(Resource::PendingCallbacks)
+            .and_then(|cb_slot| Ok((cb_slot, app.reserve_buffer(read_len)?)));
+        let (cb_slot, reservation) = match slots {
+            Ok(v) => v,
+            Err(code) => {
+                self.streams.borrow_mut().put_back(stream_id, serial, |entry| entry.read = Some(conn));
+                app.invoke2(cb_target, cb_data, code.to_i32());
+                return Ok(None);
             }
         };
+
         let streams = self.streams.clone();
         let buffers = self.buffers.clone();
 
         let app_weak1 = ctx.app.clone();
         let app_weak2 = ctx.app.clone();
 
-        tokio::executor::current_thread::spawn(
-            AsyncReadFuture::new(conn, read_len)
+        app.spawn_callback(
+            cb_slot,
+            AsyncReadFuture::new(conn, reservation.len())
                 .map(move |(stream, data)| {
-                    streams.borrow_mut()[stream_id].0 = Some(stream);
-                    let buffer_id = buffers.borrow_mut().insert(data);
+                    streams.borrow_mut().put_back(stream_id, serial, |entry| entry.read = Some(stream));
+                    let buffer_id = buffers.borrow_mut().insert(reservation.fill(data));
 
                     app_weak1.upgrade().unwrap().invoke2(
                         cb_target,
@@ -339,16 +586,30 @@ impl TcpImpl {
                 })
         );
 
-        None
+        Ok(None)
     }
 
-    pub fn write(&self, ctx: InvokeContext) -> Option<Value> {
-        let stream_id = ctx.args[0].get_i32().unwrap() as usize;
-        let data = ctx.extract_bytes(1, 2);
-        let cb_target = ctx.args[3].get_i32().unwrap();
-        let cb_data = ctx.args[4].get_i32().unwrap();
+    pub fn write(&self, ctx: InvokeContext) -> InvokeResult {
+        let stream_id = ctx.arg_usize(0)?;
+        let data = ctx.extract_bytes(1, 2)?;
+        let cb_target = ctx.arg_i32(3)?;
+        let cb_data = ctx.arg_i32(4)?;
+
+        let app = ctx.app.upgrade().unwrap();
+        let cb_slot = match app.acquire(Resource::PendingCallbacks) {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(cb_target, cb_data, code.to_i32());
+                return Ok(None);
+            }
+        };
 
-        let conn = match self.streams.borrow_mut()[stream_id].1.take() {
+        let taken = {
+            let mut streams = self.streams.borrow_mut();
+            let entry = slab_get_mut(&mut streams.entries, stream_id)?;
+            entry.write.take().map(|conn| (entry.serial, conn))
+        };
+        let (serial, conn) = match taken {
             Some(v) => v,
             None => {
                 ctx.app.upgrade().unwrap().invoke2(
@@ -356,7 +617,7 @@ impl TcpImpl {
                     cb_data,
                     ErrorCode::OngoingIo.to_i32()
                 );
-                return None;
+                return Ok(None);
             }
         };
         let streams = self.streams.clone();
@@ -366,9 +627,10 @@ impl TcpImpl {
 
         let data_len = data.len();
 
-        tokio::executor::current_thread::spawn(
+        app.spawn_callback(
+            cb_slot,
             tokio::io::write_all(conn, data.to_vec()).map(move |(a, _)| {
-                streams.borrow_mut()[stream_id].1 = Some(a);
+                streams.borrow_mut().put_back(stream_id, serial, |entry| entry.write = Some(a));
 
                 app_weak1.upgrade().unwrap().invoke2(
                     cb_target,
@@ -386,10 +648,57 @@ impl TcpImpl {
             })
         );
 
-        None
+        Ok(None)
     }
 }
 
+fn resolve(resolver: &Resolver, addr: &str) -> impl Future<Item = Vec<SocketAddr>, Error = ErrorCode> {
+    let addr = addr.to_string();
+
+    resolver.resolve_addr(&addr).map_err(move |e| {
+        derror!(logger!("(app)"), "Unable to resolve {}: {:?}", addr, e);
+        ErrorCode::from(e.kind())
+    })
+}
+
+/// Fails with `TimedOut` if `f` doesn't complete within `timeout`.
+///
+/// Must run on a runtime that has a timer; without one it fails with
+/// the error of the timer instead.
+pub fn with_timeout<F: Future<Error = io::Error>>(f: F, timeout: Duration) -> impl Future<Item = F::Item, Error = io::Error> {
+    f.select2(Delay::new(Instant::now() + timeout)).then(|result| match result {
+        Ok(Either::A((v, _))) => Ok(v),
+        Err(Either::A((e, _))) => Err(e),
+        Ok(Either::B(_)) => Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out"
+        )),
+        Err(Either::B((e, _))) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("timer error: {}", e)
+        ))
+    })
+}
+
+/// Connects to each of `addrs` in turn until one succeeds.
+pub fn connect_any(addrs: Vec<SocketAddr>) -> impl Future<Item = TcpStream, Error = io::Error> {
+    futures::future::loop_fn(
+        (addrs.into_iter(), None),
+        |(mut addrs, last_err): (::std::vec::IntoIter<SocketAddr>, Option<io::Error>)| {
+            let addr = match addrs.next() {
+                Some(v) => v,
+                None => return Either::A(futures::future::err(last_err.unwrap_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")
+                })))
+            };
+            Either::B(TcpStream::connect(&addr).then(move |result| match result {
+                Ok(stream) => Ok(Loop::Break(stream)),
+                Err(e) => Ok(Loop::Continue((addrs, Some(e))))
+            }))
+        }
+    )
+}
+
 pub struct AsyncReadFuture<T: AsyncRead> {
     inner: Option<T>,
     buf: Vec<u8>
@@ -426,3 +735,31 @@ impl<T: AsyncRead> Future for AsyncReadFuture<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use config::AppLimits;
+    use lssa::limits::Resources;
+
+    #[test]
+    fn test_stale_half() {
+        let resources = Resources::new(&AppLimits::default());
+        let streams = RefCell::new(StreamTable::new());
+        let stream = || Box::new(Cursor::new(Vec::new())) as BoxedStream;
+
+        let old_id = insert_stream(&streams, stream(), resources.track(Resource::TcpStreams));
+        let old_serial = streams.borrow().entries[old_id].serial;
+        let old_half = streams.borrow_mut().entries[old_id].read.take().unwrap();
+
+        // The id is reused while the old read is pending.
+        streams.borrow_mut().entries.remove(old_id);
+        let new_id = insert_stream(&streams, stream(), resources.track(Resource::TcpStreams));
+        assert_eq!(new_id, old_id);
+        streams.borrow_mut().entries[new_id].read.take().unwrap();
+
+        streams.borrow_mut().put_back(old_id, old_serial, |entry| entry.read = Some(old_half));
+        assert!(streams.borrow().entries[new_id].read.is_none());
+    }
+}
@@ -1,10 +1,18 @@
-use super::super::namespace::{InvokeContext, MigrationProvider, Migration};
+use super::super::namespace::{InvokeContext, InvokeResult, MigrationProvider, Migration, restore_slab};
+use super::super::error::ErrorCode;
+use super::super::app::ApplicationImpl;
+use super::super::limits::{Resource, Slot};
 use wasm_core::value::Value;
-use std::cell::Cell;
-use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::rc::{Rc, Weak};
+use std::time::{Duration, Instant};
+use slab::Slab;
 
 use futures;
-use tokio;
+use futures::{Future, Stream};
+use futures::future::Either;
+use futures::unsync::oneshot;
+use tokio::timer::{Delay, Interval};
 
 decl_namespace_with_migration_provider!(
     TimerNs,
@@ -12,66 +20,334 @@ decl_namespace_with_migration_provider!(
     TimerImpl,
     TimerMigrationProvider,
     now_millis,
-    set_immediate
+    set_immediate,
+    set_timeout,
+    set_interval,
+    clear
 );
 
+#[derive(Serialize, Deserialize, Clone)]
+struct TimerMigrationInfo {
+    timers: Vec<(usize, MigratedTimer)>,
+    immediates: Vec<TimerCallback>
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct MigratedTimer {
+    cb: TimerCallback,
+    remaining_ms: u64,
+    interval_ms: Option<u64>
+}
+
 pub struct TimerMigrationProvider;
 impl MigrationProvider<TimerNs> for TimerMigrationProvider {
     fn start_migration(target: &TimerNs) -> Option<Migration> {
-        if target.provider.pending.get() > 0 {
-            None
-        } else {
-            target.provider.migrated.set(true);
-            Some(Migration::empty())
-        }
+        let mig = Self::snapshot(target);
+
+        // Taking the entries out cancels the timers on this instance.
+        *target.provider.timers.borrow_mut() = Slab::new();
+        *target.provider.immediates.borrow_mut() = Slab::new();
+        mig
     }
 
-    fn complete_migration(target: &TimerNs, _: &Migration) {
-        if target.provider.pending.get() > 0 {
-            panic!("pending > 0");
+    fn snapshot(target: &TimerNs) -> Option<Migration> {
+        let now = Instant::now();
+
+        Some(Migration::new(&TimerMigrationInfo {
+            timers: target.provider.timers.borrow().iter()
+                .map(|(id, entry)| {
+                    let remaining = if entry.deadline > now {
+                        entry.deadline - now
+                    } else {
+                        Duration::from_millis(0)
+                    };
+                    (id, MigratedTimer {
+                        cb: entry.cb,
+                        remaining_ms: duration_to_millis(remaining),
+                        interval_ms: entry.interval.map(duration_to_millis)
+                    })
+                })
+                .collect(),
+            immediates: target.provider.immediates.borrow().iter()
+                .map(|(_, &(_, cb))| cb)
+                .collect()
+        }))
+    }
+
+    fn complete_migration(target: &TimerNs, mig: &Migration) -> Result<(), String> {
+        let info: TimerMigrationInfo = mig.extract()?;
+        let provider = &target.provider;
+        let resources = target.app.upgrade().unwrap().resources().clone();
+        let now = Instant::now();
+
+        let mut to_arm = Vec::new();
+        let entries = info.timers.into_iter()
+            .map(|(id, t)| {
+                let (tx, rx) = oneshot::channel();
+                let serial = provider.next_serial();
+                let deadline = now + Duration::from_millis(t.remaining_ms);
+                let interval = t.interval_ms.map(Duration::from_millis);
+
+                to_arm.push((id, serial, deadline, interval, rx));
+                (id, TimerEntry {
+                    serial: serial,
+                    cb: t.cb,
+                    deadline: deadline,
+                    interval: interval,
+                    _cancel: tx,
+                    _slot: resources.track(Resource::PendingCallbacks)
+                })
+            })
+            .collect();
+
+        *provider.timers.borrow_mut() = restore_slab(entries, || TimerEntry {
+            serial: ::std::u64::MAX,
+            cb: TimerCallback {
+                cb_target: 0,
+                cb_data: 0
+            },
+            deadline: now,
+            interval: None,
+            _cancel: oneshot::channel().0,
+            _slot: resources.track(Resource::PendingCallbacks)
+        })?;
+
+        for (id, serial, deadline, interval, rx) in to_arm {
+            provider.arm(target.app.clone(), id, serial, deadline, interval, rx);
         }
+        for cb in info.immediates {
+            provider.schedule_immediate(target.app.clone(), cb, resources.track(Resource::PendingCallbacks));
+        }
+
+        Ok(())
     }
 }
 
+fn duration_to_millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + (d.subsec_nanos() / 1000000) as u64
+}
+
 pub struct TimerImpl {
-    migrated: Cell<bool>,
-    pending: Rc<Cell<usize>>
+    immediates: Rc<RefCell<Slab<(u64, TimerCallback)>>>,
+    timers: Rc<RefCell<Slab<TimerEntry>>>,
+    next_serial: Cell<u64>
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+struct TimerCallback {
+    cb_target: i32,
+    cb_data: i32
+}
+
+struct TimerEntry {
+    serial: u64,
+    cb: TimerCallback,
+    deadline: Instant,
+    interval: Option<Duration>,
+
+    // Dropping the sender (by removing the entry) cancels the timer.
+    _cancel: oneshot::Sender<()>,
+
+    // Counts as a pending callback until the timer is removed.
+    _slot: Slot
 }
 
 impl TimerImpl {
     pub fn new() -> TimerImpl {
         TimerImpl {
-            migrated: Cell::new(false),
-            pending: Rc::new(Cell::new(0))
+            immediates: Rc::new(RefCell::new(Slab::new())),
+            timers: Rc::new(RefCell::new(Slab::new())),
+            next_serial: Cell::new(0)
         }
     }
-    pub fn now_millis(&self, _ctx: InvokeContext) -> Option<Value> {
+    pub fn now_millis(&self, _ctx: InvokeContext) -> InvokeResult {
         use chrono;
         let utc_time: chrono::DateTime<chrono::Utc> = chrono::Utc::now();
-        Some(Value::I64(utc_time.timestamp_millis()))
+        Ok(Some(Value::I64(utc_time.timestamp_millis())))
+    }
+
+    pub fn set_immediate(&self, ctx: InvokeContext) -> InvokeResult {
+        let cb_target = ctx.arg_i32(0)?;
+        let cb_data = ctx.arg_i32(1)?;
+
+        let slot = match ctx.app.upgrade().unwrap().acquire(Resource::PendingCallbacks) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
+
+        self.schedule_immediate(ctx.app.clone(), TimerCallback {
+            cb_target: cb_target,
+            cb_data: cb_data
+        }, slot);
+
+        Ok(Some(ErrorCode::Success.to_ret()))
+    }
+
+    pub fn set_timeout(&self, ctx: InvokeContext) -> InvokeResult {
+        let ms = ctx.arg_i64(0)?;
+        let cb_target = ctx.arg_i32(1)?;
+        let cb_data = ctx.arg_i32(2)?;
+
+        if ms < 0 {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
+        }
+
+        let slot = match ctx.app.upgrade().unwrap().acquire(Resource::PendingCallbacks) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
+
+        let id = self.add_timer(ctx.app.clone(), TimerCallback {
+            cb_target: cb_target,
+            cb_data: cb_data
+        }, Duration::from_millis(ms as u64), None, slot);
+
+        Ok(Some(Value::I32(id as i32)))
     }
 
-    pub fn set_immediate(&self, ctx: InvokeContext) -> Option<Value> {
-        let app_weak = ctx.app.clone();
-        let cb_target = ctx.args[0].get_i32().unwrap();
-        let cb_data = ctx.args[1].get_i32().unwrap();
+    pub fn set_interval(&self, ctx: InvokeContext) -> InvokeResult {
+        let ms = ctx.arg_i64(0)?;
+        let cb_target = ctx.arg_i32(1)?;
+        let cb_data = ctx.arg_i32(2)?;
 
-        if self.migrated.get() {
-            panic!("migrated");
+        // A zero interval would keep the executor busy forever.
+        if ms <= 0 {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
         }
 
-        self.pending.set(self.pending.get() + 1);
-        let pending = self.pending.clone();
+        let period = Duration::from_millis(ms as u64);
+        let slot = match ctx.app.upgrade().unwrap().acquire(Resource::PendingCallbacks) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
 
-        tokio::executor::current_thread::spawn(futures::future::lazy(move || {
-            pending.set(pending.get() - 1);
-            app_weak.upgrade().unwrap().invoke1(
-                cb_target,
-                cb_data
+        let id = self.add_timer(ctx.app.clone(), TimerCallback {
+            cb_target: cb_target,
+            cb_data: cb_data
+        }, period, Some(period), slot);
+
+        Ok(Some(Value::I32(id as i32)))
+    }
+
+    pub fn clear(&self, ctx: InvokeContext) -> InvokeResult {
+        let id = ctx.arg_usize(0)?;
+
+        let mut timers = self.timers.borrow_mut();
+        Ok(Some(if timers.contains(id) {
+            timers.remove(id);
+            ErrorCode::Success.to_ret()
+        } else {
+            ErrorCode::NotFound.to_ret()
+        }))
+    }
+
+    fn next_serial(&self) -> u64 {
+        let serial = self.next_serial.get();
+        self.next_serial.set(serial + 1);
+        serial
+    }
+
+    fn schedule_immediate(&self, app: Weak<ApplicationImpl>, cb: TimerCallback, slot: Slot) {
+        let serial = self.next_serial();
+        let id = self.immediates.borrow_mut().insert((serial, cb));
+        let immediates = self.immediates.clone();
+
+        app.upgrade().unwrap().spawn_callback(slot, futures::future::lazy(move || {
+            // The entry is gone if the timer namespace has been migrated away.
+            match immediates.borrow().get(id) {
+                Some(&(s, _)) if s == serial => {},
+                _ => return Ok(())
+            }
+            immediates.borrow_mut().remove(id);
+
+            app.upgrade().unwrap().invoke1(
+                cb.cb_target,
+                cb.cb_data
             );
             Ok(())
         }));
+    }
 
-        None
+    fn add_timer(
+        &self,
+        app: Weak<ApplicationImpl>,
+        cb: TimerCallback,
+        delay: Duration,
+        interval: Option<Duration>,
+        slot: Slot
+    ) -> usize {
+        let (tx, rx) = oneshot::channel();
+        let deadline = Instant::now() + delay;
+        let serial = self.next_serial();
+
+        let id = self.timers.borrow_mut().insert(TimerEntry {
+            serial: serial,
+            cb: cb,
+            deadline: deadline,
+            interval: interval,
+            _cancel: tx,
+            _slot: slot
+        });
+        self.arm(app, id, serial, deadline, interval, rx);
+
+        id
+    }
+
+    fn arm(
+        &self,
+        app: Weak<ApplicationImpl>,
+        id: usize,
+        serial: u64,
+        deadline: Instant,
+        interval: Option<Duration>,
+        cancel: oneshot::Receiver<()>
+    ) {
+        let timers = self.timers.clone();
+        let owner = app.upgrade().unwrap();
+
+        // `cancel` is polled first so that a cleared timer never fires.
+        // Intervals additionally check `serial` since a callback may clear
+        // its own timer and reuse the slot before `cancel` is polled again.
+        match interval {
+            None => owner.spawn(
+                cancel.select2(Delay::new(deadline)).then(move |r| {
+                    if let Ok(Either::B(_)) = r {
+                        let cb = timers.borrow_mut().remove(id).cb;
+                        app.upgrade().unwrap().invoke1(
+                            cb.cb_target,
+                            cb.cb_data
+                        );
+                    }
+                    Ok(())
+                })
+            ),
+            Some(period) => owner.spawn(
+                cancel.select2(
+                    Interval::new(deadline, period)
+                        .map_err(|e| {
+                            derror!(logger!("(app)"), "Interval error: {:?}", e);
+                        })
+                        .for_each(move |_| {
+                            let cb = {
+                                let mut timers = timers.borrow_mut();
+                                let entry = match timers.get_mut(id) {
+                                    Some(v) => v,
+                                    None => return Err(())
+                                };
+                                if entry.serial != serial {
+                                    return Err(());
+                                }
+                                entry.deadline += period;
+                                entry.cb
+                            };
+                            app.upgrade().unwrap().invoke1(
+                                cb.cb_target,
+                                cb.cb_data
+                            );
+                            Ok(())
+                        })
+                ).then(|_| Ok(()))
+            )
+        }
     }
 }
@@ -0,0 +1,510 @@
+use config::AppPermission;
+use super::super::namespace::{InvokeContext, InvokeResult, slab_get, slab_remove, MigrationProvider, Migration, restore_slab};
+use wasm_core::value::Value;
+use std::net::SocketAddr;
+use std::rc::{Rc, Weak};
+use std::cell::{Cell, RefCell};
+use slab::Slab;
+
+use futures;
+use futures::Future;
+use futures::future::Either;
+use futures::unsync::oneshot;
+use tokio;
+use tokio::net::UdpSocket;
+use super::super::error::ErrorCode;
+use super::super::app::ApplicationImpl;
+use super::super::host_memory::HostBuffer;
+use super::super::limits::{Resource, Slot};
+
+decl_namespace_with_migration_provider!(
+    UdpNs,
+    "udp",
+    UdpImpl,
+    UdpMigrationProvider,
+    bind,
+    send_to,
+    recv_from,
+    packet_addr,
+    take_packet,
+    release_packet,
+    close
+);
+
+#[derive(Serialize, Deserialize, Clone)]
+struct UdpMigrationInfo {
+    sockets: Vec<(usize, String)>,
+    recv_callbacks: Vec<RecvCallback>
+}
+
+pub struct UdpMigrationProvider;
+impl MigrationProvider<UdpNs> for UdpMigrationProvider {
+    fn start_migration(target: &UdpNs) -> Option<Migration> {
+        let mig = Self::snapshot(target);
+
+        // Taking the sockets out closes them so that they can be re-bound
+        // on this host, and tells pending receives not to call back.
+        *target.provider.sockets.borrow_mut() = Slab::new();
+        *target.provider.recv_callbacks.borrow_mut() = Slab::new();
+        mig
+    }
+
+    fn snapshot(target: &UdpNs) -> Option<Migration> {
+        Some(Migration::new(&UdpMigrationInfo {
+            sockets: target.provider.sockets.borrow().iter()
+                .map(|(id, s)| (id, s.addr.clone()))
+                .collect(),
+            recv_callbacks: target.provider.recv_callbacks.borrow().iter()
+                .map(|(_, r)| r.cb)
+                .collect()
+        }))
+    }
+
+    fn complete_migration(target: &UdpNs, mig: &Migration) -> Result<(), String> {
+        let info: UdpMigrationInfo = mig.extract()?;
+        let app = target.provider.app.upgrade().unwrap();
+
+        let sockets = info.sockets.into_iter()
+            .map(|(id, addr)| {
+                let socket = addr.parse()
+                    .map_err(|_| ())
+                    .and_then(|a| UdpSocket::bind(&a).map_err(|e| {
+                        derror!(
+                            logger!(&app.name),
+                            "Unable to rebind UDP socket on {}: {:?}",
+                            addr,
+                            e
+                        );
+                    }))
+                    .ok();
+                (id, SocketEntry {
+                    serial: target.provider.next_serial(),
+                    addr: addr,
+                    socket: socket,
+                    _slot: app.resources().track(Resource::UdpSockets)
+                })
+            })
+            .collect();
+        *target.provider.sockets.borrow_mut() = restore_slab(sockets, || SocketEntry {
+            serial: ::std::u64::MAX,
+            addr: String::new(),
+            socket: None,
+            _slot: app.resources().track(Resource::UdpSockets)
+        })?;
+
+        // Packets in flight are lost anyway.
+        for cb in &info.recv_callbacks {
+            app.invoke2(
+                cb.cb_target,
+                cb.cb_data,
+                ErrorCode::Generic.to_i32()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+struct SocketEntry {
+    // Differs from that of any earlier socket with the same id, so that a send
+    // or receive that outlives its socket doesn't go through a new one.
+    serial: u64,
+
+    addr: String,
+
+    // `None` if the socket could not be re-bound after a migration.
+    socket: Option<UdpSocket>,
+
+    _slot: Slot
+}
+
+type SocketTable = Rc<RefCell<Slab<SocketEntry>>>;
+
+/// The socket `socket_id` refers to, unless it has been closed since `serial` was read.
+fn socket_mut(sockets: &mut Slab<SocketEntry>, socket_id: usize, serial: u64) -> Option<&mut UdpSocket> {
+    sockets.get_mut(socket_id)
+        .filter(|s| s.serial == serial)
+        .and_then(|s| s.socket.as_mut())
+}
+
+#[derive(Serialize, Deserialize, Copy, Clone)]
+struct RecvCallback {
+    cb_target: i32,
+    cb_data: i32
+}
+
+struct PendingRecv {
+    socket_id: usize,
+    cb: RecvCallback,
+
+    // Dropping the sender (by removing the entry) drops the receive.
+    _cancel: oneshot::Sender<()>
+}
+
+pub struct UdpImpl {
+    app: Weak<ApplicationImpl>,
+    sockets: SocketTable,
+    next_serial: Cell<u64>,
+    packets: Rc<RefCell<Slab<(HostBuffer, SocketAddr)>>>,
+    recv_callbacks: Rc<RefCell<Slab<PendingRecv>>>
+}
+
+impl UdpImpl {
+    pub fn new(app: Weak<ApplicationImpl>) -> UdpImpl {
+        UdpImpl {
+            app: app,
+            sockets: Rc::new(RefCell::new(Slab::new())),
+            next_serial: Cell::new(0),
+            packets: Rc::new(RefCell::new(Slab::new())),
+            recv_callbacks: Rc::new(RefCell::new(Slab::new()))
+        }
+    }
+
+    fn next_serial(&self) -> u64 {
+        let serial = self.next_serial.get();
+        self.next_serial.set(serial + 1);
+        serial
+    }
+
+    /// The serial of `socket_id`, to be checked when an operation on it completes.
+    fn serial_of(&self, socket_id: usize) -> Result<u64, ErrorCode> {
+        self.sockets.borrow().get(socket_id)
+            .map(|s| s.serial)
+            .ok_or(ErrorCode::InvalidInput)
+    }
+
+    pub fn bind(&self, ctx: InvokeContext) -> InvokeResult {
+        let addr = ctx.extract_str(0, 1)?;
+        let app = ctx.app.upgrade().unwrap();
+
+        match app.check_permission(&AppPermission::UdpBindAny)
+            .or_else(|_| app.check_permission(&AppPermission::UdpBind(addr.to_string()))) {
+                Ok(_) => {},
+                Err(_) => {
+                    derror!(
+                        logger!(&app.name),
+                        "UdpBindAny or UdpBind({}) permission is required",
+                        addr
+                    );
+                    return Ok(Some(ErrorCode::PermissionDenied.to_ret()));
+                }
+            }
+
+        let saddr: SocketAddr = match addr.parse() {
+            Ok(v) => v,
+            Err(_) => return Ok(Some(ErrorCode::InvalidInput.to_ret()))
+        };
+
+        let slot = match app.acquire(Resource::UdpSockets) {
+            Ok(v) => v,
+            Err(code) => return Ok(Some(code.to_ret()))
+        };
+
+        let socket = match UdpSocket::bind(&saddr) {
+            Ok(v) => v,
+            Err(e) => {
+                derror!(
+                    logger!(&app.name),
+                    "Bind failed: {:?}",
+                    e
+                );
+                return Ok(Some(ErrorCode::BindFail.to_ret()));
+            }
+        };
+
+        let serial = self.next_serial();
+        let id = self.sockets.borrow_mut().insert(SocketEntry {
+            serial: serial,
+            addr: addr.to_string(),
+            socket: Some(socket),
+            _slot: slot
+        });
+        Ok(Some(Value::I32(id as i32)))
+    }
+
+    pub fn close(&self, ctx: InvokeContext) -> InvokeResult {
+        let socket_id = ctx.arg_usize(0)?;
+        slab_remove(&mut self.sockets.borrow_mut(), socket_id)?;
+
+        // Pending receives on the socket fail right away.
+        let pending: Vec<RecvCallback> = {
+            let mut recv_callbacks = self.recv_callbacks.borrow_mut();
+            let ids: Vec<usize> = recv_callbacks.iter()
+                .filter(|&(_, r)| r.socket_id == socket_id)
+                .map(|(id, _)| id)
+                .collect();
+            ids.into_iter().map(|id| recv_callbacks.remove(id).cb).collect()
+        };
+
+        let app = ctx.app.upgrade().unwrap();
+        for cb in pending {
+            app.invoke2(
+                cb.cb_target,
+                cb.cb_data,
+                ErrorCode::Shutdown.to_i32()
+            );
+        }
+
+        Ok(None)
+    }
+
+    pub fn send_to(&self, ctx: InvokeContext) -> InvokeResult {
+        let socket_id = ctx.arg_usize(0)?;
+        let data = ctx.extract_bytes(1, 2)?.to_vec();
+        let addr = ctx.extract_str(3, 4)?;
+        let cb_target = ctx.arg_i32(5)?;
+        let cb_data = ctx.arg_i32(6)?;
+
+        let app = ctx.app.upgrade().unwrap();
+
+        let result = app.check_permission(&AppPermission::UdpSendTo)
+            .map_err(|_| {
+                derror!(
+                    logger!(&app.name),
+                    "UdpSendTo permission is required"
+                );
+                ErrorCode::PermissionDenied
+            })
+            .and_then(|_| -> Result<SocketAddr, ErrorCode> {
+                addr.parse().map_err(|_| ErrorCode::InvalidInput)
+            });
+        let result = result.and_then(|addr| Ok((
+            addr,
+            self.serial_of(socket_id)?,
+            app.acquire(Resource::PendingCallbacks)?
+        )));
+        let (addr, serial, cb_slot) = match result {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(
+                    cb_target,
+                    cb_data,
+                    code.to_i32()
+                );
+                return Ok(None);
+            }
+        };
+
+        let app_weak1 = ctx.app.clone();
+        let app_weak2 = ctx.app.clone();
+
+        app.spawn_callback(
+            cb_slot,
+            SendToFuture {
+                sockets: self.sockets.clone(),
+                socket_id: socket_id,
+                serial: serial,
+                data: data,
+                addr: addr
+            }.map(move |n| {
+                app_weak1.upgrade().unwrap().invoke2(
+                    cb_target,
+                    cb_data,
+                    n as _
+                );
+            }).or_else(move |e| {
+                derror!(logger!("(app)"), "Send error: {:?}", e);
+                app_weak2.upgrade().unwrap().invoke2(
+                    cb_target,
+                    cb_data,
+                    ErrorCode::Generic.to_i32()
+                );
+                Ok(())
+            })
+        );
+
+        Ok(None)
+    }
+
+    pub fn recv_from(&self, ctx: InvokeContext) -> InvokeResult {
+        let socket_id = ctx.arg_usize(0)?;
+        let max_len = ctx.arg_usize(1)?;
+        let cb_target = ctx.arg_i32(2)?;
+        let cb_data = ctx.arg_i32(3)?;
+
+        let app = ctx.app.upgrade().unwrap();
+
+        // Larger datagrams are truncated to the host memory left to the application.
+        let slots = self.serial_of(socket_id)
+            .and_then(|serial| Ok((serial, app.acquire(Resource::PendingCallbacks)?)))
+            .and_then(|(serial, cb_slot)| Ok((serial, cb_slot, app.reserve_buffer(max_len)?)));
+        let (serial, cb_slot, reservation) = match slots {
+            Ok(v) => v,
+            Err(code) => {
+                app.invoke2(cb_target, cb_data, code.to_i32());
+                return Ok(None);
+            }
+        };
+
+        let packets = self.packets.clone();
+        let recv_callbacks = self.recv_callbacks.clone();
+
+        let (tx, rx) = oneshot::channel();
+        let cb_id = self.recv_callbacks.borrow_mut().insert(PendingRecv {
+            socket_id: socket_id,
+            cb: RecvCallback {
+                cb_target: cb_target,
+                cb_data: cb_data
+            },
+            _cancel: tx
+        });
+
+        let app_weak = ctx.app.clone();
+
+        // `rx` is polled first so that a receive that has been closed or
+        // migrated away never calls back, even if its slot has been reused.
+        app.spawn_callback(
+            cb_slot,
+            rx.select2(RecvFromFuture {
+                sockets: self.sockets.clone(),
+                socket_id: socket_id,
+                serial: serial,
+                buf: vec! [ 0; reservation.len() ]
+            }).then(move |r| {
+                let code = match r {
+                    Ok(Either::B(((data, addr), _))) => {
+                        packets.borrow_mut().insert((reservation.fill(data), addr)) as i32
+                    },
+                    Err(Either::B((e, _))) => {
+                        derror!(logger!("(app)"), "Receive error: {:?}", e);
+                        ErrorCode::Generic.to_i32()
+                    },
+                    _ => return Ok(())
+                };
+                recv_callbacks.borrow_mut().remove(cb_id);
+
+                app_weak.upgrade().unwrap().invoke2(
+                    cb_target,
+                    cb_data,
+                    code
+                );
+                Ok(())
+            })
+        );
+
+        Ok(None)
+    }
+
+    pub fn packet_addr(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let packet_id = ctx.arg_usize(0)?;
+        let addr = format!("{}", slab_get(&self.packets.borrow(), packet_id)?.1);
+
+        let out = ctx.extract_bytes_mut(1, 2)?;
+        let bytes = addr.as_bytes();
+
+        // The caller should retry with a larger buffer if the returned
+        // length is greater than the length of its buffer.
+        if bytes.len() <= out.len() {
+            out[0..bytes.len()].copy_from_slice(bytes);
+        }
+        Ok(Some(Value::I32(bytes.len() as i32)))
+    }
+
+    pub fn take_packet(&self, mut ctx: InvokeContext) -> InvokeResult {
+        let packet_id = ctx.arg_usize(0)?;
+        let target_ptr = ctx.arg_usize(1)?;
+        let max_len = ctx.arg_usize(2)?;
+
+        let mut packets = self.packets.borrow_mut();
+        let len = slab_get(&packets, packet_id)?.0.len();
+        if len > max_len {
+            return Ok(Some(ErrorCode::InvalidInput.to_ret()));
+        }
+
+        ctx.write_bytes(target_ptr, &packets[packet_id].0)?;
+        packets.remove(packet_id);
+
+        Ok(Some(Value::I32(len as i32)))
+    }
+
+    pub fn release_packet(&self, ctx: InvokeContext) -> InvokeResult {
+        let packet_id = ctx.arg_usize(0)?;
+        slab_remove(&mut self.packets.borrow_mut(), packet_id)?;
+        Ok(None)
+    }
+}
+
+fn socket_gone() -> tokio::io::Error {
+    tokio::io::Error::new(tokio::io::ErrorKind::NotConnected, "socket closed")
+}
+
+struct SendToFuture {
+    sockets: SocketTable,
+    socket_id: usize,
+    serial: u64,
+    data: Vec<u8>,
+    addr: SocketAddr
+}
+
+impl Future for SendToFuture {
+    type Item = usize;
+    type Error = tokio::io::Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let mut sockets = self.sockets.borrow_mut();
+        match socket_mut(&mut sockets, self.socket_id, self.serial) {
+            Some(s) => s.poll_send_to(&self.data, &self.addr),
+            None => Err(socket_gone())
+        }
+    }
+}
+
+struct RecvFromFuture {
+    sockets: SocketTable,
+    socket_id: usize,
+    serial: u64,
+    buf: Vec<u8>
+}
+
+impl Future for RecvFromFuture {
+    type Item = (Box<[u8]>, SocketAddr);
+    type Error = tokio::io::Error;
+
+    fn poll(&mut self) -> futures::Poll<Self::Item, Self::Error> {
+        let mut sockets = self.sockets.borrow_mut();
+        let socket = match socket_mut(&mut sockets, self.socket_id, self.serial) {
+            Some(v) => v,
+            None => return Err(socket_gone())
+        };
+
+        match socket.poll_recv_from(&mut self.buf) {
+            Ok(futures::Async::Ready((n_bytes, addr))) => Ok(
+                futures::Async::Ready(
+                    (self.buf[0..n_bytes].to_vec().into_boxed_slice(), addr)
+                )
+            ),
+            Ok(futures::Async::NotReady) => Ok(futures::Async::NotReady),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::AppLimits;
+    use lssa::limits::Resources;
+
+    #[test]
+    fn test_stale_socket() {
+        let resources = Resources::new(&AppLimits::default());
+        let mut sockets = Slab::new();
+        let entry = |serial| SocketEntry {
+            serial: serial,
+            addr: "127.0.0.1:0".into(),
+            socket: Some(UdpSocket::bind(&"127.0.0.1:0".parse().unwrap()).unwrap()),
+            _slot: resources.track(Resource::UdpSockets)
+        };
+
+        let old_id = sockets.insert(entry(0));
+        assert!(socket_mut(&mut sockets, old_id, 0).is_some());
+
+        // The id is reused while a send on the old socket is pending.
+        sockets.remove(old_id);
+        let new_id = sockets.insert(entry(1));
+        assert_eq!(new_id, old_id);
+        assert!(socket_mut(&mut sockets, old_id, 0).is_none());
+        assert!(socket_mut(&mut sockets, new_id, 1).is_some());
+        assert_eq!(resources.usage().udp_sockets, 1);
+    }
+}
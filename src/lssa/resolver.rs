@@ -108,10 +108,26 @@ impl<I: NativeResolver> LssaResolver<I> {
             ns::tcp::TcpImpl::new(app.clone()),
             app.clone()
         ));
+        self.add_namespace(ns::udp::UdpNs::new(
+            ns::udp::UdpImpl::new(app.clone()),
+            app.clone()
+        ));
+        self.add_namespace(ns::dns::DnsNs::new(
+            ns::dns::DnsImpl::new(app.clone()),
+            app.clone()
+        ));
         self.add_namespace(ns::file::FileNs::new(
             ns::file::FileImpl::new(),
             app.clone()
         ));
+        self.add_namespace(ns::http::HttpNs::new(
+            ns::http::HttpImpl::new(),
+            app.clone()
+        ));
+        self.add_namespace(ns::http_client::HttpClientNs::new(
+            ns::http_client::HttpClientImpl::new(app.clone()),
+            app.clone()
+        ));
     }
 
     fn resolve_local(&self, module: &str, field: &str) -> Option<NativeEntry> {
@@ -126,19 +142,18 @@ impl<I: NativeResolver> LssaResolver<I> {
                     Some(v) => v,
                     None => return None
                 };
-                let mut parts = full_path.splitn(2, '_');
-                let ns_name = match parts.next() {
-                    Some(v) => v,
-                    None => return None
-                };
-                let field_name = match parts.next() {
-                    Some(v) => v,
-                    None => return None
-                };
-                let ns = match self.namespaces.get(ns_name) {
-                    Some(v) => v,
-                    None => return None
-                };
+                // Namespace names may contain underscores (e.g. `http_client`),
+                // so pick the longest one that prefixes the path.
+                let (ns, field_name) = match self.namespaces.iter()
+                    .filter(|&(name, _)| {
+                        full_path.len() > name.len()
+                            && full_path.starts_with(name.as_str())
+                            && full_path.as_bytes()[name.len()] == b'_'
+                    })
+                    .max_by_key(|&(name, _)| name.len()) {
+                        Some((name, ns)) => (ns, &full_path[name.len() + 1 ..]),
+                        None => return None
+                    };
                 ns.dispatch(field_name)
             }
         }
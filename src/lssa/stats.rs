@@ -1,5 +1,6 @@
 use std::collections::BTreeMap;
 use futures::sync::mpsc::Sender;
+use super::limits::ResourceUsage;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Stats {
@@ -9,7 +10,26 @@ pub struct Stats {
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct AppStats {
     pub start_time: i64,
-    pub running_time: i64
+    pub running_time: i64,
+
+    /// Calls interrupted for exceeding `max_callback_ms` since the
+    /// application was loaded, across restarts.
+    pub callback_timeouts: u64,
+
+    /// Bytes of linear memory.
+    pub memory: usize,
+
+    /// Highest `memory` seen.
+    pub memory_peak: usize,
+
+    /// Bytes held by the host on behalf of the application.
+    pub host_memory: usize,
+
+    pub resources: ResourceUsage,
+
+    /// Crashed and released. The other fields are as of the crash, with
+    /// nothing held anymore.
+    pub crashed: bool
 }
 
 pub struct StatsRequest {
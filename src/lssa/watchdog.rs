@@ -0,0 +1,453 @@
+//! Interrupts calls into applications that run longer than their `max_callback_ms`.
+//!
+//! Every thread that calls into applications gets a watchdog thread the first
+//! time a limit applies. When a call overruns, the watchdog sends the calling
+//! thread `INTERRUPT_SIGNAL`. If the thread is still running guest code when
+//! the signal arrives, the handler raises a `SIGSEGV`, which `protected_call`
+//! turns into a trap like any other fault of the guest. A signal that arrives
+//! after the call has finished is ignored, and one that lands in runtime
+//! helpers (e.g. growing the memory) is sent again a little later.
+//!
+//! A thread is never interrupted inside a host call, where it may hold locks
+//! or be in the middle of updating a namespace. A call that overruns there
+//! traps as soon as it returns to the guest instead. Callbacks that a host
+//! call makes into the guest are watched against the deadline of the call
+//! they are nested in.
+
+use std::cell::{Cell, RefCell};
+use std::ptr;
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::thread;
+
+use wasm_core::executor::{ExecuteError, ExecuteResult};
+
+#[cfg(unix)]
+use libc;
+
+/// No call is being watched.
+const IDLE: usize = 0;
+
+/// Running guest code before the deadline.
+const GUEST: usize = 1;
+
+/// Running a host call on behalf of the guest.
+const HOST: usize = 2;
+
+/// The deadline passed during a host call.
+const EXPIRED: usize = 3;
+
+/// The deadline passed in guest code and the signal is on its way.
+const INTERRUPTED: usize = 4;
+
+/// The signal arrived in guest code, which traps.
+const TRAPPING: usize = 5;
+
+/// How long to wait before sending the signal again when it landed outside
+/// of guest code.
+const RETRY_MS: u64 = 1;
+
+#[cfg(unix)]
+const INTERRUPT_SIGNAL: libc::c_int = libc::SIGUSR2;
+
+struct Shared {
+    state: AtomicUsize,
+    deadline: Mutex<Option<Instant>>,
+    cond: Condvar,
+
+    #[cfg(unix)]
+    thread: libc::pthread_t
+}
+
+thread_local! {
+    static WATCHDOG: RefCell<Option<Arc<Shared>>> = RefCell::new(None);
+
+    // Whether the last watched call that trapped on this thread was interrupted.
+    static INTERRUPTED_CALL: Cell<bool> = Cell::new(false);
+
+    // The state of the watchdog of this thread, for the signal handler, which
+    // must not borrow `WATCHDOG`. The watchdog thread keeps it alive.
+    static STATE: Cell<*const AtomicUsize> = Cell::new(ptr::null());
+}
+
+fn current() -> Option<Arc<Shared>> {
+    WATCHDOG.with(|w| w.borrow().clone())
+}
+
+fn current_or_start() -> Arc<Shared> {
+    install_handler();
+
+    WATCHDOG.with(|w| {
+        w.borrow_mut().get_or_insert_with(|| {
+            let shared = Arc::new(Shared {
+                state: AtomicUsize::new(IDLE),
+                deadline: Mutex::new(None),
+                cond: Condvar::new(),
+                #[cfg(unix)]
+                thread: unsafe { libc::pthread_self() }
+            });
+
+            STATE.with(|s| s.set(&shared.state));
+
+            let watched = shared.clone();
+            thread::Builder::new()
+                .name("watchdog".into())
+                .spawn(move || run(watched))
+                .unwrap();
+
+            shared
+        }).clone()
+    })
+}
+
+fn run(shared: Arc<Shared>) {
+    let mut deadline = shared.deadline.lock().unwrap();
+    loop {
+        let target = match *deadline {
+            Some(v) => v,
+            None => {
+                deadline = shared.cond.wait(deadline).unwrap();
+                continue;
+            }
+        };
+
+        let now = Instant::now();
+        if now < target {
+            deadline = shared.cond.wait_timeout(deadline, target - now).unwrap().0;
+            continue;
+        }
+        *deadline = None;
+
+        // The lock is held so that a new call can't be armed in between.
+        match shared.state.compare_exchange(GUEST, INTERRUPTED, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => {
+                interrupt(&shared);
+                *deadline = Some(now + Duration::from_millis(RETRY_MS));
+            },
+
+            // The signal hasn't been handled yet, and may put the state
+            // back to `GUEST`.
+            Err(INTERRUPTED) => *deadline = Some(now + Duration::from_millis(RETRY_MS)),
+            Err(_) => {
+                let _ = shared.state.compare_exchange(HOST, EXPIRED, Ordering::SeqCst, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn interrupt(shared: &Shared) {
+    unsafe {
+        libc::pthread_kill(shared.thread, INTERRUPT_SIGNAL);
+    }
+}
+
+#[cfg(not(unix))]
+fn interrupt(_: &Shared) {}
+
+#[cfg(unix)]
+fn install_handler() {
+    use std::mem;
+    use std::sync::{Once, ONCE_INIT};
+
+    static INSTALL: Once = ONCE_INIT;
+    INSTALL.call_once(|| unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        let handler: extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void) = on_interrupt;
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(INTERRUPT_SIGNAL, &action, ptr::null_mut());
+    });
+}
+
+#[cfg(not(unix))]
+fn install_handler() {}
+
+/// Turns an interrupt into a trap if the thread is still running the guest
+/// code that overran. Calls that finished in the meantime have moved the state
+/// out of `INTERRUPTED`, so a late signal does nothing.
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int, _: *mut libc::siginfo_t, ctx: *mut libc::c_void) {
+    let state = STATE.with(|s| s.get());
+    if state.is_null() {
+        return;
+    }
+    let state = unsafe { &*state };
+
+    if unsafe { in_loaded_code(ctx) } {
+        // A runtime helper or the host code around the call. The watchdog
+        // sends the signal again unless the call finishes first.
+        let _ = state.compare_exchange(INTERRUPTED, GUEST, Ordering::SeqCst, Ordering::SeqCst);
+        return;
+    }
+
+    if state.compare_exchange(INTERRUPTED, TRAPPING, Ordering::SeqCst, Ordering::SeqCst).is_ok() {
+        unsafe {
+            libc::raise(libc::SIGSEGV);
+        }
+    }
+}
+
+/// Whether the thread was interrupted in code loaded from a file rather than
+/// in code generated for the guest, which belongs to no file.
+#[cfg(all(unix, target_os = "linux", target_arch = "x86_64"))]
+unsafe fn in_loaded_code(ctx: *mut libc::c_void) -> bool {
+    use std::mem;
+
+    let uc = ctx as *const libc::ucontext_t;
+    let pc = (*uc).uc_mcontext.gregs[libc::REG_RIP as usize] as usize;
+
+    let mut info: libc::Dl_info = mem::zeroed();
+    libc::dladdr(pc as *const libc::c_void, &mut info) != 0
+}
+
+/// Without a way to tell where the thread is, every interrupt traps.
+#[cfg(all(unix, not(all(target_os = "linux", target_arch = "x86_64"))))]
+unsafe fn in_loaded_code(_: *mut libc::c_void) -> bool {
+    false
+}
+
+fn timeout_error() -> ExecuteError {
+    ExecuteError::Custom("max_callback_ms exceeded".into())
+}
+
+/// Runs the guest code `f`, interrupting it if it runs longer than `limit`.
+///
+/// Must be called inside `protected_call`. Calls nested in a watched call,
+/// e.g. callbacks made by a host call, share its deadline.
+pub fn watch<T, F: FnOnce() -> T>(limit: Option<Duration>, f: F) -> T {
+    let limit = match limit {
+        Some(v) => v,
+        None => return f()
+    };
+
+    let shared = current_or_start();
+    let nested = {
+        // The watchdog only changes the state with the lock held.
+        let mut deadline = shared.deadline.lock().unwrap();
+        let nested = match shared.state.load(Ordering::SeqCst) {
+            IDLE => {
+                *deadline = Some(Instant::now() + limit);
+                Some(false)
+            },
+            HOST => Some(true),
+
+            // The deadline passed during the host call that makes this one,
+            // so it is interrupted right away.
+            EXPIRED => {
+                *deadline = Some(Instant::now());
+                Some(true)
+            },
+            _ => None
+        };
+        if nested.is_some() {
+            shared.state.store(GUEST, Ordering::SeqCst);
+            shared.cond.notify_one();
+        }
+        nested
+    };
+
+    let _restore = match nested {
+        Some(v) => Restore {
+            shared: shared,
+            nested: v
+        },
+        None => return f()
+    };
+
+    f()
+}
+
+/// Puts the watchdog back into the state it had before a call to `watch`,
+/// whether the call returned or trapped.
+struct Restore {
+    shared: Arc<Shared>,
+
+    // Made by a host call, which resumes once it is done.
+    nested: bool
+}
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let trapped = thread::panicking();
+        let state = &self.shared.state;
+
+        let last = if self.nested {
+            match state.compare_exchange(GUEST, HOST, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => GUEST,
+                Err(v) => {
+                    state.store(EXPIRED, Ordering::SeqCst);
+                    v
+                }
+            }
+        } else {
+            state.swap(IDLE, Ordering::SeqCst)
+        };
+
+        // If the call finished right as the deadline passed, the state is no
+        // longer `INTERRUPTED` and the signal on its way is ignored.
+        if trapped {
+            INTERRUPTED_CALL.with(|v| v.set(last == INTERRUPTED || last == TRAPPING || last == EXPIRED));
+        }
+    }
+}
+
+/// Runs the host call `f` on behalf of the guest, shielding it from interrupts.
+///
+/// Fails instead if the deadline of the calling guest code has passed.
+pub fn host_call<T, F: FnOnce() -> ExecuteResult<T>>(f: F) -> ExecuteResult<T> {
+    let shared = match current() {
+        Some(v) => v,
+        None => return f()
+    };
+
+    match shared.state.compare_exchange(GUEST, HOST, Ordering::SeqCst, Ordering::SeqCst) {
+        Ok(_) => {},
+        Err(INTERRUPTED) => {
+            // The signal on its way is ignored once the state has moved on.
+            shared.state.store(EXPIRED, Ordering::SeqCst);
+            return Err(timeout_error());
+        },
+        Err(_) => return f()
+    }
+
+    let ret = f();
+
+    match shared.state.compare_exchange(HOST, GUEST, Ordering::SeqCst, Ordering::SeqCst) {
+        Err(EXPIRED) => Err(timeout_error()),
+        _ => ret
+    }
+}
+
+/// Returns whether the last call into an application that trapped on this
+/// thread was interrupted, and forgets it.
+pub fn take_interrupted() -> bool {
+    INTERRUPTED_CALL.with(|v| v.replace(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    fn state() -> usize {
+        current().unwrap().state.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn test_nested_trap() {
+        let limit = Some(Duration::from_secs(60));
+
+        watch(limit, || {
+            host_call(|| {
+                // A callback made by the host call traps.
+                let result = catch_unwind(AssertUnwindSafe(|| watch(limit, || {
+                    assert_eq!(state(), GUEST);
+                    panic!("trap")
+                })));
+                assert!(result.is_err());
+                assert!(!take_interrupted());
+
+                // The host call is still shielded, and returns into watched code.
+                assert_eq!(state(), HOST);
+                Ok(())
+            }).unwrap();
+            assert_eq!(state(), GUEST);
+        });
+        assert_eq!(state(), IDLE);
+    }
+
+    #[test]
+    fn test_nested_expired() {
+        let limit = Some(Duration::from_secs(60));
+
+        let result = catch_unwind(AssertUnwindSafe(|| watch(limit, || {
+            let ret = host_call(|| {
+                // The deadline passes while a callback made by the host call runs.
+                let _ = catch_unwind(AssertUnwindSafe(|| watch(limit, || {
+                    current().unwrap().state.store(EXPIRED, Ordering::SeqCst);
+                    panic!("trap")
+                })));
+                assert!(take_interrupted());
+                assert_eq!(state(), EXPIRED);
+                Ok(())
+            });
+            assert!(ret.is_err());
+            panic!("trap")
+        })));
+        assert!(result.is_err());
+        assert!(take_interrupted());
+        assert_eq!(state(), IDLE);
+    }
+
+    /// Blocks or unblocks `INTERRUPT_SIGNAL` on this thread.
+    fn mask_interrupt(how: libc::c_int) {
+        use std::mem;
+
+        unsafe {
+            let mut set: libc::sigset_t = mem::zeroed();
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, INTERRUPT_SIGNAL);
+            libc::pthread_sigmask(how, &set, ptr::null_mut());
+        }
+    }
+
+    /// What the watchdog does when the deadline passes in guest code.
+    fn expire() {
+        let shared = current().unwrap();
+        shared.state.compare_exchange(GUEST, INTERRUPTED, Ordering::SeqCst, Ordering::SeqCst).unwrap();
+        interrupt(&shared);
+    }
+
+    #[test]
+    fn test_finished_at_deadline() {
+        let limit = Some(Duration::from_secs(60));
+
+        let ret = watch(limit, || {
+            // The signal is held back until the callback has returned.
+            mask_interrupt(libc::SIG_BLOCK);
+            expire();
+            42
+        });
+        mask_interrupt(libc::SIG_UNBLOCK);
+
+        assert_eq!(ret, 42);
+        assert_eq!(state(), IDLE);
+        assert!(!take_interrupted());
+    }
+
+    #[test]
+    fn test_host_call_at_deadline() {
+        let limit = Some(Duration::from_secs(60));
+
+        let result = catch_unwind(AssertUnwindSafe(|| watch(limit, || {
+            mask_interrupt(libc::SIG_BLOCK);
+            expire();
+
+            // The guest makes a host call before the signal arrives.
+            let ret = host_call(|| Ok(()));
+            mask_interrupt(libc::SIG_UNBLOCK);
+            assert!(ret.is_err());
+            assert_eq!(state(), EXPIRED);
+            panic!("trap")
+        })));
+        assert!(result.is_err());
+        assert!(take_interrupted());
+        assert_eq!(state(), IDLE);
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn test_interrupt_outside_guest() {
+        let limit = Some(Duration::from_secs(60));
+
+        watch(limit, || {
+            // The signal lands in host code, so the watchdog tries again later.
+            expire();
+            assert_eq!(state(), GUEST);
+        });
+        assert_eq!(state(), IDLE);
+    }
+}
@@ -13,6 +13,13 @@ extern crate chrono;
 extern crate tokio_io;
 extern crate bincode;
 extern crate sha2;
+extern crate httparse;
+extern crate futures_cpupool;
+extern crate native_tls;
+extern crate tokio_tls;
+extern crate tokio_signal;
+#[cfg(unix)]
+extern crate libc;
 
 #[macro_use]
 mod logging;
@@ -21,10 +28,13 @@ mod logging;
 mod lssa;
 
 mod container;
+mod dns;
+mod tls;
 mod config;
 mod server;
+mod management;
+mod service;
 
-use std::panic::catch_unwind;
 use config::Config;
 use server::Server;
 
@@ -36,20 +46,18 @@ fn main() {
         );
         ::std::process::exit(1);
     });
-    let config = match catch_unwind(|| Config::from_file(&config_path)) {
+    let config = match Config::from_file(&config_path) {
         Ok(v) => v,
-        Err(_) => {
+        Err(e) => {
             derror!(
                 logger!("(main)"),
-                "Invalid config file"
+                "Invalid config file: {}",
+                e
             );
             ::std::process::exit(1);
         }
     };
 
-    let server = Server::new(config);
-
-    tokio::executor::current_thread::block_on_all(
-        server.run_apps()
-    ).unwrap();
+    let server = Server::new(config_path, config);
+    server::block_on_all(server.run_apps()).unwrap();
 }
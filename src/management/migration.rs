@@ -0,0 +1,601 @@
+//! Moves running applications between hosts.
+//!
+//! The source host takes the state out of an application and connects to
+//! the migration listener of the target:
+//!
+//! 1. The target sends a `Challenge` with a random nonce.
+//! 2. The source replies with a `Hello` naming the application, with a nonce
+//!    of its own and a proof that it knows the token.
+//! 3. The target checks the proof and replies with a `HelloResult` carrying
+//!    its own proof, which the source checks in turn.
+//! 4. The source sends a `Transfer` with the state and its MAC. The target
+//!    activates the application with the code it has loaded for it, which
+//!    must be the code the state was taken from, and replies with a
+//!    `TransferResult`.
+//!
+//! The token itself never goes over the wire, but the state is not encrypted.
+//! The source stops its copy only after the target has confirmed, and resumes
+//! it with its state otherwise, including when either stage times out.
+//!
+//! Frames are the same as those of the management API. The target
+//! application must be declared, and stopped, on the target host.
+
+use std::io;
+use std::io::Read;
+use std::fs::File;
+use std::rc::Rc;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures;
+use futures::{Future, Stream};
+use tokio;
+use tokio::net::TcpStream;
+use tokio_io::{AsyncRead, AsyncWrite};
+use bincode;
+use serde::de::DeserializeOwned;
+use sha2::{Sha256, Digest};
+
+use container::Container;
+use lssa::app::AppMigration;
+use lssa::control::Control;
+use lssa::ns::tcp::{connect_any, with_timeout};
+use super::{BoxedFuture, read_frame, write_frame, query, token_eq};
+use super::protocol::MAX_FRAME_LEN;
+
+/// Upper bound of the frames exchanged before the peer has been authenticated.
+const MAX_HANDSHAKE_LEN: usize = 4096;
+
+/// Time allowed for connecting and authenticating.
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// Time allowed for sending the state and having it confirmed, which
+/// includes compiling the application on the target.
+const TRANSFER_TIMEOUT_SECS: u64 = 300;
+
+type Nonce = [u8; 32];
+type Mac = [u8; 32];
+
+/// Sent by the target as soon as the source connects.
+#[derive(Serialize, Deserialize)]
+struct Challenge {
+    nonce: Nonce
+}
+
+#[derive(Serialize, Deserialize)]
+struct Hello {
+    app: String,
+    nonce: Nonce,
+
+    /// `Session::mac(b"source", &[])`.
+    proof: Mac
+}
+
+#[derive(Serialize, Deserialize)]
+enum HelloResult {
+    /// `proof` is `Session::mac(b"target", &[])`.
+    Ok { proof: Mac },
+    Error(String)
+}
+
+#[derive(Serialize, Deserialize)]
+struct Transfer {
+    /// A bincode-encoded `AppMigration`.
+    migration: Vec<u8>,
+
+    /// `Session::mac(b"state", &migration)`.
+    mac: Mac
+}
+
+#[derive(Serialize, Deserialize)]
+enum TransferResult {
+    Ok,
+    Error(String)
+}
+
+/// What both sides know once `Hello` has been sent.
+struct Session {
+    token: Rc<String>,
+    target_nonce: Nonce,
+    source_nonce: Nonce,
+    app: String
+}
+
+impl Session {
+    /// Authenticates `data` as sent by `sender` in this session.
+    fn mac(&self, sender: &[u8], data: &[u8]) -> Mac {
+        hmac_sha256(self.token.as_bytes(), &[
+            sender,
+            &self.target_nonce,
+            &self.source_nonce,
+            &sha256(self.app.as_bytes()),
+            &sha256(data)
+        ])
+    }
+}
+
+/// Starts accepting applications if `migration.listen` is configured.
+pub fn start(container: &Container) {
+    let cfg = match container.config_state.read().unwrap().config.migration.clone() {
+        Some(v) => v,
+        None => return
+    };
+    let listen = match cfg.listen {
+        Some(v) => v,
+        None => return
+    };
+
+    if cfg.token.is_empty() {
+        derror!(
+            logger!("migration"),
+            "migration.token must not be empty; not accepting migrations"
+        );
+        return;
+    }
+
+    let container = container.clone();
+    let token = Rc::new(cfg.token);
+    let allow_from = cfg.allow_from;
+    let addr = listen.clone();
+
+    tokio::executor::current_thread::spawn(
+        futures::future::result(
+            listen.parse::<SocketAddr>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))
+                .and_then(|addr| tokio::net::TcpListener::bind(&addr))
+        ).and_then(move |listener| {
+            dinfo!(logger!("migration"), "Accepting migrations on {}", listen);
+            listener.incoming().for_each(move |stream| {
+                if !allow_from.is_empty() {
+                    match stream.peer_addr() {
+                        Ok(ref peer) if allow_from.contains(&peer.ip()) => {},
+                        peer => {
+                            dwarning!(logger!("migration"), "Refused a connection from {:?}", peer);
+                            return Ok(());
+                        }
+                    }
+                }
+                tokio::executor::current_thread::spawn(
+                    accept(container.clone(), token.clone(), stream)
+                );
+                Ok(())
+            })
+        }).map_err(move |e| {
+            derror!(
+                logger!("migration"),
+                "Migration listener on {} failed: {:?}",
+                addr,
+                e
+            );
+        })
+    );
+}
+
+/// Receives one application and replies with whether it was activated.
+fn accept(container: Container, token: Rc<String>, stream: TcpStream) -> impl Future<Item = (), Error = ()> {
+    futures::future::result(random_nonce()).and_then(move |target_nonce| {
+        let handshake = write_frame(stream, &Challenge {
+            nonce: target_nonce
+        }).and_then(|stream| read_message::<_, Hello>(stream, MAX_HANDSHAKE_LEN));
+
+        with_timeout(handshake, Duration::from_secs(HANDSHAKE_TIMEOUT_SECS)).and_then(move |(stream, hello)| {
+            let result = check_hello(&container, &token, &target_nonce, &hello);
+            let app_id = match result {
+                Ok(v) => v,
+                Err(e) => {
+                    dwarning!(logger!("migration"), "Rejected an incoming migration: {}", e);
+                    return Box::new(write_frame(stream, &HelloResult::Error(e)).map(|_| ())) as BoxedFuture<()>;
+                }
+            };
+
+            let session = Session {
+                token: token,
+                target_nonce: target_nonce,
+                source_nonce: hello.nonce,
+                app: hello.app
+            };
+            let proof = session.mac(b"target", &[]);
+
+            // Only now that the source is known is it allowed to send a large frame.
+            let transfer = write_frame(stream, &HelloResult::Ok {
+                proof: proof
+            }).and_then(|stream| {
+                read_message::<_, Transfer>(stream, MAX_FRAME_LEN)
+            });
+
+            Box::new(with_timeout(transfer, Duration::from_secs(TRANSFER_TIMEOUT_SECS)).and_then(move |(stream, transfer)| {
+                activate(&container, app_id, &session, transfer).and_then(move |result| {
+                    if let TransferResult::Error(ref e) = result {
+                        dwarning!(logger!("migration"), "Rejected an incoming migration: {}", e);
+                    }
+                    write_frame(stream, &result)
+                })
+            }).map(|_| ()))
+        })
+    }).map_err(|e| {
+        dwarning!(logger!("migration"), "Incoming migration failed: {:?}", e);
+    })
+}
+
+/// Checks the proof of the source and that the application can be received,
+/// returning its id.
+fn check_hello(container: &Container, token: &Rc<String>, target_nonce: &Nonce, hello: &Hello) -> Result<usize, String> {
+    let session = Session {
+        token: token.clone(),
+        target_nonce: *target_nonce,
+        source_nonce: hello.nonce,
+        app: hello.app.clone()
+    };
+    if !token_eq(&hello.proof, &session.mac(b"source", &[])) {
+        return Err("Invalid token".into());
+    }
+
+    container.lookup_app_id_by_name(&hello.app)
+        .ok_or_else(|| format!("Application `{}` not found", hello.app))
+}
+
+fn activate(container: &Container, app_id: usize, session: &Session, transfer: Transfer) -> BoxedFuture<TransferResult> {
+    if !token_eq(&transfer.mac, &session.mac(b"state", &transfer.migration)) {
+        return Box::new(futures::future::ok(TransferResult::Error("Invalid MAC".into())));
+    }
+
+    let migration: AppMigration = match bincode::deserialize(&transfer.migration) {
+        Ok(v) => v,
+        Err(e) => return Box::new(futures::future::ok(
+            TransferResult::Error(format!("Invalid migration: {}", e))
+        ))
+    };
+
+    let name = session.app.clone();
+
+    Box::new(query(container, move |sender| Control::ActivateMigration {
+        app_id: app_id,
+        migration: migration,
+        sender: sender
+    }).map(move |result| match result {
+        Ok(_) => {
+            dinfo!(logger!("migration"), "Application {} migrated in", name);
+            TransferResult::Ok
+        },
+        Err(e) => TransferResult::Error(e)
+    }))
+}
+
+/// Moves the application `app_id` to the host whose migration listener is at `target`.
+pub fn migrate_to(
+    container: &Container,
+    app_id: usize,
+    app: String,
+    target: String
+) -> BoxedFuture<Result<(), String>> {
+    let token = match container.config_state.read().unwrap().config.migration {
+        Some(ref cfg) => Rc::new(cfg.token.clone()),
+        None => return Box::new(futures::future::ok(Err("Migration is not configured".into())))
+    };
+
+    let container = container.clone();
+    let resolver = container.resolver.clone();
+
+    Box::new(query(&container, |sender| Control::PrepareMigration {
+        app_id: app_id,
+        sender: sender
+    }).and_then(move |prepared| -> BoxedFuture<Result<(), String>> {
+        let migration = match prepared {
+            Ok(v) => v,
+            Err(e) => return Box::new(futures::future::ok(Err(e)))
+        };
+
+        let migration_data = bincode::serialize(&migration).unwrap();
+
+        let connect = resolver.resolve_addr(&target).and_then(connect_any);
+
+        Box::new(
+            with_timeout(connect, Duration::from_secs(HANDSHAKE_TIMEOUT_SECS))
+                .and_then(move |stream| send(stream, token, app, migration_data))
+                .then(move |result| {
+                    let result = match result {
+                        Ok(TransferResult::Ok) => Ok(()),
+                        Ok(TransferResult::Error(e)) => Err(format!("Rejected by {}: {}", target, e)),
+                        Err(e) => Err(format!("Unable to migrate to {}: {}", target, e))
+                    };
+
+                    // Only the confirmation of the target may stop the application here.
+                    // If it timed out, the target may have started its copy as well.
+                    let _ = container.dispatch_control(Control::FinishMigration {
+                        app_id: app_id,
+                        migration: match result {
+                            Ok(_) => None,
+                            Err(_) => Some(migration)
+                        }
+                    });
+                    Ok(result)
+                })
+        )
+    }))
+}
+
+/// Authenticates both sides, then sends the state of `app`.
+fn send<S: AsyncRead + AsyncWrite + 'static>(
+    stream: S,
+    token: Rc<String>,
+    app: String,
+    migration: Vec<u8>
+) -> BoxedFuture<TransferResult> {
+    let source_nonce = match random_nonce() {
+        Ok(v) => v,
+        Err(e) => return Box::new(futures::future::err(e))
+    };
+
+    let handshake = read_message::<_, Challenge>(stream, MAX_HANDSHAKE_LEN).and_then(move |(stream, challenge)| {
+        let session = Session {
+            token: token,
+            target_nonce: challenge.nonce,
+            source_nonce: source_nonce,
+            app: app
+        };
+        let hello = Hello {
+            app: session.app.clone(),
+            nonce: source_nonce,
+            proof: session.mac(b"source", &[])
+        };
+
+        write_frame(stream, &hello).and_then(|stream| {
+            read_message::<_, HelloResult>(stream, MAX_HANDSHAKE_LEN)
+        }).map(move |(stream, result)| (stream, result, session))
+    });
+
+    Box::new(
+        with_timeout(handshake, Duration::from_secs(HANDSHAKE_TIMEOUT_SECS))
+            .and_then(move |(stream, result, session)| -> BoxedFuture<TransferResult> {
+                match result {
+                    HelloResult::Ok { proof } => if !token_eq(&proof, &session.mac(b"target", &[])) {
+                        return Box::new(futures::future::err(io::Error::new(
+                            io::ErrorKind::PermissionDenied,
+                            "the target does not know the token"
+                        )));
+                    },
+                    HelloResult::Error(e) => return Box::new(futures::future::ok(TransferResult::Error(e)))
+                }
+
+                let transfer = Transfer {
+                    mac: session.mac(b"state", &migration),
+                    migration: migration
+                };
+                let confirmed = write_frame(stream, &transfer).and_then(|stream| {
+                    read_message::<_, TransferResult>(stream, MAX_HANDSHAKE_LEN)
+                }).map(|(_, result)| result);
+
+                Box::new(with_timeout(confirmed, Duration::from_secs(TRANSFER_TIMEOUT_SECS)))
+            })
+    )
+}
+
+/// Reads and decodes a message, failing if the peer closed the connection.
+fn read_message<S: AsyncRead + 'static, T: DeserializeOwned + 'static>(stream: S, max_len: usize) -> BoxedFuture<(S, T)> {
+    Box::new(read_frame(stream, max_len).and_then(|frame| {
+        let (stream, frame) = frame.ok_or_else(|| io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed by the peer"
+        ))?;
+        let msg = bincode::deserialize(&frame)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok((stream, msg))
+    }))
+}
+
+fn random_nonce() -> io::Result<Nonce> {
+    let mut nonce = [0u8; 32];
+    File::open("/dev/urandom")?.read_exact(&mut nonce)?;
+    Ok(nonce)
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::default();
+    hasher.input(data);
+    let mut out = [0u8; 32];
+    out.copy_from_slice(hasher.result().as_slice());
+    out
+}
+
+/// HMAC-SHA256 (RFC 2104) of the concatenation of `parts`.
+fn hmac_sha256(key: &[u8], parts: &[&[u8]]) -> Mac {
+    const BLOCK_LEN: usize = 64;
+
+    let mut block = [0u8; BLOCK_LEN];
+    if key.len() > BLOCK_LEN {
+        block[..32].copy_from_slice(&sha256(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let mut inner = Sha256::default();
+    inner.input(&block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+    for part in parts {
+        inner.input(part);
+    }
+
+    let mut outer = Sha256::default();
+    outer.input(&block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+    outer.input(inner.result().as_slice());
+
+    let mut out = [0u8; 32];
+    out.copy_from_slice(outer.result().as_slice());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Sink;
+    use futures::sync::mpsc;
+    use tokio::net::TcpListener;
+    use server::block_on_all;
+    use container::ControlDispatcher;
+    use config::Config;
+
+    const CONFIG: &str = "
+applications:
+  - name: hello_world
+    path: ./hello_world
+migration:
+  token: secret
+";
+
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_hmac_sha256() {
+        // RFC 4231, test cases 2 and 6.
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", &[b"what do ya want ", b"for nothing?"])),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(&[0xaa; 131], &[b"Test Using Larger Than Block-Size Key - Hash Key First"])),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_session_mac() {
+        let session = Session {
+            token: Rc::new("secret".into()),
+            target_nonce: [1; 32],
+            source_nonce: [2; 32],
+            app: "hello_world".into()
+        };
+        let source = session.mac(b"source", &[]);
+        assert!(source != session.mac(b"target", &[]));
+        assert!(session.mac(b"state", b"a") != session.mac(b"state", b"b"));
+
+        let other = Session {
+            token: Rc::new("guess".into()),
+            ..session
+        };
+        assert!(source != other.mac(b"source", &[]));
+    }
+
+    /// A host whose application manager is played by the test.
+    fn host(token: &str) -> (Container, mpsc::Receiver<Control>) {
+        let mut config: Config = ::serde_yaml::from_str(CONFIG).unwrap();
+        config.migration.as_mut().unwrap().token = token.into();
+
+        let container = Container::new(String::new(), config);
+        let (tx, rx) = mpsc::channel(16);
+        container.set_control_dispatcher(ControlDispatcher::new(tx));
+        (container, rx)
+    }
+
+    fn test_migration() -> AppMigration {
+        AppMigration {
+            code_sha256: [7; 32],
+            memory: vec! [ 1, 2, 3, 4 ],
+            globals: vec! [ 42 ],
+            ..Default::default()
+        }
+    }
+
+    /// Plays the manager of the source, which is asked for the state first
+    /// and then told how the migration ended.
+    ///
+    /// Resolves to the state the application was to resume with.
+    fn source_manager(rx: mpsc::Receiver<Control>) -> impl Future<Item = Option<AppMigration>, Error = io::Error> {
+        rx.into_future().and_then(|(c, rx)| {
+            match c {
+                Some(Control::PrepareMigration { mut sender, .. }) => {
+                    let _ = sender.start_send(Ok(test_migration()));
+                },
+                _ => panic!("expected PrepareMigration")
+            }
+            rx.into_future()
+        }).map(|(c, _)| match c {
+            Some(Control::FinishMigration { migration, .. }) => migration,
+            _ => panic!("expected FinishMigration")
+        }).map_err(|_| io::Error::new(io::ErrorKind::Other, "manager"))
+    }
+
+    /// Moves `test_migration()` between two hosts on 127.0.0.1, the target
+    /// answering the activation with `activated`. Both run on the executor
+    /// of the main thread, as the listener and `MigrateTo` do.
+    ///
+    /// Returns the result on the source, the state the source was told to
+    /// resume with, and the state the target was asked to activate.
+    fn migrate(
+        source_token: &str,
+        target_token: &str,
+        activated: Result<(), String>
+    ) -> (Result<(), String>, Option<AppMigration>, Option<AppMigration>) {
+        let (source, source_rx) = host(source_token);
+        let (target, target_rx) = host(target_token);
+
+        // Ends once the target host has been dropped after `accept`.
+        let target_manager = target_rx.fold(None, move |received, c| {
+            match c {
+                Control::ActivateMigration { migration, mut sender, .. } => {
+                    let _ = sender.start_send(activated.clone());
+                    Ok(Some(migration))
+                },
+                _ => Ok(received)
+            }
+        }).map_err(|_| io::Error::new(io::ErrorKind::Other, "manager"));
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let token = Rc::new(target_token.to_string());
+        let target_side = listener.incoming().into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(stream, _)| {
+                accept(target, token, stream.unwrap())
+                    .map_err(|_| io::Error::new(io::ErrorKind::Other, "accept"))
+            });
+
+        let (result, resumed, _, received) = block_on_all(
+            migrate_to(&source, 0, "hello_world".into(), format!("{}", addr))
+                .join4(source_manager(source_rx), target_side, target_manager)
+        ).unwrap();
+
+        (result, resumed, received)
+    }
+
+    #[test]
+    fn test_migrate() {
+        let (result, resumed, received) = migrate("secret", "secret", Ok(()));
+        assert!(result.is_ok());
+        assert!(resumed.is_none());
+
+        let received = received.unwrap();
+        assert_eq!(received.code_sha256, [7; 32]);
+        assert_eq!(received.memory, vec! [ 1, 2, 3, 4 ]);
+        assert_eq!(received.globals, vec! [ 42 ]);
+    }
+
+    #[test]
+    fn test_migrate_wrong_token() {
+        let (result, resumed, received) = migrate("guess", "secret", Ok(()));
+        assert!(result.unwrap_err().contains("Invalid token"));
+        assert_eq!(resumed.unwrap().memory, vec! [ 1, 2, 3, 4 ]);
+        assert!(received.is_none());
+    }
+
+    #[test]
+    fn test_migrate_rejected() {
+        let (result, resumed, received) = migrate("secret", "secret", Err("Code mismatch".into()));
+        assert!(result.unwrap_err().contains("Code mismatch"));
+        assert!(resumed.is_some());
+        assert!(received.is_some());
+    }
+
+    #[test]
+    fn test_migrate_unreachable() {
+        let addr = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap().local_addr().unwrap();
+        let (source, source_rx) = host("secret");
+
+        let (result, resumed) = block_on_all(
+            migrate_to(&source, 0, "hello_world".into(), format!("{}", addr)).join(source_manager(source_rx))
+        ).unwrap();
+        assert!(result.is_err());
+        assert!(resumed.is_some());
+    }
+}
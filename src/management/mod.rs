@@ -0,0 +1,385 @@
+pub mod protocol;
+mod migration;
+
+use std::io;
+use std::net::SocketAddr;
+use std::rc::Rc;
+
+use futures;
+use futures::{Future, Stream};
+use futures::future::Loop;
+use futures::sync::mpsc;
+use tokio;
+use tokio::net::TcpListener;
+use tokio_io::{AsyncRead, AsyncWrite};
+use bincode;
+use serde::Serialize;
+
+use container::Container;
+use config::{Config, ManagementConfig};
+use lssa::app::AppMigration;
+use lssa::control::Control;
+use lssa::manager::ReloadReport;
+use lssa::stats::StatsRequest;
+use self::protocol::{Request, Response, Command, AppInfo, AppStats};
+
+type BoxedFuture<T> = Box<Future<Item = T, Error = io::Error>>;
+
+/// Starts the management and migration listeners that are configured.
+pub fn start(container: &Container) {
+    migration::start(container);
+
+    let cfg = match container.config_state.read().unwrap().config.management.clone() {
+        Some(v) => v,
+        None => return
+    };
+
+    if cfg.token.is_empty() {
+        derror!(
+            logger!("management"),
+            "management.token must not be empty; management API disabled"
+        );
+        return;
+    }
+
+    tokio::executor::current_thread::spawn(run(container.clone(), cfg));
+}
+
+/// Re-reads the config file and applies it to the running applications.
+pub fn reload(container: &Container) -> BoxedFuture<Result<ReloadReport, String>> {
+    let config = match Config::from_file(&container.config_path) {
+        Ok(v) => v,
+        Err(e) => return Box::new(futures::future::ok(
+            Err(format!("Invalid config file: {}", e))
+        ))
+    };
+
+    Box::new(query(container, |sender| Control::Reload {
+        config: config,
+        sender: sender
+    }).map(Ok))
+}
+
+fn run(container: Container, cfg: ManagementConfig) -> Box<Future<Item = (), Error = ()>> {
+    let token = Rc::new(cfg.token.clone());
+    let listen = cfg.listen.clone();
+
+    let server: BoxedFuture<()> = if cfg.listen.starts_with("unix:") {
+        let path = cfg.listen["unix:".len()..].to_string();
+        Box::new(futures::future::result(bind_unix(&path)).and_then(move |listener| {
+            dinfo!(logger!("management"), "Listening on unix:{}", path);
+            serve(container, token, listener.incoming())
+        }))
+    } else {
+        Box::new(futures::future::result(bind_tcp(&cfg.listen)).and_then(move |listener| {
+            dinfo!(logger!("management"), "Listening on {}", listen);
+            serve(container, token, listener.incoming())
+        }))
+    };
+
+    let listen = cfg.listen;
+    Box::new(server.map_err(move |e| {
+        derror!(
+            logger!("management"),
+            "Management listener on {} failed: {:?}",
+            listen,
+            e
+        );
+    }))
+}
+
+/// Only loopback addresses are accepted since the protocol is not encrypted.
+fn bind_tcp(addr: &str) -> io::Result<TcpListener> {
+    let addr: SocketAddr = addr.parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))?;
+    if !addr.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the management API may only listen on a loopback address"
+        ));
+    }
+    TcpListener::bind(&addr)
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &str) -> io::Result<::tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // Remove a socket left behind by a previous run, but nothing else.
+    if let Ok(meta) = ::std::fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            ::std::fs::remove_file(path)?;
+        }
+    }
+    ::tokio::net::UnixListener::bind(path)
+}
+
+#[cfg(not(unix))]
+fn bind_unix(_path: &str) -> io::Result<TcpListener> {
+    Err(io::Error::new(io::ErrorKind::Other, "unix sockets are not supported on this platform"))
+}
+
+fn serve<S, I>(container: Container, token: Rc<String>, incoming: I) -> impl Future<Item = (), Error = io::Error>
+    where S: AsyncRead + AsyncWrite + 'static,
+          I: Stream<Item = S, Error = io::Error>
+{
+    incoming.for_each(move |stream| {
+        tokio::executor::current_thread::spawn(
+            handle_connection(container.clone(), token.clone(), stream)
+        );
+        Ok(())
+    })
+}
+
+fn handle_connection<S: AsyncRead + AsyncWrite + 'static>(
+    container: Container,
+    token: Rc<String>,
+    stream: S
+) -> impl Future<Item = (), Error = ()> {
+    // Large frames are only read once the peer has shown it knows the token.
+    futures::future::loop_fn((stream, false), move |(stream, authenticated)| {
+        let container = container.clone();
+        let token = token.clone();
+        let max_len = if authenticated {
+            protocol::MAX_FRAME_LEN
+        } else {
+            protocol::MAX_REQUEST_LEN
+        };
+
+        read_frame(stream, max_len).and_then(move |frame| -> BoxedFuture<Loop<(), (S, bool)>> {
+            let (stream, frame) = match frame {
+                Some(v) => v,
+                None => return Box::new(futures::future::ok(Loop::Break(())))
+            };
+            let (authenticated, resp) = match authenticate(&token, &frame) {
+                Ok(command) => (true, handle_command(&container, command)),
+                Err(resp) => (authenticated, Box::new(futures::future::ok(resp)) as BoxedFuture<Response>)
+            };
+            Box::new(resp.and_then(move |resp| {
+                write_frame(stream, &resp)
+            }).map(move |stream| Loop::Continue((stream, authenticated))))
+        })
+    }).map_err(|e| {
+        dwarning!(logger!("management"), "Connection closed: {:?}", e);
+    })
+}
+
+/// Reads a frame of up to `max_len` bytes, or `None` if the peer closed the connection.
+fn read_frame<S: AsyncRead + 'static>(stream: S, max_len: usize) -> BoxedFuture<Option<(S, Vec<u8>)>> {
+    Box::new(::tokio_io::io::read_exact(stream, [0u8; 4]).then(move |result| -> BoxedFuture<Option<(S, Vec<u8>)>> {
+        let (stream, header) = match result {
+            Ok(v) => v,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Box::new(futures::future::ok(None));
+            },
+            Err(e) => return Box::new(futures::future::err(e))
+        };
+        let len = match protocol::decode_frame_len(&header, max_len) {
+            Some(v) => v,
+            None => return Box::new(futures::future::err(
+                io::Error::new(io::ErrorKind::InvalidData, "frame too large")
+            ))
+        };
+        Box::new(::tokio_io::io::read_exact(stream, vec! [ 0u8; len ]).map(|(stream, data)| {
+            Some((stream, data))
+        }))
+    }))
+}
+
+fn write_frame<S: AsyncWrite + 'static, T: Serialize>(stream: S, msg: &T) -> BoxedFuture<S> {
+    let payload = bincode::serialize(msg).unwrap();
+    Box::new(::tokio_io::io::write_all(stream, protocol::encode_frame(&payload)).map(|(stream, _)| stream))
+}
+
+/// Decodes a request and checks its token.
+fn authenticate(token: &str, frame: &[u8]) -> Result<Command, Response> {
+    let req: Request = match bincode::deserialize(frame) {
+        Ok(v) => v,
+        Err(e) => return Err(Response::Error(format!("Invalid request: {}", e)))
+    };
+
+    if !token_eq(req.token.as_bytes(), token.as_bytes()) {
+        dwarning!(logger!("management"), "Rejected a request with an invalid token");
+        return Err(Response::Error("Invalid token".into()));
+    }
+
+    Ok(req.command)
+}
+
+fn handle_command(container: &Container, command: Command) -> BoxedFuture<Response> {
+    let resp: BoxedFuture<Response> = match command {
+        Command::Ping => Box::new(futures::future::ok(Response::Ok)),
+        Command::ListApps => Box::new(query(container, |sender| Control::ListApps {
+            sender: sender
+        }).map(|apps| Response::Apps(
+            apps.into_iter().map(|app| AppInfo {
+                id: app.id,
+                name: app.name,
+                running: app.running,
+                crashed: app.crashed
+            }).collect()
+        ))),
+        Command::Stats => Box::new(query(container, |sender| Control::Stats(StatsRequest {
+            feedback: sender
+        })).map(|stats| Response::Stats(
+            stats.applications.into_iter().map(|(name, s)| (name, AppStats {
+                start_time: s.start_time,
+                running_time: s.running_time,
+                callback_timeouts: s.callback_timeouts,
+                memory: s.memory as u64,
+                memory_peak: s.memory_peak as u64,
+                host_memory: s.host_memory as u64,
+                files: s.resources.files as u64,
+                tcp_streams: s.resources.tcp_streams as u64,
+                listeners: s.resources.listeners as u64,
+                pending_callbacks: s.resources.pending_callbacks as u64,
+                buffers: s.resources.buffers as u64,
+                udp_sockets: s.resources.udp_sockets as u64,
+                crashed: s.crashed
+            })).collect()
+        ))),
+        Command::MigrateOut { app } => match lookup_app(container, &app) {
+            Ok(app_id) => Box::new(query(container, |sender| Control::MigrateAway {
+                app_id: app_id,
+                sender: sender
+            }).map(|result| match result {
+                Ok(mig) => Response::Migration(bincode::serialize(&mig).unwrap()),
+                Err(e) => Response::Error(e)
+            })),
+            Err(resp) => Box::new(futures::future::ok(resp))
+        },
+        Command::MigrateIn { app, migration } => {
+            let migration: AppMigration = match bincode::deserialize(&migration) {
+                Ok(v) => v,
+                Err(e) => return Box::new(futures::future::ok(
+                    Response::Error(format!("Invalid migration: {}", e))
+                ))
+            };
+            match lookup_app(container, &app) {
+                Ok(app_id) => Box::new(query(container, |sender| Control::ActivateMigration {
+                    app_id: app_id,
+                    migration: migration,
+                    sender: sender
+                }).map(to_response)),
+                Err(resp) => Box::new(futures::future::ok(resp))
+            }
+        },
+        Command::Checkpoint { app } => match lookup_app(container, &app) {
+            Ok(app_id) => Box::new(query(container, |sender| Control::Checkpoint {
+                app_id: app_id,
+                sender: Some(sender)
+            }).map(to_response)),
+            Err(resp) => Box::new(futures::future::ok(resp))
+        },
+        Command::Upgrade { app, code } => match lookup_app(container, &app) {
+            Ok(app_id) => Box::new(query(container, |sender| Control::Upgrade {
+                app_id: app_id,
+                code: code,
+                sender: sender
+            }).map(to_response)),
+            Err(resp) => Box::new(futures::future::ok(resp))
+        },
+        Command::LoadApp { app } => load_app(container, app),
+        Command::UnloadApp { app } => match lookup_app(container, &app) {
+            Ok(app_id) => Box::new(query(container, |sender| Control::UnloadApp {
+                app_id: app_id,
+                sender: sender
+            }).map(to_response)),
+            Err(resp) => Box::new(futures::future::ok(resp))
+        },
+        Command::MigrateTo { app, target } => match lookup_app(container, &app) {
+            Ok(app_id) => Box::new(migration::migrate_to(container, app_id, app, target).map(to_response)),
+            Err(resp) => Box::new(futures::future::ok(resp))
+        },
+        Command::Reload => Box::new(reload(container).map(|result| match result {
+            Ok(report) => Response::Reloaded(protocol::ReloadReport {
+                started: report.started,
+                stopped: report.stopped,
+                updated: report.updated,
+                restart_required: report.restart_required,
+                errors: report.errors
+            }),
+            Err(e) => Response::Error(e)
+        })),
+        Command::Logs { app } => Box::new(futures::future::ok(match lookup_app(container, &app) {
+            Ok(_) => Response::Logs(container.app_logs.recent(&app)),
+            Err(resp) => resp
+        }))
+    };
+
+    Box::new(resp.or_else(|e| Ok(Response::Error(format!("{}", e)))))
+}
+
+/// Starts an application that is loaded but not running, e.g. a `deferred`
+/// one, or loads it from the config file otherwise.
+fn load_app(container: &Container, name: String) -> BoxedFuture<Response> {
+    let container = container.clone();
+
+    Box::new(query(&container, |sender| Control::ListApps {
+        sender: sender
+    }).and_then(move |apps| -> BoxedFuture<Response> {
+        match apps.into_iter().find(|app| app.name == name) {
+            Some(ref app) if app.running => Box::new(futures::future::ok(
+                Response::Error(format!("Application `{}` is already running", name))
+            )),
+            Some(app) => Box::new(query(&container, |sender| Control::StartApp {
+                app_id: app.id,
+                sender: sender
+            }).map(to_response)),
+            None => {
+                let config = match Config::from_file(&container.config_path) {
+                    Ok(v) => v,
+                    Err(e) => return Box::new(futures::future::ok(
+                        Response::Error(format!("Invalid config file: {}", e))
+                    ))
+                };
+                let app = match config.applications.into_iter().find(|app| app.name == name) {
+                    Some(v) => v,
+                    None => return Box::new(futures::future::ok(
+                        Response::Error(format!("Application `{}` not found in the config file", name))
+                    ))
+                };
+                Box::new(query(&container, |sender| Control::LoadApp {
+                    config: app,
+                    sender: sender
+                }).map(|result| to_response(result.map(|_| ()))))
+            }
+        }
+    }))
+}
+
+/// Sends a control message built by `f` to the manager and waits for its reply.
+fn query<T: 'static, F: FnOnce(mpsc::Sender<T>) -> Control>(container: &Container, f: F) -> BoxedFuture<T> {
+    let (tx, rx) = mpsc::channel(1);
+
+    if container.dispatch_control(f(tx)).is_err() {
+        return Box::new(futures::future::err(
+            io::Error::new(io::ErrorKind::Other, "Application manager is not available")
+        ));
+    }
+
+    Box::new(rx.into_future().then(|result| match result {
+        Ok((Some(v), _)) => Ok(v),
+        _ => Err(io::Error::new(io::ErrorKind::Other, "No response from the application manager"))
+    }))
+}
+
+fn lookup_app(container: &Container, name: &str) -> Result<usize, Response> {
+    container.lookup_app_id_by_name(name)
+        .ok_or_else(|| Response::Error(format!("Application `{}` not found", name)))
+}
+
+fn to_response(result: Result<(), String>) -> Response {
+    match result {
+        Ok(_) => Response::Ok,
+        Err(e) => Response::Error(e)
+    }
+}
+
+/// Compares tokens in time independent of where they differ.
+fn token_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
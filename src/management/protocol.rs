@@ -0,0 +1,157 @@
+//! Wire format of the management API.
+//!
+//! Every message is a bincode-encoded `Request` or `Response` preceded by
+//! its length as a big-endian `u32`. A connection carries any number of
+//! request/response pairs.
+//!
+//! This module only depends on `serde` so that clients can include it as is.
+
+use std::collections::BTreeMap;
+
+/// Upper bound of a frame. Migrations carry the whole linear memory of an
+/// application, so this is large.
+pub const MAX_FRAME_LEN: usize = 1 << 30;
+
+/// Upper bound of a request until one on the same connection has carried
+/// a valid token, so that unauthenticated peers can't make the server
+/// allocate `MAX_FRAME_LEN` bytes.
+pub const MAX_REQUEST_LEN: usize = 16 * 1048576;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Request {
+    /// Must match `management.token` in the server config.
+    pub token: String,
+    pub command: Command
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Command {
+    /// Does nothing. Sending it first lets the connection carry requests
+    /// larger than `MAX_REQUEST_LEN`.
+    Ping,
+
+    ListApps,
+    Stats,
+
+    /// Stops an application and returns its state.
+    MigrateOut { app: String },
+
+    /// Starts a stopped application from the state returned by `MigrateOut`.
+    MigrateIn { app: String, migration: Vec<u8> },
+
+    /// Moves a running application to the host whose migration listener
+    /// is at `target` (`host:port`).
+    MigrateTo { app: String, target: String },
+
+    /// Writes a snapshot of a running application to its `restore_from` file.
+    Checkpoint { app: String },
+
+    /// Replaces the code of a running application with `code` (a wasm binary).
+    Upgrade { app: String, code: Vec<u8> },
+
+    /// Starts an application that is not running, loading it from the
+    /// config file if it is not loaded.
+    LoadApp { app: String },
+
+    /// Stops an application and frees everything it holds.
+    UnloadApp { app: String },
+
+    /// Re-reads the config file.
+    Reload,
+
+    /// Fetches recent lines logged by an application.
+    Logs { app: String }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Response {
+    Ok,
+    Error(String),
+    Apps(Vec<AppInfo>),
+    Stats(BTreeMap<String, AppStats>),
+    Migration(Vec<u8>),
+    Logs(Vec<String>),
+    Reloaded(ReloadReport)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppInfo {
+    pub id: usize,
+    pub name: String,
+    pub running: bool,
+
+    /// Crashed and not restarted (yet).
+    pub crashed: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppStats {
+    /// Milliseconds since the Unix epoch.
+    pub start_time: i64,
+
+    /// Milliseconds.
+    pub running_time: i64,
+
+    /// Calls interrupted for exceeding `max_callback_ms`.
+    pub callback_timeouts: u64,
+
+    /// Bytes of linear memory.
+    pub memory: u64,
+
+    /// Highest `memory` seen.
+    pub memory_peak: u64,
+
+    /// Bytes held by the host on behalf of the application, e.g. received data.
+    pub host_memory: u64,
+
+    /// Usage of the resources capped by `limits`.
+    pub files: u64,
+    pub tcp_streams: u64,
+    pub listeners: u64,
+    pub pending_callbacks: u64,
+    pub buffers: u64,
+    pub udp_sockets: u64,
+
+    /// Crashed and not restarted (yet). The other fields are as of the crash.
+    pub crashed: bool
+}
+
+/// What changed when the config file was re-read.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReloadReport {
+    pub started: Vec<String>,
+    pub stopped: Vec<String>,
+    pub updated: Vec<String>,
+
+    /// Changes that only apply after restarting the server.
+    pub restart_required: Vec<String>,
+    pub errors: Vec<String>
+}
+
+/// Prepends the length header to an encoded message.
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    let len = payload.len() as u32;
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&[
+        (len >> 24) as u8,
+        (len >> 16) as u8,
+        (len >> 8) as u8,
+        len as u8
+    ]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Decodes a length header, rejecting frames larger than `max_len`.
+pub fn decode_frame_len(header: &[u8; 4], max_len: usize) -> Option<usize> {
+    let len = ((header[0] as usize) << 24)
+        | ((header[1] as usize) << 16)
+        | ((header[2] as usize) << 8)
+        | (header[3] as usize);
+
+    if len > max_len {
+        None
+    } else {
+        Some(len)
+    }
+}
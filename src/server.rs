@@ -14,16 +14,16 @@ pub struct Server {
 }
 
 impl Server {
-    pub fn new(config: Config) -> Server {
+    pub fn new(config_path: String, config: Config) -> Server {
         Server {
-            container: Container::new(config)
+            container: Container::new(config_path, config)
         }
     }
 
     fn launch_manager(container: Container) -> futures::sync::mpsc::Sender<Control> {
         let (tx, rx) = futures::sync::mpsc::channel(4096);
         ::std::thread::spawn(move || {
-            ::tokio::executor::current_thread::block_on_all(
+            block_on_all(
                 futures::future::ok(()).map(move |_| {
                     let mut manager = AppManager::new(container.clone());
                     load_apps_from_config(
@@ -47,9 +47,13 @@ impl Server {
         self.container.set_control_dispatcher(ControlDispatcher::new(tx));
 
         let container = self.container.clone();
-        let mut control_sender = Self::launch_manager(container);
+        let mut control_sender = Self::launch_manager(container.clone());
 
         futures::future::ok(()).then(move |_: Result<(), ()>| {
+            ::service::start_services(&container);
+            ::management::start(&container);
+            reload_on_sighup(&container);
+
             rx.for_each(move |c| {
                 control_sender.start_send(c).unwrap();
                 Ok(())
@@ -58,16 +62,43 @@ impl Server {
     }
 }
 
-fn load_apps_from_config(manager: &mut AppManager, config: &Config) {
-    use std::fs::File;
-    use std::io::Read;
+/// Runs `f` and the tasks it spawns on the calling thread until they are all done.
+///
+/// Unlike a bare executor, the runtime provides the timer that timeouts and
+/// the `timer` namespace rely on.
+pub fn block_on_all<F: Future>(f: F) -> Result<F::Item, F::Error> {
+    ::tokio::runtime::current_thread::block_on_all(f)
+}
 
+#[cfg(unix)]
+fn reload_on_sighup(container: &Container) {
+    use tokio_signal::unix::{Signal, SIGHUP};
+
+    let container = container.clone();
+
+    ::tokio::executor::current_thread::spawn(
+        Signal::new(SIGHUP).flatten_stream().for_each(move |_| {
+            dinfo!(logger!("reload"), "Received SIGHUP, reloading the config file");
+            ::management::reload(&container).then(|result| {
+                match result {
+                    Ok(Ok(_)) => {},
+                    Ok(Err(e)) => derror!(logger!("reload"), "{}", e),
+                    Err(e) => derror!(logger!("reload"), "{:?}", e)
+                }
+                Ok(())
+            })
+        }).map_err(|e| {
+            derror!(logger!("reload"), "Unable to handle SIGHUP: {:?}", e);
+        })
+    );
+}
+
+#[cfg(not(unix))]
+fn reload_on_sighup(_container: &Container) {}
+
+fn load_apps_from_config(manager: &mut AppManager, config: &Config) {
     for (i, app) in config.applications.iter().enumerate() {
-        let mut code_file = match File::open(
-            &::std::path::Path::new(
-                &app.path
-            ).join(&app.metadata.bin).to_str().unwrap()
-        ) {
+        let code = match AppManager::read_code(app) {
             Ok(v) => v,
             Err(e) => {
                 dwarning!(
@@ -79,9 +110,46 @@ fn load_apps_from_config(manager: &mut AppManager, config: &Config) {
                 continue;
             }
         };
-        let mut code: Vec<u8> = Vec::new();
-        code_file.read_to_end(&mut code).unwrap();
 
-        manager.load(&code, i, app.clone());
+        if let Err(e) = manager.load(&code, i, app.clone()) {
+            derror!(
+                logger!("load_apps_from_config"),
+                "Unable to start app `{}`: {}",
+                app.name,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::time::{Duration, Instant};
+    use tokio;
+    use lssa::ns::tcp::with_timeout;
+
+    fn never() -> impl Future<Item = (), Error = io::Error> {
+        futures::future::empty()
+    }
+
+    #[test]
+    fn test_timeout() {
+        let start = Instant::now();
+        let e = block_on_all(with_timeout(never(), Duration::from_millis(50))).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        block_on_all(with_timeout(futures::future::ok(()), Duration::from_secs(5))).unwrap();
+    }
+
+    #[test]
+    fn test_timeout_without_timer() {
+        // A bare executor has no timer, which must not pass for a timeout.
+        let e = tokio::executor::current_thread::block_on_all(
+            with_timeout(never(), Duration::from_secs(5))
+        ).unwrap_err();
+        assert!(e.kind() != io::ErrorKind::TimedOut);
     }
 }
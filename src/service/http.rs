@@ -0,0 +1,407 @@
+use std::io;
+use std::net::SocketAddr;
+
+use futures;
+use futures::{Future, Stream, Async, Poll};
+use futures::future::{Either, Loop};
+use futures::sync::oneshot;
+use tokio;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::prelude::AsyncRead;
+use httparse;
+
+use container::Container;
+use config::ServiceConfig;
+use lssa::control::Control;
+use lssa::event::EventInfo;
+use lssa::ns::http::{HttpRequest, HttpResponse, HttpRequestEvent};
+
+const MAX_HEADERS: usize = 64;
+const MAX_HEAD_SIZE: usize = 65536;
+const MAX_BODY_SIZE: usize = 16 * 1048576;
+
+/// Runs an HTTP/1.1 service that forwards requests to `svc.app`.
+pub fn run(container: Container, svc: ServiceConfig) -> impl Future<Item = (), Error = ()> {
+    let addr = svc.address.clone();
+
+    futures::future::result(
+        svc.address.parse::<SocketAddr>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))
+            .and_then(|addr| TcpListener::bind(&addr))
+    ).and_then(move |listener| {
+        dinfo!(
+            logger!("http_service"),
+            "Listening on {} for application `{}`",
+            svc.address,
+            svc.app
+        );
+        listener.incoming().for_each(move |stream| {
+            tokio::executor::current_thread::spawn(
+                handle_connection(container.clone(), svc.clone(), stream)
+            );
+            Ok(())
+        })
+    }).map_err(move |e| {
+        derror!(
+            logger!("http_service"),
+            "Service on {} failed: {:?}",
+            addr,
+            e
+        );
+    })
+}
+
+fn handle_connection(
+    container: Container,
+    svc: ServiceConfig,
+    stream: TcpStream
+) -> impl Future<Item = (), Error = ()> {
+    futures::future::loop_fn((stream, Vec::new()), move |(stream, buf)| {
+        let container = container.clone();
+        let svc = svc.clone();
+
+        ReadRequest::new(stream, buf).and_then(move |(stream, req, rest)| {
+            let (req, keep_alive) = match req {
+                Incoming::Request(req, keep_alive) => (req, keep_alive),
+                Incoming::Closed => return Either::A(Either::A(futures::future::ok(Loop::Break(())))),
+                Incoming::Bad(e) => {
+                    dwarning!(logger!("http_service"), "Bad request: {}", e.reason);
+                    return Either::A(Either::B(
+                        tokio::io::write_all(stream, serialize_response(&HttpResponse::new(e.status), false))
+                            .map(|_| Loop::Break(()))
+                    ));
+                }
+            };
+
+            Either::B(
+                dispatch(&container, &svc, req)
+                    .and_then(move |resp| {
+                        tokio::io::write_all(stream, serialize_response(&resp, keep_alive))
+                    })
+                    .map(move |(stream, _)| if keep_alive {
+                        Loop::Continue((stream, rest))
+                    } else {
+                        Loop::Break(())
+                    })
+            )
+        })
+    }).map_err(|e: io::Error| {
+        dwarning!(logger!("http_service"), "Connection error: {:?}", e);
+    })
+}
+
+fn dispatch(
+    container: &Container,
+    svc: &ServiceConfig,
+    req: HttpRequest
+) -> impl Future<Item = HttpResponse, Error = io::Error> {
+    let (tx, rx) = oneshot::channel();
+
+    if let Some(app_id) = super::lookup_service_app(container, svc) {
+        let _ = container.dispatch_control(Control::Event(EventInfo::new(
+            app_id,
+            HttpRequestEvent::new(req, tx, svc.entry().to_string())
+        )));
+    }
+
+    // The sender is dropped if the request isn't handled.
+    rx.or_else(|_| Ok(HttpResponse::new(503)))
+}
+
+/// A request that is answered with `status` and then closes the connection.
+#[derive(Debug)]
+struct BadRequest {
+    status: u16,
+    reason: &'static str
+}
+
+fn bad_request(reason: &'static str) -> BadRequest {
+    BadRequest {
+        status: 400,
+        reason: reason
+    }
+}
+
+fn too_large(reason: &'static str) -> BadRequest {
+    BadRequest {
+        status: 413,
+        reason: reason
+    }
+}
+
+/// Tries to parse a complete request from the beginning of `buf`.
+///
+/// Returns the request, whether the connection should be kept alive and
+/// the number of bytes consumed.
+fn try_parse(buf: &[u8]) -> Result<Option<(HttpRequest, bool, usize)>, BadRequest> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut req = httparse::Request::new(&mut headers);
+
+    let head_len = match req.parse(buf) {
+        Ok(httparse::Status::Complete(n)) => n,
+        Ok(httparse::Status::Partial) => {
+            if buf.len() > MAX_HEAD_SIZE {
+                return Err(too_large("request head too large"));
+            }
+            return Ok(None);
+        },
+        Err(_) => return Err(bad_request("malformed request head"))
+    };
+
+    let headers: Vec<(String, String)> = req.headers.iter()
+        .map(|h| (h.name.to_string(), String::from_utf8_lossy(h.value).into_owned()))
+        .collect();
+
+    let mut content_length: Option<usize> = None;
+    let mut connection: Option<String> = None;
+
+    for &(ref k, ref v) in &headers {
+        if k.eq_ignore_ascii_case("Content-Length") {
+            // A proxy in front may pick another one than we would, so
+            // anything but plain digits and repeats of the same value is refused.
+            let v = v.trim();
+            if v.is_empty() || !v.bytes().all(|c| c.is_ascii_digit()) {
+                return Err(bad_request("invalid content length"));
+            }
+            let len = v.parse().map_err(|_| too_large("request body too large"))?;
+            if content_length.map(|prev| prev != len).unwrap_or(false) {
+                return Err(bad_request("conflicting content lengths"));
+            }
+            content_length = Some(len);
+        } else if k.eq_ignore_ascii_case("Transfer-Encoding") {
+            return Err(bad_request("transfer encodings are not supported"));
+        } else if k.eq_ignore_ascii_case("Connection") {
+            connection = Some(v.trim().to_lowercase());
+        }
+    }
+
+    let content_length = content_length.unwrap_or(0);
+    if content_length > MAX_BODY_SIZE {
+        return Err(too_large("request body too large"));
+    }
+    if buf.len() < head_len + content_length {
+        return Ok(None);
+    }
+
+    let keep_alive = match req.version {
+        Some(1) => connection.as_ref().map(|v| v.as_str()) != Some("close"),
+        _ => connection.as_ref().map(|v| v.as_str()) == Some("keep-alive")
+    };
+
+    Ok(Some((
+        HttpRequest {
+            method: req.method.unwrap_or("").to_string(),
+            path: req.path.unwrap_or("").to_string(),
+            headers: headers,
+            body: buf[head_len .. head_len + content_length].to_vec()
+        },
+        keep_alive,
+        head_len + content_length
+    )))
+}
+
+enum Incoming {
+    /// A request, and whether the connection should be kept alive after it.
+    Request(HttpRequest, bool),
+
+    /// The peer closed the connection before sending anything.
+    Closed,
+
+    Bad(BadRequest)
+}
+
+/// Reads a request from a stream.
+struct ReadRequest {
+    stream: Option<TcpStream>,
+    buf: Vec<u8>
+}
+
+impl ReadRequest {
+    fn new(stream: TcpStream, buf: Vec<u8>) -> ReadRequest {
+        ReadRequest {
+            stream: Some(stream),
+            buf: buf
+        }
+    }
+}
+
+impl Future for ReadRequest {
+    type Item = (TcpStream, Incoming, Vec<u8>);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            match try_parse(&self.buf) {
+                Ok(Some((req, keep_alive, consumed))) => {
+                    let rest = self.buf.split_off(consumed);
+                    return Ok(Async::Ready((
+                        self.stream.take().unwrap(),
+                        Incoming::Request(req, keep_alive),
+                        rest
+                    )));
+                },
+                Ok(None) => {},
+                Err(e) => return Ok(Async::Ready((self.stream.take().unwrap(), Incoming::Bad(e), Vec::new())))
+            }
+
+            let mut chunk = [0u8; 4096];
+            let n = match self.stream.as_mut().unwrap().poll_read(&mut chunk)? {
+                Async::Ready(n) => n,
+                Async::NotReady => return Ok(Async::NotReady)
+            };
+
+            if n == 0 {
+                if self.buf.is_empty() {
+                    return Ok(Async::Ready((self.stream.take().unwrap(), Incoming::Closed, Vec::new())));
+                }
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete request"));
+            }
+            self.buf.extend_from_slice(&chunk[0..n]);
+        }
+    }
+}
+
+fn serialize_response(resp: &HttpResponse, keep_alive: bool) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {} {}\r\n", resp.status, status_reason(resp.status));
+
+    for &(ref k, ref v) in &resp.headers {
+        // Framing is controlled by the host.
+        if k.eq_ignore_ascii_case("Content-Length") || k.eq_ignore_ascii_case("Connection")
+            || k.eq_ignore_ascii_case("Transfer-Encoding") {
+            continue;
+        }
+        head.push_str(&format!("{}: {}\r\n", k, v));
+    }
+    head.push_str(&format!("Content-Length: {}\r\n", resp.body.len()));
+    head.push_str(if keep_alive {
+        "Connection: keep-alive\r\n\r\n"
+    } else {
+        "Connection: close\r\n\r\n"
+    });
+
+    let mut out = head.into_bytes();
+    out.extend_from_slice(&resp.body);
+    out
+}
+
+fn status_reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        206 => "Partial Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        303 => "See Other",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        411 => "Length Required",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(req: &str) -> Result<Option<(HttpRequest, bool, usize)>, BadRequest> {
+        try_parse(req.as_bytes())
+    }
+
+    fn status_of(req: &str) -> u16 {
+        parse(req).unwrap_err().status
+    }
+
+    #[test]
+    fn test_parse() {
+        let (req, keep_alive, consumed) = parse(
+            "POST /a?b HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\n\r\nhelloGET / HTTP/1.1\r\n"
+        ).unwrap().unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.path, "/a?b");
+        assert_eq!(req.headers[0], ("Host".to_string(), "x".to_string()));
+        assert_eq!(req.body, b"hello");
+        assert!(keep_alive);
+        assert_eq!(consumed, 55);
+
+        let (_, keep_alive, _) = parse("GET / HTTP/1.0\r\n\r\n").unwrap().unwrap();
+        assert!(!keep_alive);
+        let (_, keep_alive, _) = parse("GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap().unwrap();
+        assert!(!keep_alive);
+    }
+
+    #[test]
+    fn test_parse_partial() {
+        assert!(parse("GET / HTTP/1.1\r\nHost:").unwrap().is_none());
+        assert!(parse("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_content_length() {
+        let (req, _, _) = parse(
+            "POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nhi"
+        ).unwrap().unwrap();
+        assert_eq!(req.body, b"hi");
+
+        assert_eq!(status_of("POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\nhi!"), 400);
+        assert_eq!(status_of("POST / HTTP/1.1\r\nContent-Length: 2, 3\r\n\r\n"), 400);
+        assert_eq!(status_of("POST / HTTP/1.1\r\nContent-Length: +2\r\n\r\nhi"), 400);
+        assert_eq!(status_of("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n"), 400);
+    }
+
+    #[test]
+    fn test_parse_bad() {
+        assert_eq!(status_of("GET / HTTP/1.1\r\nBad Header\r\n\r\n"), 400);
+        assert_eq!(
+            status_of(&format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1)),
+            413
+        );
+        assert_eq!(status_of("POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n"), 413);
+
+        let mut head = "GET / HTTP/1.1\r\n".to_string();
+        while head.len() <= MAX_HEAD_SIZE {
+            head.push_str("X-Padding: 0123456789\r\n");
+        }
+        // Too many headers fail before the size is reached.
+        assert_eq!(status_of(&head), 400);
+        let long = format!("GET /{} HTTP/1.1\r\n", "a".repeat(MAX_HEAD_SIZE));
+        assert_eq!(status_of(&long), 413);
+    }
+
+    #[test]
+    fn test_serialize_response() {
+        let mut resp = HttpResponse::new(404);
+        resp.headers.push(("Content-Type".into(), "text/plain".into()));
+        resp.headers.push(("Content-Length".into(), "100".into()));
+        resp.headers.push(("Transfer-Encoding".into(), "chunked".into()));
+        resp.headers.push(("connection".into(), "upgrade".into()));
+        resp.body = b"nope".to_vec();
+
+        assert_eq!(
+            String::from_utf8(serialize_response(&resp, true)).unwrap(),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 4\r\nConnection: keep-alive\r\n\r\nnope"
+        );
+        assert_eq!(
+            String::from_utf8(serialize_response(&HttpResponse::new(799), false)).unwrap(),
+            "HTTP/1.1 799 Unknown\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    }
+}
@@ -0,0 +1,35 @@
+pub mod http;
+pub mod tcp;
+
+use tokio;
+
+use container::Container;
+use config::{ServiceConfig, ServiceKind};
+
+/// Starts all services declared in the `services` section of the config.
+pub fn start_services(container: &Container) {
+    let services = container.config_state.read().unwrap().config.services.clone();
+
+    for svc in services {
+        match svc.kind {
+            ServiceKind::Http => tokio::executor::current_thread::spawn(
+                http::run(container.clone(), svc)
+            ),
+            ServiceKind::Tcp => tokio::executor::current_thread::spawn(
+                tcp::run(container.clone(), svc)
+            )
+        }
+    }
+}
+
+fn lookup_service_app(container: &Container, svc: &ServiceConfig) -> Option<usize> {
+    let id = container.lookup_app_id_by_name(&svc.app);
+    if id.is_none() {
+        derror!(
+            logger!(&svc.address),
+            "Application `{}` not found",
+            svc.app
+        );
+    }
+    id
+}
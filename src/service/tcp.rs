@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::io;
+use std::net::SocketAddr;
+
+use futures;
+use futures::{Future, Stream};
+use tokio::net::{TcpListener, TcpStream};
+
+use container::Container;
+use config::ServiceConfig;
+use lssa::app::Application;
+use lssa::control::Control;
+use lssa::event::{Event, EventInfo};
+use lssa::ns::tcp::TcpNs;
+
+/// Runs a TCP service that hands each accepted stream to `svc.app`.
+pub fn run(container: Container, svc: ServiceConfig) -> impl Future<Item = (), Error = ()> {
+    let addr = svc.address.clone();
+
+    futures::future::result(bind(&svc.address)).and_then(move |listener| {
+        dinfo!(
+            logger!("tcp_service"),
+            "Listening on {} for application `{}`",
+            svc.address,
+            svc.app
+        );
+        serve(listener, move |stream| {
+            if let Some(app_id) = super::lookup_service_app(&container, &svc) {
+                let _ = container.dispatch_control(Control::Event(EventInfo::new(
+                    app_id,
+                    TcpAcceptEvent {
+                        stream: RefCell::new(Some(stream)),
+                        entry: svc.entry().to_string()
+                    }
+                )));
+            }
+        })
+    }).map_err(move |e| {
+        derror!(
+            logger!("tcp_service"),
+            "Service on {} failed: {:?}",
+            addr,
+            e
+        );
+    })
+}
+
+fn bind(address: &str) -> io::Result<TcpListener> {
+    address.parse::<SocketAddr>()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid address"))
+        .and_then(|addr| TcpListener::bind(&addr))
+}
+
+/// Passes each stream accepted by `listener` to `on_accept`.
+fn serve<F: FnMut(TcpStream)>(listener: TcpListener, mut on_accept: F) -> impl Future<Item = (), Error = io::Error> {
+    listener.incoming().for_each(move |stream| {
+        on_accept(stream);
+        Ok(())
+    })
+}
+
+/// Adds an accepted stream to the `tcp` namespace of an application and
+/// passes its id to the exported function `entry`.
+struct TcpAcceptEvent {
+    stream: RefCell<Option<TcpStream>>,
+    entry: String
+}
+
+impl Event for TcpAcceptEvent {
+    fn notify(&self, app: &Application) {
+        let stream = match self.stream.borrow_mut().take() {
+            Some(v) => v,
+            None => return
+        };
+
+        let stream_id = match app.with_namespace("ice", "tcp", |ns: &TcpNs| {
+            ns.provider().add_stream(stream)
+        }) {
+            Some(Ok(v)) => v,
+
+            // The connection is closed.
+            Some(Err(code)) => {
+                dwarning!(
+                    logger!(app.name()),
+                    "Rejected a connection to the TCP service: {:?}",
+                    code
+                );
+                return;
+            },
+            None => {
+                derror!(
+                    logger!(app.name()),
+                    "Rejected a connection to the TCP service: the tcp namespace is not available"
+                );
+                return;
+            }
+        };
+
+        if app.call_exported1(&self.entry, stream_id as i32).is_none() {
+            derror!(
+                logger!(app.name()),
+                "TCP entry `{}` is not exported",
+                self.entry
+            );
+            app.with_namespace("ice", "tcp", |ns: &TcpNs| {
+                ns.provider().remove_stream(stream_id);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio;
+    use server::block_on_all;
+    use tokio::prelude::AsyncRead;
+
+    #[test]
+    fn test_accept_and_echo() {
+        let listener = bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        // Echoes what it reads back to the peer, as an application would.
+        let server = serve(listener, |stream| {
+            let (reader, writer) = stream.split();
+            tokio::executor::current_thread::spawn(
+                tokio::io::copy(reader, writer).map(|_| ()).map_err(|_| ())
+            );
+        });
+
+        let client = TcpStream::connect(&addr)
+            .and_then(|stream| tokio::io::write_all(stream, b"hello"))
+            .and_then(|(stream, _)| tokio::io::read_exact(stream, [0u8; 5]))
+            .map(|(_, buf)| buf);
+
+        let buf = match block_on_all(client.select2(server)) {
+            Ok(futures::future::Either::A((buf, _))) => buf,
+            _ => panic!("the service ended first")
+        };
+        assert_eq!(&buf, b"hello");
+    }
+
+    #[test]
+    fn test_bind_invalid() {
+        assert_eq!(bind("localhost").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
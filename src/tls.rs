@@ -0,0 +1,143 @@
+use std::io;
+use std::fs::File;
+use std::io::Read;
+
+use native_tls;
+use tokio_tls::{TlsConnector, TlsAcceptor};
+
+use config::AppTlsConfig;
+
+/// TLS contexts of an application, built once when it is loaded.
+///
+/// A context that could not be built keeps its error, which is reported
+/// each time the application tries to use it.
+pub struct AppTls {
+    connector: Result<TlsConnector, String>,
+    acceptor: Result<TlsAcceptor, String>
+}
+
+impl AppTls {
+    pub fn new(cfg: &AppTlsConfig) -> AppTls {
+        AppTls {
+            connector: build_connector(cfg).map_err(|e| format!("{}", e)),
+            acceptor: build_acceptor(cfg).map_err(|e| format!("{}", e))
+        }
+    }
+
+    /// Client-side connector that trusts the system roots and `tls.ca`.
+    pub fn connector(&self) -> io::Result<TlsConnector> {
+        self.connector.clone().map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+
+    /// Server-side acceptor presenting `tls.cert`.
+    pub fn acceptor(&self) -> io::Result<TlsAcceptor> {
+        self.acceptor.clone().map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+}
+
+fn read_file(path: &str) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+fn tls_error(e: native_tls::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn build_connector(cfg: &AppTlsConfig) -> io::Result<TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+
+    if let Some(ref ca) = cfg.ca {
+        builder.add_root_certificate(
+            native_tls::Certificate::from_pem(&read_file(ca)?).map_err(tls_error)?
+        );
+    }
+
+    Ok(builder.build().map_err(tls_error)?.into())
+}
+
+fn build_acceptor(cfg: &AppTlsConfig) -> io::Result<TlsAcceptor> {
+    let (cert, key) = match (cfg.cert.as_ref(), cfg.key.as_ref()) {
+        (Some(cert), Some(key)) => (read_file(cert)?, read_file(key)?),
+        _ => return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "tls.cert and tls.key must be configured"
+        ))
+    };
+
+    let identity = native_tls::Identity::from_pkcs8(&cert, &key).map_err(tls_error)?;
+    Ok(native_tls::TlsAcceptor::new(identity).map_err(tls_error)?.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+    use tokio;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::runtime::current_thread::Runtime;
+
+    fn test_config() -> AppTlsConfig {
+        let data = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tls");
+        AppTlsConfig {
+            cert: Some(format!("{}/cert.pem", data)),
+            key: Some(format!("{}/key.pem", data)),
+            ca: Some(format!("{}/cert.pem", data))
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let tls = AppTls::new(&test_config());
+        let acceptor = tls.acceptor().unwrap();
+        let connector = tls.connector().unwrap();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = listener.incoming().into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(stream, _)| {
+                acceptor.accept(stream.unwrap()).map_err(tls_error)
+            })
+            .and_then(|stream| tokio::io::read_exact(stream, [0u8; 4]))
+            .and_then(|(stream, buf)| tokio::io::write_all(stream, buf));
+
+        let client = TcpStream::connect(&addr)
+            .and_then(move |stream| connector.connect("localhost", stream).map_err(tls_error))
+            .and_then(|stream| tokio::io::write_all(stream, *b"ping"))
+            .and_then(|(stream, _)| tokio::io::read_exact(stream, [0u8; 4]));
+
+        let (_, (_, echoed)) = Runtime::new().unwrap().block_on(server.join(client)).unwrap();
+        assert_eq!(&echoed, b"ping");
+    }
+
+    #[test]
+    fn test_untrusted() {
+        let tls = AppTls::new(&test_config());
+        let acceptor = tls.acceptor().unwrap();
+        let connector = AppTls::new(&AppTlsConfig::default()).connector().unwrap();
+
+        let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = listener.incoming().into_future()
+            .map_err(|(e, _)| e)
+            .and_then(move |(stream, _)| {
+                acceptor.accept(stream.unwrap()).then(|_| Ok(()))
+            });
+        let client = TcpStream::connect(&addr)
+            .and_then(move |stream| connector.connect("localhost", stream).map_err(tls_error));
+
+        let (_, result) = Runtime::new().unwrap().block_on(server.join(client.then(Ok))).unwrap();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_missing_identity() {
+        let tls = AppTls::new(&AppTlsConfig::default());
+        assert!(tls.connector().is_ok());
+        assert!(tls.acceptor().is_err());
+    }
+}